@@ -0,0 +1,80 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use scores_ws::osu::{Scores, ScoresDeserializer};
+
+/// Resembles a score of the osu!api's scores endpoint.
+fn score(id: u64) -> String {
+    format!(
+        r#"{{"classic_total_score":8414437,"preserve":true,"processed":true,"ranked":true,"maximum_statistics":{{"great":583,"ignore_hit":46,"large_tick_hit":12,"slider_tail_hit":34}},"mods":[{{"acronym":"HD"}},{{"acronym":"DT","settings":{{"speed_change":1.3}}}}],"statistics":{{"ok":7,"meh":1,"miss":2,"great":573,"ignore_hit":46,"ignore_miss":0,"large_tick_hit":12,"slider_tail_hit":34}},"beatmap_id":{beatmap_id},"best_id":null,"id":{id},"rank":"A","type":"solo_score","user_id":{user_id},"accuracy":0.981,"build_id":null,"ended_at":"2025-01-02T12:34:56Z","has_replay":false,"is_perfect_combo":false,"legacy_perfect":false,"legacy_score_id":null,"legacy_total_score":0,"max_combo":811,"passed":true,"pp":null,"ruleset_id":0,"started_at":"2025-01-02T12:31:02Z","total_score":914301,"replay":false,"current_user_attributes":{{"pin":null}},"user":{{"avatar_url":"https://a.ppy.sh/{user_id}?1700000000.jpeg","country_code":"DE","default_group":"default","id":{user_id},"is_active":true,"is_bot":false,"is_deleted":false,"is_online":true,"is_supporter":false,"last_visit":"2025-01-02T12:34:57+00:00","pm_friends_only":false,"profile_colour":null,"username":"Player {{{user_id}}} \"quoted\""}}}}"#,
+        beatmap_id = id % 4_000_000,
+        user_id = id % 30_000_000,
+    )
+}
+
+/// Resembles a score whose user contains deeply nested objects and arrays.
+fn nested_score(id: u64) -> String {
+    const DEPTH: usize = 32;
+
+    let nested = format!(
+        "{}{}{}",
+        r#"{"groups":[{"playmodes":["#.repeat(DEPTH),
+        r#""osu","mania""#,
+        "]}]}".repeat(DEPTH),
+    );
+
+    format!(
+        r#"{{"id":{id},"ruleset_id":3,"pp":123.45,"user":{{"id":2,"badges":{nested},"username":"nested"}}}}"#
+    )
+}
+
+fn payload(count: u64) -> String {
+    payload_with(count, score)
+}
+
+fn payload_with(count: u64, score: fn(u64) -> String) -> String {
+    let scores: Vec<_> = (0..count).map(|i| score(2_000_000_000 + i)).collect();
+
+    format!(
+        r#"{{"scores":[{}],"cursor":{{"id":{}}},"cursor_string":"abc"}}"#,
+        scores.join(","),
+        2_000_000_000 + count
+    )
+}
+
+fn deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+
+    let payloads = [
+        ("empty".to_owned(), payload(0)),
+        ("1 score".to_owned(), payload(1)),
+        ("10 scores".to_owned(), payload(10)),
+        ("1000 scores".to_owned(), payload(1000)),
+        (
+            "100 nested scores".to_owned(),
+            payload_with(100, nested_score),
+        ),
+    ];
+
+    for (name, payload) in payloads {
+        let bytes = bytes::Bytes::from(payload);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || (bytes.clone(), Scores::new()),
+                |(bytes, mut scores)| {
+                    ScoresDeserializer::new(bytes)
+                        .deserialize(&mut scores)
+                        .unwrap();
+
+                    scores
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, deserialize);
+criterion_main!(benches);
@@ -0,0 +1,27 @@
+use scores_ws::{config::Config, poll};
+use tokio::sync::broadcast::error::RecvError;
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    // Instead of running `scores-ws` as its own binary, scores can also be
+    // polled within your own program. The config is read from `config.toml`
+    // just like for the binary but websocket-related settings are ignored.
+    let Config { setup, osu } = Config::parse();
+
+    let (ctx, mut scores) = poll::stream(&setup, osu)?;
+
+    // Let's process scores until the first hundred
+    for _ in 0..100 {
+        match scores.recv().await {
+            Ok(score) => println!("Received score {}", score.id()),
+            // We didn't keep up with receiving so some scores were skipped
+            Err(RecvError::Lagged(skipped)) => println!("Skipped {skipped} scores"),
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    // Stop polling
+    ctx.shutdown();
+
+    Ok(())
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, fmt, net::IpAddr, str::FromStr, sync::Mutex, time::Instant};
+
+use serde::Deserialize;
+
+/// A range of IP addresses in CIDR notation, e.g. `10.0.0.0/8` or `::1/128`.
+///
+/// A plain address without prefix length matches only itself.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 peers may connect through IPv4-mapped IPv6 addresses
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(addr), IpAddr::V4(ip)) => Self::prefix_matches(
+                addr.to_bits().into(),
+                ip.to_bits().into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(addr), IpAddr::V6(ip)) => {
+                Self::prefix_matches(addr.to_bits(), ip.to_bits(), 128, self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+
+    const fn prefix_matches(addr: u128, ip: u128, bits: u8, prefix_len: u8) -> bool {
+        let ignored = bits - prefix_len;
+
+        // Shifting by the full width would overflow
+        ignored == bits || (addr ^ ip) >> ignored == 0
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix_len) = match s.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr.parse().map_err(|_| InvalidCidr(s.into()))?;
+        let max_len = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix_len = match prefix_len {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|&prefix_len| prefix_len <= max_len)
+                .ok_or_else(|| InvalidCidr(s.into()))?,
+            None => max_len,
+        };
+
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = InvalidCidr;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCidr(Box<str>);
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid CIDR `{}`", self.0)
+    }
+}
+
+impl std::error::Error for InvalidCidr {}
+
+/// Decides which peers may connect.
+///
+/// Denied ranges take precedence over allowed ones. If no allowed ranges are
+/// specified, all peers that are not denied may connect; otherwise only peers
+/// within an allowed range may.
+#[derive(Clone, Default)]
+pub struct IpFilter {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    pub const fn new(allow: Vec<Cidr>, deny: Vec<Cidr>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+/// Throttles new connections through a token bucket per IP address.
+///
+/// Each bucket holds up to `burst` tokens and refills by `rate` tokens per
+/// second. A connection consumes one token and is rejected if there is none.
+pub struct ConnectionLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl ConnectionLimiter {
+    /// Buckets are pruned once there are more than this many.
+    const PRUNE_THRESHOLD: usize = 1024;
+
+    pub fn new(rate: f64, burst: u32) -> Self {
+        Self {
+            rate: rate.max(f64::MIN_POSITIVE),
+            burst: f64::from(burst.max(1)),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Consumes a token of `ip`'s bucket and returns whether there was one.
+    pub fn try_acquire(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() > Self::PRUNE_THRESHOLD {
+            // Full buckets behave the same as missing ones
+            buckets.retain(|_, bucket| self.tokens(bucket, now) < self.burst);
+        }
+
+        // IPv4 peers may connect through IPv4-mapped IPv6 addresses
+        let bucket = buckets.entry(ip.to_canonical()).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+
+        bucket.tokens = self.tokens(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+
+        bucket.tokens -= 1.0;
+
+        true
+    }
+
+    /// The bucket's tokens after refilling until `now`.
+    fn tokens(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+
+        (bucket.tokens + elapsed * self.rate).min(self.burst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(s: &[&str]) -> Vec<Cidr> {
+        s.iter().map(|cidr| cidr.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert!("10.0.0.0/8".parse::<Cidr>().is_ok());
+        assert!("::1".parse::<Cidr>().is_ok());
+        assert!("0.0.0.0/0".parse::<Cidr>().is_ok());
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("fe80::/129".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn contains() {
+        let cidr: Cidr = "192.168.0.0/16".parse().unwrap();
+        assert!(cidr.contains(ip("192.168.12.34")));
+        assert!(cidr.contains(ip("::ffff:192.168.0.1")));
+        assert!(!cidr.contains(ip("192.169.0.1")));
+        assert!(!cidr.contains(ip("::1")));
+
+        let cidr: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(cidr.contains(ip("2001:db8:1::1")));
+        assert!(!cidr.contains(ip("2001:db9::1")));
+
+        let cidr: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(cidr.contains(ip("1.2.3.4")));
+    }
+
+    #[test]
+    fn default_allow() {
+        let filter = IpFilter::new(Vec::new(), cidrs(&["10.0.0.0/8", "fd00::/8"]));
+
+        assert!(filter.is_allowed(ip("127.0.0.1")));
+        assert!(filter.is_allowed(ip("::1")));
+        assert!(!filter.is_allowed(ip("10.1.2.3")));
+        assert!(!filter.is_allowed(ip("fd12::1")));
+    }
+
+    #[test]
+    fn default_deny() {
+        let filter = IpFilter::new(
+            cidrs(&["10.0.0.0/8", "::1"]),
+            cidrs(&["10.0.0.1", "::1/128"]),
+        );
+
+        assert!(filter.is_allowed(ip("10.0.0.2")));
+        assert!(!filter.is_allowed(ip("10.0.0.1")));
+        assert!(!filter.is_allowed(ip("::1")));
+        assert!(!filter.is_allowed(ip("192.168.0.1")));
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limiter = ConnectionLimiter::new(2.0, 3);
+        let start = Instant::now();
+        let later = |millis| start + std::time::Duration::from_millis(millis);
+
+        for _ in 0..3 {
+            assert!(limiter.try_acquire(ip("10.0.0.1"), start));
+        }
+
+        assert!(!limiter.try_acquire(ip("10.0.0.1"), start));
+        assert!(!limiter.try_acquire(ip("::ffff:10.0.0.1"), start));
+
+        // Other peers have their own bucket
+        assert!(limiter.try_acquire(ip("10.0.0.2"), start));
+
+        // Two tokens per second
+        assert!(!limiter.try_acquire(ip("10.0.0.1"), later(400)));
+        assert!(limiter.try_acquire(ip("10.0.0.1"), later(500)));
+        assert!(!limiter.try_acquire(ip("10.0.0.1"), later(500)));
+
+        // Refills up to the burst
+        for _ in 0..3 {
+            assert!(limiter.try_acquire(ip("10.0.0.1"), later(60_000)));
+        }
+
+        assert!(!limiter.try_acquire(ip("10.0.0.1"), later(60_000)));
+    }
+}
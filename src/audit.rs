@@ -0,0 +1,300 @@
+//! Append-only log of all broadcast scores for auditing or replaying them
+//! later.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+        Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use crate::{config::Setup, context::Batch};
+
+/// How often buffered writes are flushed to disk.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many batches may be queued up before new ones are dropped.
+const QUEUE_LEN: usize = 1024;
+
+/// Writes the raw bytes of broadcast scores to rotating files, one score per
+/// line.
+///
+/// Writing happens on a dedicated thread so that broadcasting never waits for
+/// the disk.
+pub struct AuditLog {
+    tx: Mutex<Option<SyncSender<Batch>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl AuditLog {
+    /// Starts a new file in `dir` and spawns the thread writing to it.
+    pub fn open(dir: &Path, rotate_size: u64, retention: usize) -> io::Result<Self> {
+        let writer = Writer::open(dir, rotate_size, retention)?;
+        let (tx, rx) = mpsc::sync_channel(QUEUE_LEN);
+
+        let thread = std::thread::Builder::new()
+            .name("audit-log".to_owned())
+            .spawn(move || writer.run(&rx))?;
+
+        Ok(Self {
+            tx: Mutex::new(Some(tx)),
+            thread: Mutex::new(Some(thread)),
+        })
+    }
+
+    /// Opens the audit log of [`Setup::audit_dir`] if specified and not
+    /// during a dry run.
+    pub fn from_setup(setup: &Setup) -> io::Result<Option<Self>> {
+        setup
+            .audit_dir
+            .as_deref()
+            .filter(|_| !setup.dry_run)
+            .map(|dir| Self::open(dir, setup.audit_rotate_size, setup.audit_retention))
+            .transpose()
+    }
+
+    /// Queues the scores of `batch` to be written.
+    pub(crate) fn append(&self, batch: &Batch) {
+        let tx = self.tx.lock().unwrap();
+
+        let Some(tx) = tx.as_ref() else {
+            return warn!(scores = batch.len(), "Audit log is closed, dropping scores");
+        };
+
+        match tx.try_send(Batch::clone(batch)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                warn!(
+                    scores = batch.len(),
+                    "Audit log is lagging behind, dropping scores"
+                );
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                warn!(scores = batch.len(), "Audit log stopped, dropping scores");
+            }
+        }
+    }
+
+    /// Writes all queued scores, flushes them to disk, and stops the writing
+    /// thread.
+    ///
+    /// Scores appended afterwards are dropped.
+    pub fn close(&self) {
+        drop(self.tx.lock().unwrap().take());
+
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            if thread.join().is_err() {
+                error!("Audit log thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+struct Writer {
+    dir: PathBuf,
+    file: BufWriter<File>,
+    /// Index of the current file; later files have higher indices.
+    index: u64,
+    /// Bytes written to the current file.
+    size: u64,
+    rotate_size: u64,
+    retention: usize,
+}
+
+impl Writer {
+    const PREFIX: &str = "scores-";
+    const EXTENSION: &str = ".ndjson";
+
+    fn open(dir: &Path, rotate_size: u64, retention: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        // Existing files are never written to again
+        let index = Self::files(dir)?.last().map_or(0, |(index, _)| index + 1);
+
+        let writer = Self {
+            dir: dir.to_owned(),
+            file: Self::create(dir, index)?,
+            index,
+            size: 0,
+            rotate_size: rotate_size.max(1),
+            retention: retention.max(1),
+        };
+
+        writer.prune()?;
+
+        Ok(writer)
+    }
+
+    fn create(dir: &Path, index: u64) -> io::Result<BufWriter<File>> {
+        let path = dir.join(format!("{}{index:06}{}", Self::PREFIX, Self::EXTENSION));
+        info!(path = %path.display(), "Writing audit log...");
+
+        File::create_new(path).map(BufWriter::new)
+    }
+
+    /// Indices and paths of all audit files in `dir`, oldest first.
+    fn files(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+        let mut files = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+
+            let index = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(Self::PREFIX))
+                .and_then(|name| name.strip_suffix(Self::EXTENSION))
+                .and_then(|index| index.parse().ok());
+
+            if let Some(index) = index {
+                files.push((index, path));
+            }
+        }
+
+        files.sort_unstable();
+
+        Ok(files)
+    }
+
+    /// Writes batches until the sending half is dropped.
+    fn run(mut self, rx: &mpsc::Receiver<Batch>) {
+        let mut last_flush = Instant::now();
+
+        loop {
+            match rx.recv_timeout(FLUSH_INTERVAL.saturating_sub(last_flush.elapsed())) {
+                Ok(batch) => {
+                    if let Err(err) = self.write(&batch) {
+                        error!(?err, "Failed to write audit log");
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+
+            if last_flush.elapsed() >= FLUSH_INTERVAL {
+                if let Err(err) = self.file.flush() {
+                    error!(?err, "Failed to flush audit log");
+                }
+
+                last_flush = Instant::now();
+            }
+        }
+
+        if let Err(err) = self.file.flush() {
+            error!(?err, "Failed to flush audit log");
+        }
+    }
+
+    fn write(&mut self, batch: &Batch) -> io::Result<()> {
+        for score in batch.iter() {
+            let bytes = score.raw();
+            self.file.write_all(bytes)?;
+            self.file.write_all(b"\n")?;
+            self.size += bytes.len() as u64 + 1;
+
+            if self.size >= self.rotate_size {
+                self.rotate()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Continues in a new file and deletes the files exceeding the retention.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.index += 1;
+        self.file = Self::create(&self.dir, self.index)?;
+        self.size = 0;
+
+        self.prune()
+    }
+
+    fn prune(&self) -> io::Result<()> {
+        let files = Self::files(&self.dir)?;
+        let excess = files.len().saturating_sub(self.retention);
+
+        for (_, path) in &files[..excess] {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Context,
+        osu::{Scores, ScoresDeserializer},
+    };
+
+    fn read_files(dir: &Path) -> Vec<String> {
+        Writer::files(dir)
+            .unwrap()
+            .into_iter()
+            .map(|(_, path)| fs::read_to_string(path).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn rotate_broadcast_scores() {
+        let dir = std::env::temp_dir().join(format!("scores-ws-audit-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let setup = Setup {
+            audit_dir: Some(dir.clone()),
+            // Three scores per file
+            audit_rotate_size: 27,
+            audit_retention: 3,
+            ..Setup::default()
+        };
+
+        let ctx =
+            Context::new(&setup).with_audit_log(AuditLog::from_setup(&setup).unwrap().unwrap());
+
+        for ids in [[1, 2], [2, 3], [4, 5], [6, 7], [8, 9]] {
+            let json = format!(r#"{{"scores":[{{"id":{}}},{{"id":{}}}]}}"#, ids[0], ids[1]);
+            let mut scores = Scores::new();
+            ScoresDeserializer::new(json.into())
+                .deserialize(&mut scores)
+                .unwrap();
+
+            ctx.broadcast(&mut scores, None);
+        }
+
+        ctx.close_audit_log();
+
+        // The duplicate of score 2 was not broadcast and the oldest file with
+        // scores 1 to 3 is deleted
+        assert_eq!(
+            read_files(&dir),
+            [
+                "{\"id\":4}\n{\"id\":5}\n{\"id\":6}\n",
+                "{\"id\":7}\n{\"id\":8}\n{\"id\":9}\n",
+                "",
+            ]
+        );
+
+        // A restart continues in a new file
+        drop(AuditLog::from_setup(&setup).unwrap());
+        assert_eq!(read_files(&dir).len(), 3);
+        assert!(Writer::files(&dir).unwrap()[2]
+            .1
+            .ends_with("scores-000004.ndjson"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::{context::Batch, filter::Filter, osu::Score};
+
+/// Collects scores for a time window and then only keeps the most recent one
+/// of each user, dropping the others.
+///
+/// Scores without a user id can't be coalesced and are always kept.
+pub struct Coalescer {
+    window: Duration,
+    /// The most recent score of each user within the current window.
+    latest: HashMap<u64, Score>,
+    without_user: Vec<Score>,
+    /// When the current window ends; `None` if no score is pending.
+    deadline: Option<Instant>,
+}
+
+impl Coalescer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            latest: HashMap::new(),
+            without_user: Vec::new(),
+            deadline: None,
+        }
+    }
+
+    /// Adds the batch's scores that match the filter and returns how many
+    /// didn't.
+    ///
+    /// The first pending score starts the window.
+    pub fn push(&mut self, batch: &Batch, filter: &Filter) -> usize {
+        let mut filtered = 0;
+
+        for score in batch.iter() {
+            if !filter.matches(score) {
+                filtered += 1;
+
+                continue;
+            }
+
+            let Some(user_id) = score.user_id() else {
+                self.without_user.push(score.clone());
+
+                continue;
+            };
+
+            self.latest
+                .entry(user_id)
+                .and_modify(|latest| {
+                    if score.id() > latest.id() {
+                        *latest = score.clone();
+                    }
+                })
+                .or_insert_with(|| score.clone());
+
+            self.deadline
+                .get_or_insert_with(|| Instant::now() + self.window);
+        }
+
+        if !self.without_user.is_empty() {
+            self.deadline
+                .get_or_insert_with(|| Instant::now() + self.window);
+        }
+
+        filtered
+    }
+
+    pub const fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Takes all pending scores in ascending order of their id and ends the
+    /// current window.
+    pub fn flush(&mut self) -> Batch {
+        self.deadline = None;
+
+        let mut scores: Vec<_> = self
+            .latest
+            .drain()
+            .map(|(_, score)| score)
+            .chain(self.without_user.drain(..))
+            .collect();
+
+        scores.sort_unstable_by_key(Score::id);
+
+        scores.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osu::{Scores, ScoresDeserializer};
+
+    fn batch(json: &'static str) -> Batch {
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(json.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        scores.into_iter().collect()
+    }
+
+    #[test]
+    fn latest_per_user() {
+        let mut coalescer = Coalescer::new(Duration::from_secs(1));
+        assert!(coalescer.deadline().is_none());
+
+        coalescer.push(
+            &batch(r#"{"scores": [{"id": 1, "user": {"id": 2}}, {"id": 2, "user": {"id": 3}}, {"id": 3}]}"#),
+            &Filter::default(),
+        );
+
+        let deadline = coalescer.deadline().unwrap();
+
+        coalescer.push(
+            &batch(r#"{"scores": [{"id": 5, "user": {"id": 2}}, {"id": 4, "user": {"id": 2}}]}"#),
+            &Filter::default(),
+        );
+
+        // Later scores don't extend the window
+        assert_eq!(coalescer.deadline(), Some(deadline));
+
+        let ids: Vec<_> = coalescer.flush().iter().map(Score::id).collect();
+        assert_eq!(ids, [2, 3, 5]);
+        assert!(coalescer.deadline().is_none());
+        assert!(coalescer.flush().is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, Write};
+
+use bytes::Bytes;
+use flate2::{write::GzEncoder, Compression};
+
+/// Content encodings that HTTP streams may be compressed with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+impl Encoding {
+    /// The encoding that the `Accept-Encoding` header prefers the most.
+    ///
+    /// Encodings with a quality of 0 are excluded. On equal quality, zstd is
+    /// preferred over gzip. `None` if neither is accepted.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut preferred: Option<(Self, f32)> = None;
+
+        for entry in accept_encoding.split(',') {
+            let mut params = entry.split(';').map(str::trim);
+
+            let encoding = match params.next() {
+                Some(name) if name.eq_ignore_ascii_case("gzip") => Self::Gzip,
+                Some(name) if name.eq_ignore_ascii_case("zstd") => Self::Zstd,
+                _ => continue,
+            };
+
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |quality| quality.parse().ok());
+
+            let Some(quality) = quality.filter(|&quality| quality > 0.0) else {
+                continue;
+            };
+
+            // Compares the quality first and whether it's zstd second
+            let is_preferred = preferred.is_none_or(|(prev, prev_quality)| {
+                (quality, encoding == Self::Zstd) > (prev_quality, prev == Self::Zstd)
+            });
+
+            if is_preferred {
+                preferred = Some((encoding, quality));
+            }
+        }
+
+        preferred.map(|(encoding, _)| encoding)
+    }
+
+    /// The value of the `Content-Encoding` header.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+        }
+    }
+}
+
+/// Compresses a stream chunk by chunk.
+///
+/// Each chunk is flushed so that clients can decompress it right away instead
+/// of waiting for the compressor's buffer to fill up.
+pub enum Compressor {
+    Gzip(GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Compressor {
+    pub fn new(encoding: Encoding) -> io::Result<Self> {
+        match encoding {
+            Encoding::Gzip => Ok(Self::Gzip(GzEncoder::new(
+                Vec::new(),
+                Compression::default(),
+            ))),
+            Encoding::Zstd => {
+                zstd::stream::write::Encoder::new(Vec::new(), zstd::DEFAULT_COMPRESSION_LEVEL)
+                    .map(Self::Zstd)
+            }
+        }
+    }
+
+    /// Compresses `bytes` and returns all compressed bytes so far.
+    pub fn compress(&mut self, bytes: &[u8]) -> io::Result<Bytes> {
+        let buf = match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(bytes)?;
+                encoder.flush()?;
+
+                encoder.get_mut()
+            }
+            Self::Zstd(encoder) => {
+                encoder.write_all(bytes)?;
+                encoder.flush()?;
+
+                encoder.get_mut()
+            }
+        };
+
+        Ok(Bytes::from(std::mem::take(buf)))
+    }
+
+    /// Ends the stream and returns the remaining compressed bytes.
+    pub fn finish(self) -> io::Result<Bytes> {
+        let buf = match self {
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+
+        Ok(Bytes::from(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate("gzip, zstd"), Some(Encoding::Zstd));
+        assert_eq!(
+            Encoding::negotiate("zstd;q=0.5, GZIP"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(Encoding::negotiate("gzip;q=0, zstd;q=0"), None);
+        assert_eq!(Encoding::negotiate("gzip;q=abc"), None);
+        assert_eq!(Encoding::negotiate("identity, *"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn zstd_chunks() {
+        let mut compressor = Compressor::new(Encoding::Zstd).unwrap();
+        let mut decoder = zstd::stream::write::Decoder::new(Vec::new()).unwrap();
+
+        for chunk in ["{\"id\": 1}\n", "{\"id\": 2}\n"] {
+            let compressed = compressor.compress(chunk.as_bytes()).unwrap();
+            decoder.write_all(&compressed).unwrap();
+            decoder.flush().unwrap();
+        }
+
+        // Decompressible before the stream is finished
+        assert_eq!(decoder.get_ref(), b"{\"id\": 1}\n{\"id\": 2}\n");
+
+        let trailer = compressor.finish().unwrap();
+        decoder.write_all(&trailer).unwrap();
+        assert_eq!(decoder.into_inner(), b"{\"id\": 1}\n{\"id\": 2}\n");
+    }
+}
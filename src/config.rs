@@ -1,78 +1,473 @@
-use std::{fs::File, io::Read};
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
 
-use eyre::Context;
+use eyre::{Context, ContextCompat, Result};
 use serde::Deserialize;
 
-#[derive(Deserialize)]
+use crate::{access::Cidr, cursor::CursorSource};
+
+#[derive(Clone, Deserialize)]
 pub struct Config {
     pub setup: Setup,
     pub osu: OsuConfig,
 }
 
+const RULESETS: &[&str] = &["osu", "taiko", "fruits", "mania"];
+
 impl Config {
+    pub const PATH: &str = "./config.toml";
+
     pub fn parse() -> Self {
-        let mut file = File::open("./config.toml").unwrap_or_else(|_| {
-            panic!("Be sure a file `config.toml` is in the same directory as this binary")
-        });
+        assert!(
+            Path::new(Self::PATH).exists(),
+            "Be sure a file `config.toml` is in the same directory as this binary"
+        );
 
-        let mut content = String::new();
+        Self::load(Self::PATH).unwrap()
+    }
 
-        file.read_to_string(&mut content)
-            .context("Failed to read file `config.toml`")
-            .unwrap();
+    /// Reads and validates the config file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let content = fs::read_to_string(path).context("Failed to read file `config.toml`")?;
 
-        let config: Self = toml::from_str(&content)
-            .context("Failed to deserialize file `config.toml`")
-            .unwrap();
+        let config: Self =
+            toml::from_str(&content).context("Failed to deserialize file `config.toml`")?;
 
-        Self::assert_valid_str(
+        Self::validate_str(
             "setup.log",
             &config.setup.log,
             &["info", "warn", "error", "debug", "trace", "off"],
-        );
+        )?;
+
+        Self::validate_str(
+            "setup.log_format",
+            &config.setup.log_format,
+            &["full", "pretty", "json"],
+        )?;
 
         if let Some(ruleset) = config.osu.ruleset.as_deref() {
-            Self::assert_valid_str("osu.ruleset", ruleset, &["osu", "taiko", "fruits", "mania"]);
+            Self::validate_str("osu.ruleset", ruleset, RULESETS)?;
+        }
+
+        for ruleset in &config.osu.rulesets {
+            Self::validate_str("osu.rulesets", ruleset, RULESETS)?;
         }
 
-        config
+        eyre::ensure!(
+            config.setup.deflate_level <= 9,
+            "Unexpected value `{}` for `setup.deflate_level` in `config.toml`; \
+            must be between 0 and 9",
+            config.setup.deflate_level,
+        );
+
+        eyre::ensure!(
+            config.setup.recording.is_none() || config.setup.upstream.is_none(),
+            "`recording` and `upstream` can't be specified together"
+        );
+
+        eyre::ensure!(
+            config.setup.recording.is_some()
+                || config.setup.upstream.is_some()
+                || !config.osu.client_secret.is_empty(),
+            "Missing `osu.client_id` and `osu.client_secret` in `config.toml`"
+        );
+
+        Ok(config)
+    }
+
+    fn validate_str(key: &str, value: &str, valid: &[&str]) -> Result<()> {
+        eyre::ensure!(
+            valid.contains(&value),
+            "Unexpected value `{value}` for `{key}` in `config.toml`; must be any of {valid:?}"
+        );
+
+        Ok(())
     }
 
-    fn assert_valid_str(key: &str, value: &str, valid: &[&str]) {
-        if valid.contains(&value) {
-            return;
+    /// The keys of settings that differ from `other` but can't be applied
+    /// through [`Context::reload`] and thus require a restart.
+    ///
+    /// [`Context::reload`]: crate::context::Context::reload
+    pub fn restart_required(&self, other: &Self) -> Vec<&'static str> {
+        macro_rules! changed {
+            ( $( $section:ident . $key:ident ),* $(,)? ) => {
+                [ $( (
+                    concat!(stringify!($section), ".", stringify!($key)),
+                    self.$section.$key != other.$section.$key,
+                ) ),* ]
+            };
         }
 
-        panic!("Unexpected value `{value}` for `{key}` in `config.toml`; must be any of {valid:?}");
+        let keys = changed![
+            setup.log,
+            setup.log_format,
+            setup.host,
+            setup.port,
+            setup.history_length,
+            setup.resume_score_id,
+            setup.cursor_file,
+            setup.cursor_source,
+            setup.metrics_host,
+            setup.metrics_port,
+            setup.sse_port,
+            setup.gap_threshold,
+            setup.skip_repeated_batches,
+            setup.backfill,
+            setup.recording,
+            setup.upstream,
+            setup.audit_dir,
+            setup.audit_rotate_size,
+            setup.audit_retention,
+            setup.tls_cert,
+            setup.tls_key,
+            setup.connection_rate,
+            setup.connection_burst,
+            setup.real_ip_header,
+            setup.trusted_proxies,
+            setup.auth_tokens,
+            setup.ping_interval,
+            setup.ping_timeout,
+            setup.handshake_timeout,
+            setup.initial_message_timeout,
+            setup.deflate,
+            setup.deflate_level,
+            setup.heartbeat_interval,
+            setup.health_timeout,
+            setup.max_connections,
+            setup.dedup_capacity,
+            setup.max_filter_user_ids,
+            setup.warmup_window,
+            setup.warmup_jitter_ms,
+            setup.dry_run,
+            osu.client_id,
+            osu.client_secret,
+            osu.credentials,
+            osu.ruleset,
+            osu.rulesets,
+            osu.base_url,
+            osu.max_retries,
+            osu.connect_timeout_ms,
+            osu.request_timeout_ms,
+            osu.max_response_size,
+            osu.max_scores,
+            osu.max_depth,
+            osu.proxy,
+        ];
+
+        keys.into_iter()
+            .filter_map(|(key, changed)| changed.then_some(key))
+            .collect()
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Setup {
     #[serde(default = "Setup::default_log")]
     pub log: Box<str>,
+    #[serde(default = "Setup::default_log_format")]
+    pub log_format: Box<str>,
+    #[serde(default = "Setup::default_host")]
+    pub host: IpAddr,
     #[serde(default = "Setup::default_port")]
     pub port: u16,
     #[serde(default = "Setup::default_interval")]
     pub interval: u64,
+    pub min_interval: Option<u64>,
+    pub max_interval: Option<u64>,
+    #[serde(default = "Setup::default_interval_factor")]
+    pub interval_factor: f64,
     #[serde(default = "Setup::default_history_length")]
     pub history_length: usize,
     pub resume_score_id: Option<u64>,
+    pub cursor_file: Option<PathBuf>,
+    #[serde(default)]
+    pub cursor_source: CursorSource,
+    /// Host of the metrics listener if it should differ from `host`.
+    pub metrics_host: Option<IpAddr>,
+    pub metrics_port: Option<u16>,
+    pub sse_port: Option<u16>,
+    #[serde(default = "Setup::default_client_buffer")]
+    pub client_buffer: usize,
+    pub gap_threshold: Option<u64>,
+    /// Skip a poll's scores if they span the same ids as the previous poll.
+    #[serde(default)]
+    pub skip_repeated_batches: bool,
+    pub backfill: Option<usize>,
+    /// Recorded responses to replay instead of polling the osu!api.
+    pub recording: Option<PathBuf>,
+    /// Websocket URL of another instance to relay scores from instead of
+    /// polling the osu!api.
+    pub upstream: Option<Box<str>>,
+    /// Directory to log the raw bytes of every broadcast score to.
+    pub audit_dir: Option<PathBuf>,
+    #[serde(default = "Setup::default_audit_rotate_size")]
+    pub audit_rotate_size: u64,
+    #[serde(default = "Setup::default_audit_retention")]
+    pub audit_retention: usize,
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    #[serde(default)]
+    pub allowed_ips: Vec<Cidr>,
+    #[serde(default)]
+    pub denied_ips: Vec<Cidr>,
+    /// New websocket connections per second per IP address.
+    pub connection_rate: Option<f64>,
+    pub connection_burst: Option<u32>,
+    /// Header of trusted proxies that contains the peer's actual IP address.
+    pub real_ip_header: Option<Box<str>>,
+    #[serde(default)]
+    pub trusted_proxies: Vec<Cidr>,
+    /// If not empty, websockets must present one of these tokens.
+    #[serde(default)]
+    pub auth_tokens: Vec<Box<str>>,
+    /// Negotiate the `permessage-deflate` extension with websocket clients.
+    #[serde(default)]
+    pub deflate: bool,
+    #[serde(default = "Setup::default_deflate_level")]
+    pub deflate_level: u32,
+    #[serde(default = "Setup::default_ping_interval")]
+    pub ping_interval: u64,
+    #[serde(default = "Setup::default_ping_timeout")]
+    pub ping_timeout: u64,
+    #[serde(default = "Setup::default_handshake_timeout")]
+    pub handshake_timeout: u64,
+    #[serde(default = "Setup::default_initial_message_timeout")]
+    pub initial_message_timeout: u64,
+    pub heartbeat_interval: Option<u64>,
+    #[serde(default = "Setup::default_health_timeout")]
+    pub health_timeout: u64,
+    pub max_connections: Option<usize>,
+    #[serde(default = "Setup::default_dedup_capacity")]
+    pub dedup_capacity: usize,
+    #[serde(default = "Setup::default_max_filter_user_ids")]
+    pub max_filter_user_ids: usize,
+    pub warmup_window: Option<u64>,
+    #[serde(default = "Setup::default_warmup_jitter_ms")]
+    pub warmup_jitter_ms: u64,
+    /// Poll without serving or sending scores anywhere.
+    #[serde(default)]
+    pub dry_run: bool,
 }
 
 #[allow(clippy::module_name_repetitions)]
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct OsuConfig {
+    /// Only optional when replaying a recording or relaying an upstream.
+    #[serde(default)]
     pub client_id: u64,
+    #[serde(default)]
     pub client_secret: Box<str>,
+    /// Additional credentials to rotate requests among.
+    #[serde(default)]
+    pub credentials: Vec<ClientCredentials>,
     pub ruleset: Option<Box<str>>,
+    #[serde(default)]
+    pub rulesets: Vec<Box<str>>,
+    #[serde(default = "OsuConfig::default_base_url")]
+    pub base_url: Box<str>,
+    #[serde(default = "OsuConfig::default_backoff_base_ms")]
+    pub backoff_base_ms: u64,
+    #[serde(default = "OsuConfig::default_backoff_cap_ms")]
+    pub backoff_cap_ms: u64,
+    pub max_retries: Option<u32>,
+    #[serde(default = "OsuConfig::default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// How long a request may take in total, including its connection.
+    #[serde(default = "OsuConfig::default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default = "OsuConfig::default_max_response_size")]
+    pub max_response_size: usize,
+    #[serde(default = "OsuConfig::default_max_scores")]
+    pub max_scores: usize,
+    /// How deep objects and arrays may be nested within a score.
+    #[serde(default = "OsuConfig::default_max_depth")]
+    pub max_depth: u32,
+    pub proxy: Option<Box<str>>,
+}
+
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+pub struct ClientCredentials {
+    pub client_id: u64,
+    pub client_secret: Box<str>,
+}
+
+impl OsuConfig {
+    /// All rulesets that should be polled separately.
+    ///
+    /// Contains a single `None` if no ruleset is specified, meaning scores of
+    /// all rulesets are polled at once.
+    pub fn rulesets(&self) -> Vec<Option<Box<str>>> {
+        let mut rulesets: Vec<_> = self.ruleset.iter().chain(&self.rulesets).cloned().collect();
+        rulesets.sort_unstable();
+        rulesets.dedup();
+
+        if rulesets.is_empty() {
+            vec![None]
+        } else {
+            rulesets.into_iter().map(Some).collect()
+        }
+    }
+
+    fn default_base_url() -> Box<str> {
+        Box::from("https://osu.ppy.sh")
+    }
+
+    const fn default_backoff_base_ms() -> u64 {
+        2000
+    }
+
+    const fn default_backoff_cap_ms() -> u64 {
+        120_000
+    }
+
+    const fn default_connect_timeout_ms() -> u64 {
+        5000
+    }
+
+    const fn default_request_timeout_ms() -> u64 {
+        10_000
+    }
+
+    const fn default_max_response_size() -> usize {
+        16 * 1024 * 1024
+    }
+
+    const fn default_max_scores() -> usize {
+        5000
+    }
+
+    const fn default_max_depth() -> u32 {
+        16
+    }
 }
 
 impl Setup {
+    /// Applies environment variables on top of the config.
+    ///
+    /// `SCORES_WS_HOST` and `SCORES_WS_PORT` override the config's `host` and
+    /// `port`. Other variables are ignored.
+    pub fn apply_env(&mut self, vars: impl Iterator<Item = (String, String)>) -> Result<()> {
+        for (key, value) in vars {
+            match key.as_str() {
+                "SCORES_WS_HOST" => self.host = Self::parse_host(&value)?,
+                "SCORES_WS_PORT" => self.port = Self::parse_port(&value)?,
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies command line arguments on top of the config and should thus be
+    /// called after [`Setup::apply_env`].
+    ///
+    /// `--host <address>` and `--port <port>` override the config's `host` and
+    /// `port`. `--resume-score-id <id>` overrides the config's
+    /// `resume_score_id` and thus also takes precedence over the persisted
+    /// cursor. `--dry-run` enables `dry_run`.
+    pub fn apply_args(&mut self, mut args: impl Iterator<Item = String>) -> Result<()> {
+        while let Some(arg) = args.next() {
+            // The only argument without a value
+            if arg == "--dry-run" {
+                self.dry_run = true;
+
+                continue;
+            }
+
+            let (key, value) = if let Some((key, value)) = arg.split_once('=') {
+                (key.to_owned(), value.to_owned())
+            } else {
+                let value = args
+                    .next()
+                    .with_context(|| format!("Missing value for `{arg}`"))?;
+
+                (arg, value)
+            };
+
+            match key.as_str() {
+                "--resume-score-id" => {
+                    let score_id = value.parse().with_context(|| {
+                        format!("Invalid score id `{value}`; must be a positive integer")
+                    })?;
+
+                    self.resume_score_id = Some(score_id);
+                }
+                "--host" => self.host = Self::parse_host(&value)?,
+                "--port" => self.port = Self::parse_port(&value)?,
+                _ => bail!("Unknown argument `{key}`"),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The address to listen on with the given port.
+    pub const fn listen_addr(&self, port: u16) -> SocketAddr {
+        SocketAddr::new(self.host, port)
+    }
+
+    /// The address to serve websockets on, or `None` during a dry run.
+    pub const fn websocket_addr(&self) -> Option<SocketAddr> {
+        if self.dry_run {
+            None
+        } else {
+            Some(self.listen_addr(self.port))
+        }
+    }
+
+    /// The address to serve metrics, health, and stats on if specified and
+    /// not during a dry run.
+    ///
+    /// Defaults to `host` if no `metrics_host` is specified.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        let host = self.metrics_host.unwrap_or(self.host);
+
+        self.metrics_port
+            .filter(|_| !self.dry_run)
+            .map(|port| SocketAddr::new(host, port))
+    }
+
+    /// The address to serve Server-Sent Events on if specified and not during
+    /// a dry run.
+    pub fn sse_addr(&self) -> Option<SocketAddr> {
+        self.sse_port
+            .filter(|_| !self.dry_run)
+            .map(|port| self.listen_addr(port))
+    }
+
+    fn parse_host(value: &str) -> Result<IpAddr> {
+        // Allows specifying IPv6 addresses the same way as in urls
+        let trimmed = value
+            .strip_prefix('[')
+            .and_then(|value| value.strip_suffix(']'))
+            .unwrap_or(value);
+
+        trimmed
+            .parse()
+            .with_context(|| format!("Invalid host `{value}`; must be an IPv4 or IPv6 address"))
+    }
+
+    fn parse_port(value: &str) -> Result<u16> {
+        value
+            .parse()
+            .with_context(|| format!("Invalid port `{value}`; must be between 0 and 65535"))
+    }
+
     fn default_log() -> Box<str> {
         Box::from("info")
     }
 
+    fn default_log_format() -> Box<str> {
+        Box::from("full")
+    }
+
+    const fn default_host() -> IpAddr {
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    }
+
     const fn default_port() -> u16 {
         7277
     }
@@ -81,7 +476,310 @@ impl Setup {
         60
     }
 
+    const fn default_interval_factor() -> f64 {
+        1.5
+    }
+
     const fn default_history_length() -> usize {
         100_000
     }
+
+    const fn default_client_buffer() -> usize {
+        32
+    }
+
+    const fn default_deflate_level() -> u32 {
+        6
+    }
+
+    const fn default_ping_interval() -> u64 {
+        30
+    }
+
+    const fn default_ping_timeout() -> u64 {
+        10
+    }
+
+    const fn default_handshake_timeout() -> u64 {
+        10
+    }
+
+    const fn default_initial_message_timeout() -> u64 {
+        5
+    }
+
+    const fn default_health_timeout() -> u64 {
+        300
+    }
+
+    const fn default_dedup_capacity() -> usize {
+        10_000
+    }
+
+    const fn default_max_filter_user_ids() -> usize {
+        1000
+    }
+
+    const fn default_warmup_jitter_ms() -> u64 {
+        1000
+    }
+
+    const fn default_audit_rotate_size() -> u64 {
+        64 * 1024 * 1024
+    }
+
+    const fn default_audit_retention() -> usize {
+        16
+    }
+}
+
+impl Default for Setup {
+    fn default() -> Self {
+        Self {
+            log: Self::default_log(),
+            log_format: Self::default_log_format(),
+            host: Self::default_host(),
+            port: Self::default_port(),
+            interval: Self::default_interval(),
+            min_interval: None,
+            max_interval: None,
+            interval_factor: Self::default_interval_factor(),
+            history_length: Self::default_history_length(),
+            resume_score_id: None,
+            cursor_file: None,
+            cursor_source: CursorSource::default(),
+            metrics_host: None,
+            metrics_port: None,
+            sse_port: None,
+            client_buffer: Self::default_client_buffer(),
+            gap_threshold: None,
+            skip_repeated_batches: false,
+            backfill: None,
+            recording: None,
+            upstream: None,
+            audit_dir: None,
+            audit_rotate_size: Self::default_audit_rotate_size(),
+            audit_retention: Self::default_audit_retention(),
+            tls_cert: None,
+            tls_key: None,
+            allowed_ips: Vec::new(),
+            denied_ips: Vec::new(),
+            connection_rate: None,
+            connection_burst: None,
+            real_ip_header: None,
+            trusted_proxies: Vec::new(),
+            auth_tokens: Vec::new(),
+            deflate: false,
+            deflate_level: Self::default_deflate_level(),
+            ping_interval: Self::default_ping_interval(),
+            ping_timeout: Self::default_ping_timeout(),
+            handshake_timeout: Self::default_handshake_timeout(),
+            initial_message_timeout: Self::default_initial_message_timeout(),
+            heartbeat_interval: None,
+            health_timeout: Self::default_health_timeout(),
+            max_connections: None,
+            max_filter_user_ids: Self::default_max_filter_user_ids(),
+            warmup_window: None,
+            warmup_jitter_ms: Self::default_warmup_jitter_ms(),
+            dry_run: false,
+            dedup_capacity: Self::default_dedup_capacity(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(args: &[&str]) -> Result<Setup> {
+        let mut setup = Setup {
+            resume_score_id: Some(1),
+            ..Setup::default()
+        };
+
+        setup.apply_args(args.iter().map(|&arg| arg.to_owned()))?;
+
+        Ok(setup)
+    }
+
+    #[test]
+    fn resume_score_id_arg() {
+        assert_eq!(apply(&[]).unwrap().resume_score_id, Some(1));
+
+        let setup = apply(&["--resume-score-id", "4321"]).unwrap();
+        assert_eq!(setup.resume_score_id, Some(4321));
+
+        let setup = apply(&["--resume-score-id=4321"]).unwrap();
+        assert_eq!(setup.resume_score_id, Some(4321));
+
+        assert!(apply(&["--resume-score-id"]).is_err());
+        assert!(apply(&["--resume-score-id", "-1"]).is_err());
+        assert!(apply(&["--resume-score-id", "abc"]).is_err());
+        assert!(apply(&["--resume-score-id", "18446744073709551616"]).is_err());
+        assert!(apply(&["--cursor", "1"]).is_err());
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Result<Setup> {
+        let mut setup = Setup::default();
+        let vars = vars
+            .iter()
+            .map(|&(key, value)| (key.to_owned(), value.to_owned()));
+        setup.apply_env(vars)?;
+
+        Ok(setup)
+    }
+
+    #[test]
+    fn listen_addr() {
+        let setup = env(&[("SCORES_WS_HOST", "0.0.0.0"), ("SCORES_WS_PORT", "8000")]).unwrap();
+        assert_eq!(
+            setup.listen_addr(setup.port),
+            "0.0.0.0:8000".parse().unwrap()
+        );
+
+        let setup = env(&[("SCORES_WS_HOST", "[::]"), ("PATH", "/bin")]).unwrap();
+        assert_eq!(setup.listen_addr(setup.port), "[::]:7277".parse().unwrap());
+
+        assert!(env(&[("SCORES_WS_HOST", "localhost")]).is_err());
+        assert!(env(&[("SCORES_WS_HOST", "127.0.0.1:80")]).is_err());
+        assert!(env(&[("SCORES_WS_PORT", "65536")]).is_err());
+
+        // Arguments take precedence over environment variables
+        let mut setup = env(&[("SCORES_WS_HOST", "::1"), ("SCORES_WS_PORT", "8000")]).unwrap();
+        let args = ["--port=9000", "--host", "127.0.0.2"];
+        setup
+            .apply_args(args.iter().map(|&arg| arg.to_owned()))
+            .unwrap();
+        assert_eq!(
+            setup.listen_addr(setup.port),
+            "127.0.0.2:9000".parse().unwrap()
+        );
+
+        assert!(apply(&["--host", "::g"]).is_err());
+        assert!(apply(&["--port", "-1"]).is_err());
+    }
+
+    #[test]
+    fn dry_run() {
+        let setup = apply(&["--dry-run", "--port", "9000"]).unwrap();
+        assert!(setup.dry_run);
+        assert_eq!(setup.port, 9000);
+
+        let setup = Setup {
+            metrics_port: Some(7728),
+            sse_port: Some(7729),
+            ..setup
+        };
+
+        assert_eq!(setup.websocket_addr(), None);
+        assert_eq!(setup.metrics_addr(), None);
+        assert_eq!(setup.sse_addr(), None);
+
+        let setup = apply(&["--port", "9000"]).unwrap();
+        assert!(!setup.dry_run);
+        assert_eq!(setup.websocket_addr(), Some(setup.listen_addr(9000)));
+        assert!(apply(&["--dry-run=true"]).is_err());
+    }
+
+    #[test]
+    fn metrics_addr() {
+        let mut setup = env(&[("SCORES_WS_HOST", "0.0.0.0")]).unwrap();
+        assert_eq!(setup.metrics_addr(), None);
+
+        setup.metrics_port = Some(7728);
+        assert_eq!(setup.metrics_addr(), Some("0.0.0.0:7728".parse().unwrap()));
+
+        setup.metrics_host = Some("10.0.0.2".parse().unwrap());
+        assert_eq!(setup.metrics_addr(), Some("10.0.0.2:7728".parse().unwrap()));
+    }
+
+    #[test]
+    fn bind_port() {
+        // Find a free port
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let setup = apply(&["--port", &port.to_string()]).unwrap();
+        let listener = std::net::TcpListener::bind(setup.listen_addr(setup.port)).unwrap();
+        assert_eq!(listener.local_addr().unwrap().port(), port);
+
+        // Already bound
+        assert!(std::net::TcpListener::bind(setup.listen_addr(setup.port)).is_err());
+    }
+
+    #[test]
+    fn cursor_source() {
+        let parse = |source: &str| {
+            let config = format!(
+                "[setup]\ncursor_source = \"{source}\"\n[osu]\nclient_id = 0\nclient_secret = \"\""
+            );
+
+            toml::from_str::<Config>(&config).map(|config| config.setup.cursor_source)
+        };
+
+        assert_eq!(
+            parse("newest-score-id").unwrap(),
+            CursorSource::NewestScoreId
+        );
+        assert_eq!(
+            parse("oldest-score-id").unwrap(),
+            CursorSource::OldestScoreId
+        );
+        assert_eq!(
+            parse("use-cursor-string").unwrap(),
+            CursorSource::CursorString
+        );
+        assert!(parse("cursor").is_err());
+
+        let config: Config =
+            toml::from_str("[setup]\n[osu]\nclient_id = 0\nclient_secret = \"\"").unwrap();
+        assert_eq!(config.setup.cursor_source, CursorSource::NewestScoreId);
+    }
+
+    #[test]
+    fn restart_required() {
+        let config: Config =
+            toml::from_str("[setup]\n[osu]\nclient_id = 0\nclient_secret = \"\"").unwrap();
+
+        let mut reloaded = config.clone();
+        reloaded.setup.interval = 1;
+        reloaded.setup.client_buffer = 1;
+        reloaded.setup.denied_ips = vec!["10.0.0.0/8".parse().unwrap()];
+        reloaded.osu.backoff_cap_ms = 1;
+        assert!(config.restart_required(&reloaded).is_empty());
+
+        reloaded.setup.port = 1;
+        reloaded.osu.proxy = Some(Box::from("http://proxy:3128"));
+        assert_eq!(
+            config.restart_required(&reloaded),
+            ["setup.port", "osu.proxy"]
+        );
+    }
+
+    #[test]
+    fn deflate_level() {
+        let path = std::env::temp_dir().join(format!(
+            "scores-ws-deflate-level-{}.toml",
+            std::process::id()
+        ));
+
+        let load = |level: u32| {
+            let config = format!(
+                "[setup]\ndeflate = true\ndeflate_level = {level}\n\
+                [osu]\nclient_id = 0\nclient_secret = \"secret\""
+            );
+
+            std::fs::write(&path, config).unwrap();
+
+            Config::load(&path).map(|config| config.setup.deflate_level)
+        };
+
+        assert_eq!(load(9).unwrap(), 9);
+        assert!(load(10).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
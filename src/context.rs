@@ -1,30 +1,323 @@
 use std::{
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-    time::Duration,
+    collections::{BTreeSet, HashMap as StdHashMap},
+    mem,
+    net::{IpAddr, SocketAddr},
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
+use bytes::Bytes;
 use eyre::Result;
-use futures_util::{stream::SplitSink, SinkExt, StreamExt, TryStreamExt};
+use flate2::Compression;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
 use papaya::HashMap;
-use tokio::{net::TcpStream, sync::mpsc};
-use tokio_tungstenite::{tungstenite::Message, WebSocketStream};
+use rand::Rng;
+use tokio::{
+    net::TcpStream,
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::{
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        http::{
+            self,
+            header::{AUTHORIZATION, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL},
+            HeaderValue, StatusCode,
+        },
+        protocol::{frame::coding::CloseCode, CloseFrame},
+        Error as WsError, Message,
+    },
+    WebSocketStream,
+};
+use tracing::{field, Instrument};
 
 use crate::{
-    config::Setup,
-    event::Event,
-    osu::{FetchResult, Osu, Score, Scores},
+    access::{Cidr, ConnectionLimiter, IpFilter},
+    audit::AuditLog,
+    coalesce::Coalescer,
+    config::{Config, OsuConfig, Setup},
+    cursor::{decode_cursor_string, CursorFile, CursorSource},
+    dedup::RecentIds,
+    deflate::{self, Deflate},
+    event::{Event, EventError},
+    filter::Filter,
+    gaps::GapDetector,
+    interval::PollInterval,
+    metrics::METRICS,
+    observer::{ConnectionObserver, DisconnectReason, Peer},
+    options::ConnectOptions,
+    osu::{encode_batch, text_message, FetchResult, Osu, Score, Scores, ScoresExt},
+    tls::Stream,
 };
 
-type Sender = mpsc::UnboundedSender<Message>;
-type Outgoing = SplitSink<WebSocketStream<TcpStream>, Message>;
+/// All scores of a single poll.
+pub type Batch = Arc<[Score]>;
+type Sender = mpsc::Sender<Batch>;
+type Receiver = mpsc::Receiver<Batch>;
+type Projections = StdHashMap<BTreeSet<Box<str>>, Arc<[Bytes]>>;
+type Cursors = StdHashMap<Option<Box<str>>, u64>;
+type WsStream = WebSocketStream<Deflate<Stream>>;
+type Outgoing = SplitSink<WsStream, Message>;
+type Incoming = SplitStream<WsStream>;
 
 const SECOND: Duration = Duration::from_secs(1);
 
+/// Which scores of the history to send to a new client.
+#[derive(Copy, Clone)]
+pub(crate) enum Replay {
+    All,
+    /// Scores with an id greater than the given one.
+    After(u64),
+    /// The given amount of most recent scores.
+    Last(usize),
+    /// Same as `After` but the client must be notified if the score id is
+    /// older than the history.
+    ResumeFrom(u64),
+}
+
+/// How many scores were forwarded to a client and how many were filtered out.
+#[derive(Default)]
+struct ForwardStats {
+    forwarded: u64,
+    filtered: u64,
+}
+
+impl ForwardStats {
+    fn record(&mut self, forwarded: usize, filtered: usize) {
+        self.forwarded += forwarded as u64;
+        self.filtered += filtered as u64;
+
+        METRICS
+            .scores_forwarded
+            .fetch_add(forwarded as u64, Relaxed);
+        METRICS.scores_filtered.fetch_add(filtered as u64, Relaxed);
+    }
+
+    fn message(&self) -> Message {
+        let Self {
+            forwarded,
+            filtered,
+        } = self;
+
+        let msg = format!(r#"{{"type":"stats","forwarded":{forwarded},"filtered":{filtered}}}"#);
+
+        Message::Text(msg.into())
+    }
+}
+
 pub struct Context {
-    clients: HashMap<SocketAddr, Sender>,
+    /// Keyed by connection id since websocket and HTTP stream clients may
+    /// share an address.
+    clients: HashMap<u64, Sender>,
     history: Mutex<Scores>,
     max_history_len: usize,
+    /// Ids of recently broadcast scores so that overlapping polls don't
+    /// broadcast a score twice.
+    recent_ids: Mutex<RecentIds>,
+    /// Settings that may change through [`Context::reload`].
+    runtime: watch::Sender<Runtime>,
+    /// Compression level of the `permessage-deflate` extension if it's
+    /// negotiated with clients.
+    deflate: Option<Compression>,
+    ping_interval: Duration,
+    ping_timeout: Duration,
+    /// How long the TLS and websocket handshakes may take combined.
+    handshake_timeout: Duration,
+    /// How long to wait for the initial message after the handshake.
+    initial_message_timeout: Duration,
+    /// Clients are sent a heartbeat message this often if specified.
+    heartbeat_interval: Option<Duration>,
+    /// The highest broadcast score id or 0 if none was broadcast yet.
+    latest_id: AtomicU64,
+    /// When the context was created.
+    started: Instant,
+    /// Set to `true` once the server is shutting down.
+    shutdown: watch::Sender<bool>,
+    /// Accepts `wss://` instead of `ws://` connections if specified.
+    tls: Option<TlsAcceptor>,
+    /// Logs all broadcast scores to disk if specified.
+    audit: Option<AuditLog>,
+    /// Notified about the lifecycle of connections if specified.
+    observer: Option<Box<dyn ConnectionObserver>>,
+    /// Throttles new websocket connections per IP address if specified.
+    connection_limiter: Option<ConnectionLimiter>,
+    /// Header containing the actual IP address of peers connecting through
+    /// one of the trusted proxies.
+    real_ip_header: Option<Box<str>>,
+    trusted_proxies: Vec<Cidr>,
+    /// If not empty, websockets must present one of these tokens.
+    auth_tokens: Vec<Box<str>>,
+    /// Projected scores of the most recently broadcast batch for each set of
+    /// projected fields.
+    projections: Mutex<Option<(Batch, Projections)>>,
+    /// Identifies connections in their tracing span.
+    next_connection_id: AtomicU64,
+    /// When any poller last fetched and parsed scores successfully.
+    last_poll: Mutex<Option<Instant>>,
+    /// How long ago the last successful poll may be while still healthy.
+    health_timeout: Duration,
+    /// Established websocket connections, including those that did not send
+    /// their initial message yet.
+    connections: AtomicUsize,
+    max_connections: usize,
+    /// How many user ids a filter may contain.
+    max_filter_user_ids: usize,
+    /// Connections are staggered for this long after startup if specified.
+    warmup_window: Option<Duration>,
+    /// The maximum delay of connections during the warmup window.
+    warmup_jitter: Duration,
+    /// Scores are only logged instead of sent anywhere.
+    dry_run: bool,
+    /// Broadcast scores for in-process consumers.
+    scores: broadcast::Sender<Score>,
+    /// Pollers that still need to finish backfilling.
+    pending_backfills: AtomicUsize,
+    /// Set to `true` once all pollers finished backfilling.
+    live: watch::Sender<bool>,
+    /// The current cursor id of each poller's ruleset.
+    cursors: watch::Sender<Cursors>,
+}
+
+/// Resolves at the deadline or never if there is none.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Resolves at the interval's next tick or never if there is none.
+async fn tick(interval: Option<&mut tokio::time::Interval>) {
+    match interval {
+        Some(interval) => {
+            interval.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Compares without exiting early so that the time taken does not reveal how
+/// much of a token is correct.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Occupies one of the [`Context`]'s connection slots until dropped.
+struct ConnectionSlot<'a>(&'a AtomicUsize);
+
+impl Drop for ConnectionSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Relaxed);
+    }
+}
+
+/// Settings of a [`Context`] that may change while running.
+struct Runtime {
+    /// Seconds between polls, see [`Poller`].
+    interval: u64,
+    min_interval: Option<u64>,
+    max_interval: Option<u64>,
+    interval_factor: f64,
+    /// Base and cap of the osu! client's backoff. Only specified once
+    /// reloaded since the osu! client was created with the initial ones.
+    backoff: Option<(Duration, Duration)>,
+    /// How many batches may be queued for a client before it's disconnected.
+    client_buffer: usize,
+    ip_filter: IpFilter,
+}
+
+impl Runtime {
+    fn new(setup: &Setup, osu: Option<&OsuConfig>) -> Self {
+        Self {
+            interval: setup.interval,
+            min_interval: setup.min_interval,
+            max_interval: setup.max_interval,
+            interval_factor: setup.interval_factor,
+            backoff: osu.map(|osu| {
+                (
+                    Duration::from_millis(osu.backoff_base_ms),
+                    Duration::from_millis(osu.backoff_cap_ms),
+                )
+            }),
+            client_buffer: setup.client_buffer.max(1),
+            ip_filter: IpFilter::new(setup.allowed_ips.clone(), setup.denied_ips.clone()),
+        }
+    }
+
+    /// Applies the backoff to `osu` and returns the new interval of a poller
+    /// whose first tick completes after one interval.
+    fn apply(&self, ruleset: Option<&str>, osu: &Osu) -> PollInterval {
+        if let Some((base, cap)) = self.backoff {
+            osu.set_backoff(base, cap);
+        }
+
+        let interval = self.interval;
+        info!(?ruleset, "Fetching scores every {interval} seconds...");
+
+        PollInterval::new(
+            interval,
+            self.min_interval,
+            self.max_interval,
+            self.interval_factor,
+            tokio::time::Instant::now() + Duration::from_secs(interval),
+        )
+    }
+}
+
+/// What a single poll of the osu!api resulted in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PollOutcome {
+    /// Fetching failed and nothing should be broadcast.
+    Failed,
+    /// The osu!api responded without any scores.
+    Empty,
+    /// The osu!api responded with scores, though some may have been received
+    /// before.
+    Scores,
+}
+
+/// Settings of a single polling loop.
+pub struct Poller {
+    /// Only poll scores of this ruleset.
+    pub ruleset: Option<Box<str>>,
+    /// Seconds between polls.
+    pub interval: u64,
+    /// If either is specified, the interval adapts to the amount of scores
+    /// per poll within these bounds in seconds.
+    pub min_interval: Option<u64>,
+    pub max_interval: Option<u64>,
+    /// How much the interval changes with each adjustment.
+    pub interval_factor: f64,
+    /// The score id to start polling from.
+    pub cursor_id: Option<u64>,
+    pub cursor_file: Option<CursorFile>,
+    pub cursor_source: CursorSource,
+    pub gap_threshold: Option<u64>,
+    /// Whether to skip the scores of a poll if they span the same ids as
+    /// those of the previous one.
+    pub skip_repeated_batches: bool,
+    /// How many recent scores to fetch before polling live if there is no
+    /// cursor id to start from.
+    pub backfill: Option<usize>,
+}
+
+impl Poller {
+    /// Whether the poller backfills before polling live.
+    pub(crate) const fn backfills(&self) -> bool {
+        self.backfill.is_some() && self.cursor_id.is_none()
+    }
 }
 
 impl Context {
@@ -33,211 +326,3368 @@ impl Context {
             history: Mutex::new(Scores::new()),
             clients: HashMap::new(),
             max_history_len: setup.history_length,
+            recent_ids: Mutex::new(RecentIds::new(setup.dedup_capacity)),
+            runtime: watch::Sender::new(Runtime::new(setup, None)),
+            deflate: setup.deflate.then(|| Compression::new(setup.deflate_level)),
+            ping_interval: Duration::from_secs(setup.ping_interval.max(1)),
+            ping_timeout: Duration::from_secs(setup.ping_timeout),
+            handshake_timeout: Duration::from_secs(setup.handshake_timeout.max(1)),
+            initial_message_timeout: Duration::from_secs(setup.initial_message_timeout.max(1)),
+            heartbeat_interval: setup
+                .heartbeat_interval
+                .map(|secs| Duration::from_secs(secs.max(1))),
+            latest_id: AtomicU64::new(0),
+            started: Instant::now(),
+            shutdown: watch::Sender::new(false),
+            tls: None,
+            audit: None,
+            observer: None,
+            connection_limiter: setup.connection_rate.map(|rate| {
+                // Allows a second worth of connections at once by default
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let burst = setup.connection_burst.unwrap_or(rate.ceil() as u32);
+
+                ConnectionLimiter::new(rate, burst)
+            }),
+            real_ip_header: setup.real_ip_header.clone(),
+            trusted_proxies: setup.trusted_proxies.clone(),
+            // An empty token would accept every `?token=` query
+            auth_tokens: setup
+                .auth_tokens
+                .iter()
+                .filter(|token| !token.is_empty())
+                .cloned()
+                .collect(),
+            projections: Mutex::new(None),
+            next_connection_id: AtomicU64::new(0),
+            last_poll: Mutex::new(None),
+            health_timeout: Duration::from_secs(setup.health_timeout),
+            connections: AtomicUsize::new(0),
+            max_connections: setup.max_connections.unwrap_or(usize::MAX),
+            max_filter_user_ids: setup.max_filter_user_ids,
+            warmup_window: setup.warmup_window.map(Duration::from_secs),
+            warmup_jitter: Duration::from_millis(setup.warmup_jitter_ms),
+            dry_run: setup.dry_run,
+            // Roughly `client_buffer` polls worth of scores
+            scores: broadcast::Sender::new(setup.client_buffer.max(1) * 1000),
+            pending_backfills: AtomicUsize::new(0),
+            live: watch::Sender::new(false),
+            cursors: watch::Sender::new(Cursors::new()),
+        }
+    }
+
+    /// Accept connections through TLS only.
+    #[must_use]
+    pub fn with_tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+
+        self
+    }
+
+    /// Log all broadcast scores to disk.
+    #[must_use]
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = Some(audit);
+
+        self
+    }
+
+    /// Notify `observer` whenever a websocket connects, subscribes, or
+    /// disconnects.
+    #[must_use]
+    pub fn with_observer(mut self, observer: impl ConnectionObserver + 'static) -> Self {
+        self.observer = Some(Box::new(observer));
+
+        self
+    }
+
+    /// Flushes the audit log if there is one and stops writing to it.
+    pub fn close_audit_log(&self) {
+        if let Some(audit) = &self.audit {
+            audit.close();
+        }
+    }
+
+    /// Whether a peer with the given address may connect.
+    pub fn is_allowed(&self, addr: SocketAddr) -> bool {
+        self.runtime.borrow().ip_filter.is_allowed(addr.ip())
+    }
+
+    /// How many batches may be queued for a new client before it's
+    /// disconnected.
+    fn client_buffer(&self) -> usize {
+        self.runtime.borrow().client_buffer
+    }
+
+    /// Applies the settings of `config` that can change while running.
+    ///
+    /// Pollers adopt the new intervals and backoff right away. The new client
+    /// buffer only applies to clients connecting afterwards, existing
+    /// connections are unaffected.
+    pub fn reload(&self, config: &Config) {
+        self.runtime
+            .send_replace(Runtime::new(&config.setup, Some(&config.osu)));
+    }
+
+    /// Whether a new connection of the given IP address is within the
+    /// connection rate.
+    fn within_rate(&self, ip: IpAddr) -> bool {
+        self.connection_limiter
+            .as_ref()
+            .is_none_or(|limiter| limiter.try_acquire(ip, Instant::now()))
+    }
+
+    /// Whether the real IP address of peers connecting from `ip` is specified
+    /// through a header.
+    fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+        self.real_ip_header.is_some() && self.trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// The rightmost IP address of the real IP header i.e. the one that was
+    /// added by the trusted proxy itself.
+    fn real_ip(&self, req: &Request) -> Option<IpAddr> {
+        let value = req.headers().get(self.real_ip_header.as_deref()?)?;
+
+        value.to_str().ok()?.rsplit(',').next()?.trim().parse().ok()
+    }
+
+    /// Whether the handshake request contains a valid token, either as
+    /// `Authorization: Bearer <token>` header or as `token` query parameter.
+    pub(crate) fn is_authorized<B>(&self, req: &http::Request<B>) -> bool {
+        if self.auth_tokens.is_empty() {
+            return true;
+        }
+
+        let header = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok()?.strip_prefix("Bearer "));
+
+        let query = req.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("token="))
+        });
+
+        header.into_iter().chain(query).any(|token| {
+            self.auth_tokens
+                .iter()
+                .any(|valid| constant_time_eq(token.trim().as_bytes(), valid.as_bytes()))
+        })
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// The highest score id broadcast so far.
+    ///
+    /// `None` if no score was broadcast yet.
+    pub fn latest_id(&self) -> Option<u64> {
+        Some(self.latest_id.load(Relaxed)).filter(|&id| id > 0)
+    }
+
+    /// How long ago the context was created.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// How long ago scores were last fetched and parsed successfully.
+    ///
+    /// `None` if no poll succeeded yet.
+    pub fn since_last_poll(&self) -> Option<Duration> {
+        self.last_poll.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Whether the last successful poll is recent enough.
+    pub fn is_healthy(&self) -> bool {
+        self.since_last_poll()
+            .is_some_and(|elapsed| elapsed <= self.health_timeout)
+    }
+
+    /// Receives all scores broadcast from now on, in the same order as they're
+    /// sent to websockets.
+    ///
+    /// If the receiver falls behind by roughly `client_buffer` polls, it skips
+    /// the oldest scores and [`RecvError::Lagged`] is returned.
+    ///
+    /// [`RecvError::Lagged`]: broadcast::error::RecvError::Lagged
+    pub fn score_stream(&self) -> broadcast::Receiver<Score> {
+        self.scores.subscribe()
+    }
+
+    /// Returns `None` if the maximum amount of connections is reached.
+    fn acquire_slot(&self) -> Option<ConnectionSlot<'_>> {
+        self.connections
+            .fetch_update(Relaxed, Relaxed, |count| {
+                (count < self.max_connections).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot(&self.connections))
+    }
+
+    /// Makes clients wait for one more poller's backfill before they're sent
+    /// the `live` message.
+    pub(crate) fn expect_backfill(&self) {
+        self.pending_backfills.fetch_add(1, Relaxed);
+    }
+
+    /// Notifies clients that they're receiving live scores from now on once
+    /// the last expected backfill finished.
+    fn finish_backfill(&self) {
+        let pending = self
+            .pending_backfills
+            .fetch_update(Relaxed, Relaxed, |pending| pending.checked_sub(1));
+
+        // Pollers whose backfill was not expected count as the only one
+        if matches!(pending, Ok(1) | Err(0)) {
+            self.live
+                .send_if_modified(|live| !std::mem::replace(live, true));
+            info!("Finished backfilling, polling live scores");
         }
     }
 
-    pub async fn fetch_scores(ctx: Arc<Self>, osu: Osu, interval: u64, mut cursor_id: Option<u64>) {
-        let Context {
-            clients,
-            history,
-            max_history_len,
-        } = &*ctx;
+    /// Notifies clients that want to know the pollers' cursor if it changed.
+    fn update_cursor(&self, ruleset: Option<&str>, cursor_id: u64) {
+        self.cursors.send_if_modified(|cursors| {
+            cursors.insert(ruleset.map(Box::from), cursor_id) != Some(cursor_id)
+        });
+    }
+
+    pub(crate) fn record_poll(&self, at: Instant) {
+        *self.last_poll.lock().unwrap() = Some(at);
+    }
+
+    /// Stops all polling loops and disconnects all clients with a close frame.
+    ///
+    /// A poll that is currently in progress is completed and broadcast first.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Resolves once [`Context::shutdown`] has been called.
+    pub(crate) async fn shutting_down(&self) {
+        let mut rx = self.shutdown.subscribe();
+        let _: Result<_, _> = rx.wait_for(|&shutdown| shutdown).await;
+    }
+
+    /// Polls scores and broadcasts them to the clients.
+    ///
+    /// Each poller should run in its own task, sharing the same context and
+    /// osu! client.
+    pub async fn fetch_scores(ctx: Arc<Self>, osu: Arc<Osu>, poller: Poller) {
+        let Poller {
+            ruleset,
+            interval,
+            min_interval,
+            max_interval,
+            interval_factor,
+            mut cursor_id,
+            cursor_file,
+            cursor_source,
+            gap_threshold,
+            skip_repeated_batches,
+            backfill,
+        } = poller;
+
+        let ruleset = ruleset.as_deref();
+        let mut gaps = gap_threshold.map(GapDetector::new);
+
+        if let Some(count) = backfill.filter(|_| cursor_id.is_none()) {
+            // Live polling continues after the newest backfilled score so
+            // that no score is sent twice
+            cursor_id = ctx
+                .broadcast_backfill(&osu, ruleset, count, gaps.as_mut())
+                .await;
+        }
+
+        info!(?ruleset, "Fetching scores every {interval} seconds...");
 
-        info!("Fetching scores every {interval} seconds...");
+        let mut interval = PollInterval::new(
+            interval,
+            min_interval,
+            max_interval,
+            interval_factor,
+            tokio::time::Instant::now(),
+        );
 
-        let mut interval = tokio::time::interval(Duration::from_secs(interval));
+        let mut runtime = ctx.runtime.subscribe();
         let mut scores = Scores::new();
+        let mut prev_span = None;
 
         loop {
-            interval.tick().await;
+            tokio::select! {
+                () = interval.tick() => {}
+                Ok(()) = runtime.changed() => {
+                    interval = runtime.borrow_and_update().apply(ruleset, &osu);
+
+                    continue;
+                }
+                () = ctx.shutting_down() => break,
+            }
 
+            let start = Instant::now();
             let prev_cursor_id = cursor_id;
 
-            if let FetchResult::CursorTooOld = osu.fetch_scores(&mut scores, cursor_id).await {
+            let span = info_span!(
+                "poll",
+                ?ruleset,
+                scores = field::Empty,
+                fetch_ms = field::Empty,
+                broadcast_ms = field::Empty,
+            );
+
+            let outcome = Self::poll(&osu, &mut scores, ruleset, &mut cursor_id, cursor_source)
+                .instrument(span.clone())
+                .await;
+
+            span.record("fetch_ms", start.elapsed().as_millis());
+
+            match outcome {
+                PollOutcome::Failed => continue,
+                PollOutcome::Empty => {
+                    METRICS.empty_polls.fetch_add(1, Relaxed);
+                    debug!(?ruleset, "No new scores");
+                }
+                PollOutcome::Scores => {}
+            }
+
+            if skip_repeated_batches && Self::is_repeated(&mut prev_span, &scores) {
+                warn!(
+                    ?ruleset,
+                    ?prev_span,
+                    "Skipping repeated scores of the last poll"
+                );
+                scores.clear();
+            }
+
+            METRICS.observe_poll(start.elapsed());
+            ctx.record_poll(Instant::now());
+
+            let batch = span.in_scope(|| {
+                let start = Instant::now();
+                let batch = ctx.broadcast(&mut scores, prev_cursor_id);
+                span.record("broadcast_ms", start.elapsed().as_millis());
+                span.record("scores", batch.len());
+
+                batch
+            });
+
+            if let Some(ref mut gaps) = gaps {
+                gaps.report(batch.iter());
+            }
+
+            let new_scores = match outcome {
+                PollOutcome::Scores => batch.len(),
+                PollOutcome::Empty | PollOutcome::Failed => 0,
+            };
+
+            if interval.adjust(new_scores) {
+                let period = interval.period();
+                debug!(?ruleset, ?period, "Adjusted poll interval");
+            }
+
+            if let Some(cursor_id) = cursor_id {
+                ctx.update_cursor(ruleset, cursor_id);
+
+                if let Some(Err(err)) = cursor_file.as_ref().map(|file| file.save(cursor_id)) {
+                    warn!(?err, "Failed to persist cursor");
+                }
+            }
+        }
+
+        info!(?ruleset, "Stopped fetching scores");
+    }
+
+    /// Fetches scores until the most recent ones are reached.
+    async fn poll(
+        osu: &Osu,
+        scores: &mut Scores,
+        ruleset: Option<&str>,
+        cursor_id: &mut Option<u64>,
+        cursor_source: CursorSource,
+    ) -> PollOutcome {
+        let prev_len = scores.len();
+
+        let (res, mut cursor_string) =
+            Self::fetch(osu, scores, ruleset, *cursor_id, cursor_source).await;
+
+        match res {
+            FetchResult::Ok => {}
+            FetchResult::CursorTooOld => {
                 if cursor_id.take().is_none() {
                     // This should never happen; bug in osu! api
                     error!("\"cursor too old\" but no cursor specified");
 
-                    continue;
+                    return PollOutcome::Failed;
                 }
 
                 tokio::time::sleep(SECOND).await;
 
-                if let FetchResult::CursorTooOld = osu.fetch_scores(&mut scores, cursor_id).await {
-                    // We took the cursor id out previously so this is the same case as above
-                    error!("\"cursor too old\" but no cursor specified");
+                let res;
+                (res, cursor_string) =
+                    Self::fetch(osu, scores, ruleset, *cursor_id, cursor_source).await;
 
-                    continue;
+                match res {
+                    FetchResult::Ok => {}
+                    FetchResult::CursorTooOld => {
+                        // We took the cursor id out previously so this is
+                        // the same case as above
+                        error!("\"cursor too old\" but no cursor specified");
+
+                        return PollOutcome::Failed;
+                    }
+                    FetchResult::Failed => return PollOutcome::Failed,
                 }
             }
+            FetchResult::Failed => return PollOutcome::Failed,
+        }
 
-            loop {
-                const SCORES_THRESHOLD: usize = 850;
-                const ID_THRESHOLD: u64 = 900;
+        loop {
+            const SCORES_THRESHOLD: usize = 850;
+            const ID_THRESHOLD: u64 = 900;
 
-                let next_cursor_id = scores.last().map(Score::id);
-                debug!(?next_cursor_id);
+            let next_cursor_id =
+                Self::next_cursor_id(*cursor_id, scores, cursor_source, cursor_string.as_deref());
+            debug!(?next_cursor_id);
+            let Some(next_cursor_id) = next_cursor_id else {
+                break;
+            };
 
-                let Some(next_cursor_id) = next_cursor_id else {
-                    cursor_id = None;
+            if cursor_id
+                .replace(next_cursor_id)
+                .is_none_or(|prev_cursor_id| {
+                    scores.len() < SCORES_THRESHOLD
+                        || next_cursor_id < prev_cursor_id + ID_THRESHOLD
+                })
+            {
+                // If either `cursor_id` was `None`, or we did not receive
+                // at least `SCORES_THRESHOLD` many new scores, or the range
+                // of most recent score ids is smaller than `ID_THRESHOLD`,
+                // we stop fetching more scores.
+                //
+                // In other words: `SCORES_THRESHOLD` is only relevant for
+                // the first iteration since `scores.len()` considers scores
+                // from all iterations. Our `ID_THRESHOLD` needs to be large
+                // enough so that within our sleep interval (1 second),
+                // it's very unlikely that the difference to the next score
+                // id will be greater than our threshold. Additionally,
+                // the threshold may not be larger than the maximum amount
+                // of scores sent by the endpoint which is 1000.
+                break;
+            }
 
-                    break;
-                };
-
-                if cursor_id
-                    .replace(next_cursor_id)
-                    .is_none_or(|prev_cursor_id| {
-                        scores.len() < SCORES_THRESHOLD
-                            || next_cursor_id < prev_cursor_id + ID_THRESHOLD
-                    })
-                {
-                    // If either `cursor_id` was `None`, or we did not receive
-                    // at least `SCORES_THRESHOLD` many new scores, or the range
-                    // of most recent score ids is smaller than `ID_THRESHOLD`,
-                    // we stop fetching more scores.
-                    //
-                    // In other words: `SCORES_THRESHOLD` is only relevant for
-                    // the first iteration since `scores.len()` considers scores
-                    // from all iterations. Our `ID_THRESHOLD` needs to be large
-                    // enough so that within our sleep interval (1 second),
-                    // it's very unlikely that the difference to the next score
-                    // id will be greater than our threshold. Additionally,
-                    // the threshold may not be larger than the maximum amount
-                    // of scores sent by the endpoint which is 1000.
-                    break;
-                }
+            tokio::time::sleep(SECOND).await;
 
-                tokio::time::sleep(SECOND).await;
+            let res;
+            (res, cursor_string) =
+                Self::fetch(osu, scores, ruleset, *cursor_id, cursor_source).await;
 
-                if let FetchResult::CursorTooOld = osu.fetch_scores(&mut scores, cursor_id).await {
+            match res {
+                FetchResult::Ok => {}
+                FetchResult::CursorTooOld => {
                     // This should never happen
                     error!("The newly fetched cursor id {next_cursor_id} was too old");
 
                     break;
                 }
+                FetchResult::Failed => break,
             }
+        }
+
+        if scores.len() == prev_len {
+            PollOutcome::Empty
+        } else {
+            PollOutcome::Scores
+        }
+    }
+
+    /// Whether `scores` span the same ids as those of the previous poll, in
+    /// which case they're likely a repeated response of the osu!api.
+    ///
+    /// `prev_span` is the oldest id, newest id, and amount of the previous
+    /// non-empty scores and gets updated.
+    fn is_repeated(prev_span: &mut Option<(u64, u64, usize)>, scores: &Scores) -> bool {
+        let span = scores
+            .oldest_id()
+            .zip(scores.latest_id())
+            .map(|(oldest, latest)| (oldest, latest, scores.len()));
+
+        if span.is_none() {
+            return false;
+        }
 
-            let range = scores.range(Score::only_id(prev_cursor_id.map_or(0, |id| id + 1))..);
+        mem::replace(prev_span, span) == span
+    }
 
-            let pin = clients.pin();
-            let mut sent = 0;
+    /// Fetches scores, including the response's `cursor_string` only if it's
+    /// needed for the cursor.
+    async fn fetch(
+        osu: &Osu,
+        scores: &mut Scores,
+        ruleset: Option<&str>,
+        cursor_id: Option<u64>,
+        cursor_source: CursorSource,
+    ) -> (FetchResult, Option<Box<str>>) {
+        match cursor_source {
+            CursorSource::CursorString => {
+                osu.fetch_scores_with_cursor_string(scores, ruleset, cursor_id)
+                    .await
+            }
+            CursorSource::NewestScoreId | CursorSource::OldestScoreId => {
+                (osu.fetch_scores(scores, ruleset, cursor_id).await, None)
+            }
+        }
+    }
 
-            for score in range {
-                sent += 1;
+    /// The cursor id to continue polling from after fetching `scores` from
+    /// `cursor_id`.
+    ///
+    /// The cursor only ever moves forward. If the osu!api responds with no
+    /// scores or only with scores older than the cursor, e.g. through a stale
+    /// or reordered page, the cursor is kept so that no score is fetched and
+    /// broadcast again.
+    ///
+    /// If the `cursor_string` is missing or invalid despite being the
+    /// `cursor_source`, the newest score id is used instead.
+    fn next_cursor_id(
+        cursor_id: Option<u64>,
+        scores: &Scores,
+        cursor_source: CursorSource,
+        cursor_string: Option<&str>,
+    ) -> Option<u64> {
+        let next = match cursor_source {
+            CursorSource::NewestScoreId => scores.latest_id(),
+            CursorSource::OldestScoreId => scores.oldest_id(),
+            CursorSource::CursorString => match cursor_string.map(decode_cursor_string) {
+                Some(Ok(id)) => Some(id),
+                Some(Err(err)) => {
+                    warn!(?err, ?cursor_string, "Failed to decode cursor string");
 
-                for tx in pin.values() {
-                    let _: Result<_, _> = tx.send(score.as_message());
+                    scores.latest_id()
                 }
+                None => scores.latest_id(),
+            },
+        };
+
+        match (cursor_id, next) {
+            (Some(cursor_id), Some(next)) if next < cursor_id => {
+                warn!(cursor_id, next, "Ignoring regressing cursor of the osu!api");
+
+                Some(cursor_id)
             }
+            (Some(cursor_id), None) => Some(cursor_id),
+            (_, next) => next,
+        }
+    }
+
+    /// Backfills `count` scores and broadcasts them.
+    ///
+    /// Returns the id of the newest backfilled score.
+    async fn broadcast_backfill(
+        &self,
+        osu: &Osu,
+        ruleset: Option<&str>,
+        count: usize,
+        gaps: Option<&mut GapDetector>,
+    ) -> Option<u64> {
+        let mut scores = Self::backfill(osu, ruleset, count).await;
+        let latest_id = scores.latest_id();
+        let batch = self.broadcast(&mut scores, None);
 
-            info!("Sent {sent} scores to {} client(s)", clients.len());
+        if let Some(gaps) = gaps {
+            gaps.report(batch.iter());
+        }
+
+        self.finish_backfill();
+
+        latest_id
+    }
+
+    /// Fetches pages through the osu!api's `cursor_string` until `count`
+    /// scores are gathered or there are no more pages.
+    ///
+    /// Returns the `count` most recent of the fetched scores.
+    async fn backfill(osu: &Osu, ruleset: Option<&str>, count: usize) -> Scores {
+        info!(?ruleset, count, "Backfilling scores...");
+
+        let mut scores = Scores::new();
+        let mut cursor_string = None;
+
+        loop {
+            let prev_len = scores.len();
+
+            let (FetchResult::Ok, next_cursor_string) = osu
+                .fetch_page(&mut scores, ruleset, cursor_string.as_deref())
+                .await
+            else {
+                warn!(?ruleset, "Failed to backfill, continuing with live scores");
 
-            let mut history = history.lock().unwrap();
-            history.append(&mut scores);
+                break;
+            };
 
-            while history.len() > *max_history_len {
-                history.pop_first();
+            // Stop on empty pages too so that an unexpected cursor can't keep
+            // us going forever
+            if scores.len() >= count || scores.len() == prev_len || next_cursor_string.is_none() {
+                break;
             }
 
-            debug!(history_len = history.len());
+            cursor_string = next_cursor_string;
+            tokio::time::sleep(SECOND).await;
+        }
+
+        while scores.len() > count {
+            scores.pop_first();
         }
+
+        info!(?ruleset, "Backfilled {} scores", scores.len());
+
+        scores
     }
 
-    pub async fn handle_connection(ctx: Arc<Self>, (stream, addr): (TcpStream, SocketAddr)) {
-        trace!(%addr, "Incoming TCP connection from");
+    /// Sends all scores newer than `prev_cursor_id` to the clients and moves
+    /// `scores` into the history.
+    ///
+    /// Scores are sent in strictly increasing order of their id, regardless
+    /// of the order in which the osu!api returned them. This is why the
+    /// scores of a poll aren't sent while the response is being deserialized;
+    /// a later score of the response might still have a lower id.
+    ///
+    /// Returns the sent scores.
+    pub fn broadcast(&self, scores: &mut Scores, prev_cursor_id: Option<u64>) -> Batch {
+        // Placeholders would be sent as empty messages
+        scores.retain(|score| !score.is_placeholder());
 
-        let ws_stream = match tokio_tungstenite::accept_async(stream).await {
-            Ok(stream) => stream,
-            Err(err) => return error!(?err, "Error during the websocket handshake"),
-        };
+        // Iterating the set yields scores in ascending order of their id
+        let range = scores.range(Score::only_id(prev_cursor_id.map_or(0, |id| id + 1))..);
 
-        trace!(%addr, "WebSocket connection established");
+        // Holding the history lock while sending ensures that clients that
+        // are being registered concurrently receive each score exactly once;
+        // either through their history replay or through this broadcast.
+        let mut history = self.history.lock().unwrap();
+        let mut recent_ids = self.recent_ids.lock().unwrap();
 
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        ctx.clients.pin().insert(addr, tx.clone());
+        let batch: Batch = range
+            .filter(|score| recent_ids.insert(score.id()))
+            .cloned()
+            .collect();
 
-        let (mut outgoing, mut incoming) = ws_stream.split();
+        drop(recent_ids);
 
-        let initial_fut = tokio::time::timeout(Duration::from_secs(5), incoming.next());
+        if let Some(score) = batch.last() {
+            self.latest_id.fetch_max(score.id(), Relaxed);
+        }
 
-        let Ok(initial) = initial_fut.await else {
-            let err = "Require initial message containing either `\"connect\"` \
-                or a score id to resume from";
-            let _: Result<_, _> = outgoing.send(Message::Text(err.into())).await;
-            info!("Disconnecting from {addr} due to missing initial message");
+        if self.dry_run {
+            let ids: Vec<_> = batch.iter().map(Score::id).collect();
+            info!(?ids, "Dry run, not sending {} scores", batch.len());
+        } else {
+            self.fan_out(&batch);
+        }
 
-            return;
-        };
+        history.append(scores);
 
-        let resume_id = match initial.map(|res| res.map(Event::try_from)) {
-            Some(Ok(Ok(Event::Connect))) => {
-                info!(%addr, "Connect");
+        while history.len() > self.max_history_len {
+            history.pop_first();
+        }
+
+        debug!(history_len = history.len());
 
-                None
+        batch
+    }
+
+    /// Sends `batch` to the clients, the score stream, and the audit log.
+    fn fan_out(&self, batch: &Batch) {
+        if !batch.is_empty() {
+            *self.projections.lock().unwrap() = Some((Arc::clone(batch), StdHashMap::new()));
+
+            if let Some(audit) = &self.audit {
+                audit.append(batch);
             }
-            Some(Ok(Ok(Event::Resume { score_id }))) => {
-                info!(score_id, %addr, "Resume");
 
-                Some(score_id)
+            if self.scores.receiver_count() > 0 {
+                for score in batch.iter() {
+                    let _: Result<_, _> = self.scores.send(score.clone());
+                }
             }
-            Some(Ok(Err(err))) => {
-                let _: Result<_, _> = outgoing.send(Message::Text(err.to_string().into())).await;
 
-                return;
+            METRICS
+                .scores_broadcast
+                .fetch_add(batch.len() as u64, Relaxed);
+
+            let clients = self.clients.pin();
+            let _span =
+                debug_span!("fan_out", scores = batch.len(), clients = clients.len()).entered();
+
+            for (id, tx) in &clients {
+                if let Err(TrySendError::Full(_)) = tx.try_send(Arc::clone(batch)) {
+                    // Dropping the sender closes the channel so the client's
+                    // connection task will disconnect it.
+                    warn!(id, "Client is lagging behind, disconnecting");
+                    clients.remove(id);
+                }
             }
-            Some(Err(err)) => return error!(?err, "Failed to receive initial message"),
-            None => return,
-        };
+        }
 
-        ctx.send_history(resume_id, addr, &tx);
+        info!(
+            "Sent {} scores to {} client(s)",
+            batch.len(),
+            self.clients.len()
+        );
+    }
 
-        let forward_fut = futures_util::stream::poll_fn(|cx| rx.poll_recv(cx))
-            .map(Ok)
-            .forward(&mut outgoing);
+    pub async fn handle_connection(ctx: Arc<Self>, conn: (TcpStream, SocketAddr)) {
+        let id = ctx.new_connection_id();
+        let span = info_span!("connection", id, addr = %conn.1);
 
-        let await_disconnect = incoming.try_any(|msg| {
-            let bytes = match msg {
-                Message::Text(ref bytes) => bytes.as_bytes(),
-                Message::Binary(ref bytes) => bytes,
-                _ => return futures_util::future::ready(false),
-            };
+        Self::handle_connection_inner(ctx, conn, id)
+            .instrument(span)
+            .await;
+    }
 
-            futures_util::future::ready(bytes == b"disconnect")
-        });
+    async fn handle_connection_inner(
+        ctx: Arc<Self>,
+        (stream, addr): (TcpStream, SocketAddr),
+        id: u64,
+    ) {
+        trace!(%addr, "Incoming TCP connection from");
 
-        tokio::select! {
-            _ = forward_fut => {},
-            res = await_disconnect => {
-                if matches!(res, Ok(true)) {
-                    ctx.process_disconnect(&mut outgoing).await;
-                }
-            },
+        // Dropping the stream closes the connection
+        if !ctx.is_allowed(addr) {
+            return info!(%addr, "Rejecting connection from disallowed address");
         }
 
-        info!("{addr} disconnected");
-        ctx.clients.pin().remove(&addr);
-    }
+        if let Some(delay) = ctx.warmup_delay() {
+            trace!(?delay, "Delaying connection during warmup");
+            tokio::time::sleep(delay).await;
+        }
 
-    fn send_history(&self, resume_id: Option<u64>, addr: SocketAddr, tx: &Sender) {
-        let range = Score::only_id(resume_id.map_or(0, |id| id + 1))..;
-        let mut sent = 0;
+        // Peers behind a trusted proxy are throttled once their real IP
+        // address is known through the handshake request
+        let proxied = ctx.is_trusted_proxy(addr.ip());
 
-        for score in self.history.lock().unwrap().range(range) {
-            sent += 1;
-            let _: Result<_, _> = tx.send(score.as_message());
+        if !proxied && !ctx.within_rate(addr.ip()) {
+            return info!(%addr, "Rejecting connection due to connection rate");
         }
 
-        info!(%addr, "Sent {sent} scores from the history");
-    }
+        let mut options = ConnectOptions::default();
+        let mut use_deflate = false;
 
-    async fn process_disconnect(&self, outgoing: &mut Outgoing) {
-        info!("Processing disconnect...");
+        // The error type is dictated by tungstenite
+        #[allow(clippy::result_large_err)]
+        let callback = |req: &Request, mut res: Response| {
+            if !ctx.is_authorized(req) {
+                info!(%addr, "Rejecting unauthorized connection");
 
-        let id = self.history.lock().unwrap().last().map_or(0, Score::id);
-        let msg = Message::Text(itoa::Buffer::new().format(id).into());
+                let mut res = ErrorResponse::new(Some("Unauthorized".to_owned()));
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
 
-        if let Err(err) = outgoing.send(msg).await {
-            warn!(?err, "Failed to send score id {id} on disconnect");
+                return Err(res);
+            }
+
+            if proxied {
+                let ip = ctx.real_ip(req).unwrap_or(addr.ip());
+
+                if !ctx.within_rate(ip) {
+                    info!(%addr, %ip, "Rejecting proxied connection due to connection rate");
+
+                    let mut res = ErrorResponse::new(Some("Too many connections".to_owned()));
+                    *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+
+                    return Err(res);
+                }
+            }
+
+            if let Some(query) = req.uri().query() {
+                options = ConnectOptions::from_query(query);
+            }
+
+            let requested = req
+                .headers()
+                .get_all(SEC_WEBSOCKET_PROTOCOL)
+                .iter()
+                .filter_map(|value| value.to_str().ok());
+
+            if let Some(protocol) = options.negotiate(requested) {
+                let value = HeaderValue::from_static(protocol);
+                res.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value);
+            }
+
+            use_deflate = ctx.negotiate_deflate(req, &mut res);
+
+            Ok(res)
+        };
+
+        let handshake = async {
+            let stream = match ctx.tls {
+                Some(ref acceptor) => match acceptor.accept(stream).await {
+                    Ok(stream) => Stream::Tls(Box::new(stream)),
+                    Err(err) => {
+                        error!(?err, "Error during the TLS handshake");
+
+                        return None;
+                    }
+                },
+                None => Stream::Plain(stream),
+            };
+
+            match tokio_tungstenite::accept_hdr_async(Deflate::new(stream), callback).await {
+                Ok(stream) => Some(stream),
+                Err(err) => {
+                    error!(?err, "Error during the websocket handshake");
+
+                    None
+                }
+            }
+        };
+
+        // Peers that never complete the handshake would hold on to the task
+        let mut ws_stream = match tokio::time::timeout(ctx.handshake_timeout, handshake).await {
+            Ok(Some(stream)) => stream,
+            Ok(None) => return,
+            Err(_) => return info!(%addr, "Disconnecting due to handshake timeout"),
+        };
+
+        if let Some(level) = ctx.deflate.filter(|_| use_deflate) {
+            ws_stream.get_mut().enable(level);
         }
+
+        let Some(_slot) = ctx.acquire_slot() else {
+            warn!(%addr, "Rejecting connection because the connection limit is reached");
+
+            let frame = CloseFrame {
+                code: CloseCode::Again,
+                reason: "Too many connections".into(),
+            };
+
+            let _: Result<_, _> = ws_stream.close(Some(frame)).await;
+
+            return;
+        };
+
+        trace!(%addr, "WebSocket connection established");
+
+        let peer = Peer { id, addr };
+
+        if let Some(ref observer) = ctx.observer {
+            observer.connected(peer);
+        }
+
+        let reason = ctx.serve_connection(ws_stream, options, peer).await;
+
+        if let Some(ref observer) = ctx.observer {
+            observer.disconnected(peer, reason);
+        }
+    }
+
+    /// Replays the history and then serves the client until it disconnects.
+    async fn serve_connection(
+        &self,
+        ws_stream: WsStream,
+        options: ConnectOptions,
+        peer: Peer,
+    ) -> DisconnectReason {
+        let Peer { id, addr } = peer;
+        let (mut outgoing, mut incoming) = ws_stream.split();
+
+        if options.hello {
+            // Same id as the one of the connection's span
+            let msg = format!(r#"{{"type":"hello","connection_id":"{id}"}}"#);
+
+            if outgoing.send(Message::Text(msg.into())).await.is_err() {
+                return DisconnectReason::SendFailed;
+            }
+        }
+
+        let Some(replay) = self
+            .receive_replay(&mut incoming, &mut outgoing, addr)
+            .await
+        else {
+            return DisconnectReason::InitialMessage;
+        };
+
+        // Subscribing before registering the client ensures that it's sent
+        // the `live` message unless backfilling finished before its history
+        // replay, which then already contains all backfilled scores.
+        let live = self.live.subscribe();
+        let (rx, too_old) = self.subscribe(replay, peer);
+
+        if too_old {
+            // Scores are only forwarded further below so this message is
+            // guaranteed to arrive before any score.
+            let msg = Message::Text(r#"{"error":"resume_too_old"}"#.into());
+
+            if outgoing.send(msg).await.is_err() {
+                self.unsubscribe(id);
+
+                return DisconnectReason::SendFailed;
+            }
+        }
+
+        let reason = self
+            .serve_client(outgoing, incoming, rx, live, options, peer)
+            .await;
+
+        info!(?reason, "{addr} disconnected");
+        self.unsubscribe(id);
+
+        reason
+    }
+
+    /// Forwards batches to the client and handles its messages until it
+    /// disconnects.
+    async fn serve_client(
+        &self,
+        mut outgoing: Outgoing,
+        mut incoming: Incoming,
+        mut rx: Receiver,
+        mut live: watch::Receiver<bool>,
+        options: ConnectOptions,
+        peer: Peer,
+    ) -> DisconnectReason {
+        let mut filter = Filter::default();
+
+        let mut ping = tokio::time::interval_at(
+            tokio::time::Instant::now() + self.ping_interval,
+            self.ping_interval,
+        );
+
+        // Set while awaiting a response to a ping
+        let mut pong_deadline = None;
+
+        let mut heartbeat = self
+            .heartbeat_interval
+            .map(|period| tokio::time::interval_at(tokio::time::Instant::now() + period, period));
+
+        let mut coalescer = options.coalesce.map(Coalescer::new);
+        let mut stats = ForwardStats::default();
+
+        let mut cursors = self.cursors.subscribe();
+        let mut sent_cursor_id = None;
+
+        // Starts off with the current cursor, if any
+        cursors.mark_changed();
+
+        loop {
+            let pong_timeout = sleep_until(pong_deadline);
+
+            let heartbeat_tick = tick(heartbeat.as_mut());
+            let window_end = sleep_until(coalescer.as_ref().and_then(Coalescer::deadline));
+
+            tokio::select! {
+                () = window_end => {
+                    let batch = coalescer.as_mut().map_or_else(Batch::default, Coalescer::flush);
+
+                    if self.forward(&mut outgoing, &batch, &filter, options, &mut stats).await.is_err() {
+                        break DisconnectReason::SendFailed;
+                    }
+                }
+                Ok(()) = live.changed() => {
+                    // Backfilled scores are sent first
+                    let drained = self
+                        .drain(&mut outgoing, &mut rx, &filter, options, coalescer.as_mut(), &mut stats)
+                        .await;
+
+                    let msg = Message::Text(r#"{"type":"live"}"#.into());
+
+                    if drained.is_err() || outgoing.send(msg).await.is_err() {
+                        break DisconnectReason::SendFailed;
+                    }
+                }
+                Ok(()) = cursors.changed(), if options.cursor => {
+                    let Some(msg) = Self::cursor_message(&mut cursors, &mut sent_cursor_id) else {
+                        continue;
+                    };
+
+                    // Scores up to the cursor are sent first
+                    let drained = self
+                        .drain(&mut outgoing, &mut rx, &filter, options, coalescer.as_mut(), &mut stats)
+                        .await;
+
+                    if drained.is_err() || outgoing.send(msg).await.is_err() {
+                        break DisconnectReason::SendFailed;
+                    }
+                }
+                () = heartbeat_tick => {
+                    if outgoing.send(self.heartbeat()).await.is_err() {
+                        break DisconnectReason::SendFailed;
+                    }
+                }
+                _ = ping.tick() => {
+                    if pong_deadline.is_none() {
+                        pong_deadline = Some(tokio::time::Instant::now() + self.ping_timeout);
+
+                        if outgoing.send(Message::Ping(Bytes::new())).await.is_err() {
+                            break DisconnectReason::SendFailed;
+                        }
+                    }
+                }
+                () = pong_timeout => {
+                    info!(addr = %peer.addr, "No response to ping, disconnecting");
+
+                    break DisconnectReason::PingTimeout;
+                }
+                () = self.shutting_down() => {
+                    self.close_for_shutdown(&mut outgoing, &mut rx, &filter, options, coalescer.as_mut(), &mut stats)
+                        .await;
+
+                    break DisconnectReason::Shutdown;
+                }
+                batch = rx.recv() => {
+                    // The sender is only dropped if the client lagged behind
+                    let Some(batch) = batch else {
+                        let frame = CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: "Lagged behind too far".into(),
+                        };
+
+                        let _: Result<_, _> = outgoing.send(Message::Close(Some(frame))).await;
+
+                        break DisconnectReason::Lagged;
+                    };
+
+                    if let Some(ref mut coalescer) = coalescer {
+                        stats.record(0, coalescer.push(&batch, &filter));
+                    } else if self.forward(&mut outgoing, &batch, &filter, options, &mut stats).await.is_err() {
+                        break DisconnectReason::SendFailed;
+                    }
+                }
+                msg = incoming.next() => {
+                    let Some(Ok(msg)) = msg else {
+                        break DisconnectReason::Client;
+                    };
+
+                    // Any message, not just pongs, shows that the client is alive
+                    pong_deadline = None;
+
+                    let handled = self
+                        .handle_message(msg, &mut outgoing, &mut filter, &stats, peer)
+                        .await;
+
+                    if let ControlFlow::Break(reason) = handled {
+                        break reason;
+                    }
+                }
+            }
+        }
+    }
+
+    /// A message carrying the lowest cursor id among all pollers or `None` if
+    /// there is none or it was already sent.
+    fn cursor_message(
+        cursors: &mut watch::Receiver<Cursors>,
+        sent_cursor_id: &mut Option<u64>,
+    ) -> Option<Message> {
+        let cursor_id = cursors.borrow_and_update().values().min().copied()?;
+
+        if *sent_cursor_id == Some(cursor_id) {
+            return None;
+        }
+
+        *sent_cursor_id = Some(cursor_id);
+        let msg = format!(r#"{{"type":"cursor","id":{cursor_id}}}"#);
+
+        Some(Message::Text(msg.into()))
+    }
+
+    /// Handles a message of the client and breaks if it should no longer be
+    /// served.
+    async fn handle_message(
+        &self,
+        msg: Message,
+        outgoing: &mut Outgoing,
+        filter: &mut Filter,
+        stats: &ForwardStats,
+        peer: Peer,
+    ) -> ControlFlow<DisconnectReason> {
+        let addr = peer.addr;
+
+        let response = match Event::try_from(msg) {
+            // Keep the previous filter
+            Ok(Event::Subscribe(new_filter))
+                if new_filter.user_ids().len() > self.max_filter_user_ids =>
+            {
+                debug!(%addr, user_ids = new_filter.user_ids().len(), "Too many user ids");
+                let err = format!(
+                    "invalid message: at most {} user ids are allowed",
+                    self.max_filter_user_ids
+                );
+
+                Message::Text(err.into())
+            }
+            Ok(Event::Subscribe(new_filter)) => {
+                info!(%addr, ?new_filter, "Subscribe");
+                *filter = *new_filter;
+
+                if let Some(ref observer) = self.observer {
+                    observer.subscribed(peer, filter);
+                }
+
+                return ControlFlow::Continue(());
+            }
+            Ok(Event::Disconnect) => {
+                self.process_disconnect(outgoing).await;
+
+                return ControlFlow::Break(DisconnectReason::Client);
+            }
+            Ok(Event::Stats) => stats.message(),
+            // Keep the previous filter; control frames are not worth a
+            // response
+            Err(err @ (EventError::Bytes | EventError::Json(_))) => {
+                debug!(%addr, %err, "Invalid message");
+
+                Message::Text(err.to_string().into())
+            }
+            _ => return ControlFlow::Continue(()),
+        };
+
+        if outgoing.send(response).await.is_ok() {
+            ControlFlow::Continue(())
+        } else {
+            ControlFlow::Break(DisconnectReason::SendFailed)
+        }
+    }
+
+    /// A random delay for connections that are accepted within the warmup
+    /// window after startup so that reconnecting clients are spread out.
+    fn warmup_delay(&self) -> Option<Duration> {
+        let window = self.warmup_window?;
+
+        if self.started.elapsed() >= window || self.warmup_jitter.is_zero() {
+            return None;
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let max_millis = self.warmup_jitter.as_millis() as u64;
+
+        Some(Duration::from_millis(
+            rand::thread_rng().gen_range(0..=max_millis),
+        ))
+    }
+
+    /// The reason of close frames upon shutdown, hinting at a random delay
+    /// within the warmup window after which clients should reconnect.
+    fn shutdown_reason(&self) -> String {
+        match self.warmup_window {
+            Some(window) => {
+                let secs = rand::thread_rng().gen_range(1..=window.as_secs().max(1));
+
+                format!("Server shutting down; retry after {secs}s")
+            }
+            None => "Server shutting down".to_owned(),
+        }
+    }
+
+    /// Forwards whatever has already been broadcast and then sends a close
+    /// frame.
+    async fn close_for_shutdown(
+        &self,
+        outgoing: &mut Outgoing,
+        rx: &mut Receiver,
+        filter: &Filter,
+        options: ConnectOptions,
+        coalescer: Option<&mut Coalescer>,
+        stats: &mut ForwardStats,
+    ) {
+        if self
+            .drain(outgoing, rx, filter, options, coalescer, stats)
+            .await
+            .is_err()
+        {
+            return;
+        }
+
+        let frame = CloseFrame {
+            code: CloseCode::Away,
+            reason: self.shutdown_reason().into(),
+        };
+
+        let _: Result<_, _> = outgoing.send(Message::Close(Some(frame))).await;
+    }
+
+    /// Forwards whatever has already been broadcast, including pending
+    /// coalesced scores.
+    async fn drain(
+        &self,
+        outgoing: &mut Outgoing,
+        rx: &mut Receiver,
+        filter: &Filter,
+        options: ConnectOptions,
+        mut coalescer: Option<&mut Coalescer>,
+        stats: &mut ForwardStats,
+    ) -> Result<(), WsError> {
+        while let Ok(batch) = rx.try_recv() {
+            match coalescer {
+                Some(ref mut coalescer) => stats.record(0, coalescer.push(&batch, filter)),
+                None => {
+                    self.forward(outgoing, &batch, filter, options, stats)
+                        .await?;
+                }
+            }
+        }
+
+        if let Some(coalescer) = coalescer {
+            self.forward(outgoing, &coalescer.flush(), filter, options, stats)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// A message carrying the latest broadcast score id and the current unix
+    /// timestamp in seconds.
+    fn heartbeat(&self) -> Message {
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        let msg = match self.latest_id.load(Relaxed) {
+            0 => format!(r#"{{"type":"heartbeat","latest_id":null,"timestamp":{timestamp}}}"#),
+            latest_id => {
+                format!(r#"{{"type":"heartbeat","latest_id":{latest_id},"timestamp":{timestamp}}}"#)
+            }
+        };
+
+        Message::Text(msg.into())
+    }
+
+    /// Awaits the client's initial message which specifies the scores to
+    /// replay from the history.
+    async fn receive_replay(
+        &self,
+        incoming: &mut Incoming,
+        outgoing: &mut Outgoing,
+        addr: SocketAddr,
+    ) -> Option<Replay> {
+        let initial_fut = tokio::time::timeout(self.initial_message_timeout, incoming.next());
+
+        let Ok(initial) = initial_fut.await else {
+            let err = "Require initial message containing either `\"connect\"`, \
+                a score id to resume from, `{\"replay\": <count>}`, \
+                or `{\"resume_from\": <score id>}`";
+            info!("Disconnecting from {addr} due to missing initial message");
+            Self::reject(
+                outgoing,
+                err.into(),
+                CloseCode::Policy,
+                "Missing initial message",
+            )
+            .await;
+
+            return None;
+        };
+
+        let msg = match initial {
+            Some(Ok(msg)) => msg,
+            Some(Err(err)) => {
+                error!(?err, "Failed to receive initial message");
+
+                return None;
+            }
+            None => return None,
+        };
+
+        let replay = match Event::try_from(msg) {
+            Ok(Event::Connect) => {
+                info!(%addr, "Connect");
+
+                Replay::All
+            }
+            Ok(Event::Resume { score_id }) => {
+                info!(score_id, %addr, "Resume");
+
+                Replay::After(score_id)
+            }
+            Ok(Event::Replay { count }) => {
+                info!(count, %addr, "Replay");
+
+                Replay::Last(count)
+            }
+            Ok(Event::ResumeFrom { score_id }) => {
+                info!(score_id, %addr, "Resume from");
+
+                Replay::ResumeFrom(score_id)
+            }
+            Ok(Event::Subscribe(_) | Event::Disconnect | Event::Stats) => {
+                let err = "Initial message must contain either `\"connect\"`, \
+                    a score id to resume from, `{\"replay\": <count>}`, \
+                    or `{\"resume_from\": <score id>}`";
+                Self::reject(
+                    outgoing,
+                    err.into(),
+                    CloseCode::Policy,
+                    "Invalid initial message",
+                )
+                .await;
+
+                return None;
+            }
+            Err(err) => {
+                let code = CloseCode::Unsupported;
+                Self::reject(outgoing, err.to_string(), code, "Invalid initial message").await;
+
+                return None;
+            }
+        };
+
+        Some(replay)
+    }
+
+    /// Sends the error message and closes the connection so that clients can
+    /// tell the reason apart through the close code.
+    async fn reject(outgoing: &mut Outgoing, err: String, code: CloseCode, reason: &'static str) {
+        if outgoing.send(Message::Text(err.into())).await.is_err() {
+            return;
+        }
+
+        let frame = CloseFrame {
+            code,
+            reason: reason.into(),
+        };
+
+        let _: Result<_, _> = outgoing.send(Message::Close(Some(frame))).await;
+    }
+
+    /// Accepts the `permessage-deflate` extension if it's enabled and the
+    /// client offered it.
+    fn negotiate_deflate(&self, req: &Request, res: &mut Response) -> bool {
+        if self.deflate.is_none() {
+            return false;
+        }
+
+        let offers = req
+            .headers()
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        let Some(extension) = deflate::negotiate(offers) else {
+            return false;
+        };
+
+        let value = HeaderValue::from_static(extension);
+        res.headers_mut().insert(SEC_WEBSOCKET_EXTENSIONS, value);
+
+        true
+    }
+
+    /// Registers a client and returns the receiver for its batches, starting
+    /// with the replayed history.
+    ///
+    /// Also returns `true` if the client wants to resume from a score id that
+    /// is older than the history.
+    pub(crate) fn subscribe(&self, replay: Replay, peer: Peer) -> (Receiver, bool) {
+        let (tx, rx) = mpsc::channel(self.client_buffer());
+        let too_old = self.register_client(replay, peer, tx);
+
+        (rx, too_old)
+    }
+
+    /// Removes the client with the given connection id.
+    pub(crate) fn unsubscribe(&self, id: u64) {
+        self.clients.pin().remove(&id);
+    }
+
+    /// Returns a unique id for a new websocket or HTTP stream connection.
+    pub(crate) fn new_connection_id(&self) -> u64 {
+        self.next_connection_id.fetch_add(1, Relaxed)
+    }
+
+    /// Sends scores from the history and then adds the client so that it
+    /// receives future broadcasts.
+    ///
+    /// Returns `true` if the client wants to resume from a score id that is
+    /// older than the history, meaning some scores may be missing.
+    fn register_client(&self, replay: Replay, peer: Peer, tx: Sender) -> bool {
+        let history = self.history.lock().unwrap();
+
+        let too_old = matches!(
+            replay,
+            Replay::ResumeFrom(score_id) if history.first().is_some_and(|oldest| score_id < oldest.id)
+        );
+
+        let scores: Box<dyn Iterator<Item = &Score>> = match replay {
+            Replay::All => Box::new(history.iter()),
+            Replay::After(score_id) | Replay::ResumeFrom(score_id) => {
+                match score_id.checked_add(1) {
+                    Some(next_id) => Box::new(history.range(Score::only_id(next_id)..)),
+                    // No score can follow the maximum id
+                    None => Box::new(std::iter::empty()),
+                }
+            }
+            Replay::Last(count) => {
+                Box::new(history.iter().skip(history.len().saturating_sub(count)))
+            }
+        };
+
+        let batch: Batch = scores.cloned().collect();
+        let sent = batch.len();
+
+        if !batch.is_empty() {
+            let _: Result<_, _> = tx.try_send(batch);
+        }
+
+        self.clients.pin().insert(peer.id, tx);
+
+        info!(addr = %peer.addr, "Sent {sent} scores from the history");
+
+        too_old
+    }
+
+    async fn forward(
+        &self,
+        outgoing: &mut Outgoing,
+        batch: &Batch,
+        filter: &Filter,
+        options: ConnectOptions,
+        stats: &mut ForwardStats,
+    ) -> Result<(), WsError> {
+        let matching = batch
+            .iter()
+            .enumerate()
+            .filter(|(_, score)| filter.matches(score));
+
+        let scores: Vec<Bytes> = match filter.fields() {
+            Some(fields) => {
+                let projected = self.project(batch, fields);
+
+                matching.map(|(i, _)| projected[i].clone()).collect()
+            }
+            None => matching.map(|(_, score)| score.raw().clone()).collect(),
+        };
+
+        stats.record(scores.len(), batch.len() - scores.len());
+
+        if scores.is_empty() {
+            return Ok(());
+        }
+
+        if options.batch {
+            return outgoing.send(Message::Binary(encode_batch(scores))).await;
+        }
+
+        for score in scores {
+            let msg = if options.text {
+                text_message(score)
+            } else {
+                Message::Binary(score)
+            };
+
+            outgoing.feed(msg).await?;
+        }
+
+        outgoing.flush().await
+    }
+
+    /// Projects all scores of the batch onto the given fields.
+    ///
+    /// Projections of the most recently broadcast batch are cached so that
+    /// each distinct set of fields is only projected once per poll.
+    fn project(&self, batch: &Batch, fields: &BTreeSet<Box<str>>) -> Arc<[Bytes]> {
+        let mut cache = self.projections.lock().unwrap();
+
+        let cached = cache
+            .as_mut()
+            .filter(|(cached, _)| Arc::ptr_eq(cached, batch))
+            .map(|(_, projections)| projections);
+
+        let project = || batch.iter().map(|score| score.project(fields)).collect();
+
+        match cached {
+            Some(projections) => {
+                Arc::clone(projections.entry(fields.clone()).or_insert_with(project))
+            }
+            None => project(),
+        }
+    }
+
+    async fn process_disconnect(&self, outgoing: &mut Outgoing) {
+        info!("Processing disconnect...");
+
+        let id = self.history.lock().unwrap().last().map_or(0, Score::id);
+        let msg = Message::Text(itoa::Buffer::new().format(id).into());
+
+        if let Err(err) = outgoing.send(msg).await {
+            warn!(?err, "Failed to send score id {id} on disconnect");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::MaybeTlsStream;
+
+    use super::*;
+    use crate::osu::{mock, ScoresDeserializer};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    async fn serve() -> (Arc<Context>, SocketAddr) {
+        serve_with(&Setup::default()).await
+    }
+
+    async fn serve_with(setup: &Setup) -> (Arc<Context>, SocketAddr) {
+        serve_context(Context::new(setup)).await
+    }
+
+    async fn serve_context(ctx: Context) -> (Arc<Context>, SocketAddr) {
+        let ctx = Arc::new(ctx);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn({
+            let ctx = Arc::clone(&ctx);
+
+            async move {
+                while let Ok(conn) = listener.accept().await {
+                    tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+                }
+            }
+        });
+
+        (ctx, addr)
+    }
+
+    async fn connect(addr: SocketAddr, messages: &[&str]) -> Client {
+        connect_with_query(addr, "", messages).await
+    }
+
+    async fn connect_with_query(addr: SocketAddr, query: &str, messages: &[&str]) -> Client {
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}{query}"))
+            .await
+            .unwrap();
+
+        for &msg in messages {
+            client.send(Message::from(msg)).await.unwrap();
+        }
+
+        client
+    }
+
+    fn scores(json: &str) -> Scores {
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(json.to_owned().into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        scores
+    }
+
+    /// Receives binary messages until no message arrives for a short while.
+    async fn receive_messages(client: &mut Client) -> Vec<Bytes> {
+        let mut messages = Vec::new();
+
+        while let Ok(Some(msg)) =
+            tokio::time::timeout(Duration::from_millis(200), client.next()).await
+        {
+            let Message::Binary(bytes) = msg.unwrap() else {
+                panic!("expected binary message");
+            };
+
+            messages.push(bytes);
+        }
+
+        messages
+    }
+
+    /// Receives score ids until no message arrives for a short while.
+    async fn receive_ids(client: &mut Client) -> Vec<u64> {
+        receive_messages(client)
+            .await
+            .iter()
+            .map(|bytes| {
+                let score: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+
+                score["id"].as_u64().unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ruleset_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut mania = connect(addr, &["connect", r#"{"ruleset": 3}"#]).await;
+        let mut osu = connect(addr, &["connect", r#"{"ruleset": 0}"#]).await;
+        let mut all = connect(addr, &["connect"]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "ruleset_id": 0}, {"id": 2, "ruleset_id": 3}, {"id": 3, "ruleset_id": 3}, {"id": 4, "ruleset_id": 1}]}"#,
+        );
+
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut mania).await, [2, 3]);
+        assert_eq!(receive_ids(&mut osu).await, [1]);
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn forward_stats() {
+        let (ctx, addr) = serve().await;
+
+        let mut client = connect(addr, &["connect", r#"{"ruleset": 3}"#]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let filtered_before = METRICS.scores_filtered.load(Relaxed);
+
+        let mut scores_ = scores(
+            r#"{"scores": [{"id": 1, "ruleset_id": 0}, {"id": 2, "ruleset_id": 3}, {"id": 3, "ruleset_id": 1}]}"#,
+        );
+
+        ctx.broadcast(&mut scores_, None);
+        assert_eq!(receive_ids(&mut client).await, [2]);
+
+        client.send(Message::from("stats")).await.unwrap();
+        let stats = client.next().await.unwrap().unwrap();
+        assert_eq!(
+            stats.into_text().unwrap(),
+            r#"{"type":"stats","forwarded":1,"filtered":2}"#
+        );
+
+        let mut scores_ = scores(r#"{"scores": [{"id": 4, "ruleset_id": 2}]}"#);
+        ctx.broadcast(&mut scores_, None);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        client.send(Message::from("stats")).await.unwrap();
+        let stats = client.next().await.unwrap().unwrap();
+        assert_eq!(
+            stats.into_text().unwrap(),
+            r#"{"type":"stats","forwarded":1,"filtered":3}"#
+        );
+
+        assert!(METRICS.scores_filtered.load(Relaxed) >= filtered_before + 3);
+    }
+
+    #[tokio::test]
+    async fn rulesets_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut client = connect(addr, &["connect", r#"{"rulesets": [0, 3]}"#]).await;
+        let mut all = connect(addr, &["connect", r#"{"rulesets": []}"#]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ = scores(
+            r#"{"scores": [{"id": 1, "ruleset_id": 0}, {"id": 2, "ruleset_id": 1}, {"id": 3, "ruleset_id": 2}, {"id": 4, "ruleset_id": 3}, {"id": 5}]}"#,
+        );
+
+        ctx.broadcast(&mut scores_, None);
+
+        assert_eq!(receive_ids(&mut client).await, [1, 4]);
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 3, 4, 5]);
+
+        client
+            .send(Message::from(r#"{"rulesets": [1, 4]}"#))
+            .await
+            .unwrap();
+
+        let Some(Ok(Message::Text(text))) = client.next().await else {
+            panic!("expected text message");
+        };
+
+        assert!(text.contains("invalid ruleset 4"), "{text}");
+
+        // The previous filter is kept
+        let mut scores_ =
+            scores(r#"{"scores": [{"id": 6, "ruleset_id": 1}, {"id": 7, "ruleset_id": 3}]}"#);
+        ctx.broadcast(&mut scores_, Some(5));
+        assert_eq!(receive_ids(&mut client).await, [7]);
+    }
+
+    #[tokio::test]
+    async fn passed_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut passed = connect(addr, &["connect", r#"{"passed_only": true}"#]).await;
+        let mut mania_passed =
+            connect(addr, &["connect", r#"{"ruleset": 3, "passed_only": true}"#]).await;
+        let mut all = connect(addr, &["connect", r#"{"passed_only": false}"#]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "passed": true, "ruleset_id": 3}, {"id": 2, "passed": false, "ruleset_id": 3}, {"id": 3}, {"id": 4, "passed": true, "ruleset_id": 0}]}"#,
+        );
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut passed).await, [1, 4]);
+        assert_eq!(receive_ids(&mut mania_passed).await, [1]);
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn beatmap_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut subset = connect(addr, &["connect", r#"{"beatmap_ids": [10, 30]}"#]).await;
+        let mut all = connect(addr, &["connect", r#"{"beatmap_ids": []}"#]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "beatmap_id": 10, "user": {"id": 30}}, {"id": 2, "beatmap_id": 20}, {"id": 10, "beatmap": {"id": 30}}, {"id": 30}, {"id": 31, "beatmap_id": 10}]}"#,
+        );
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut subset).await, [1, 10, 31]);
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 10, 30, 31]);
+    }
+
+    #[tokio::test]
+    async fn user_filter() {
+        let setup = Setup {
+            max_filter_user_ids: 3,
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+
+        let mut watchlist = connect(addr, &["connect", r#"{"user_ids": [2, 1023, 124493]}"#]).await;
+        let mut all = connect(addr, &["connect", r#"{"user_ids": []}"#]).await;
+        let mut too_many = connect(
+            addr,
+            &[
+                "connect",
+                r#"{"user_ids": [1]}"#,
+                r#"{"user_ids": [2, 3, 4, 5]}"#,
+            ],
+        )
+        .await;
+
+        let Some(Ok(Message::Text(text))) = too_many.next().await else {
+            panic!("expected error message");
+        };
+
+        assert!(text.contains("at most 3 user ids"), "{text}");
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "user": {"id": 2}}, {"id": 2, "user": {"id": 5}}, {"id": 3}, {"id": 4, "user": {"id": 124493}}, {"id": 5, "user": {"id": 1}}]}"#,
+        );
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut watchlist).await, [1, 4]);
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 3, 4, 5]);
+        // Keeps its previous filter
+        assert_eq!(receive_ids(&mut too_many).await, [5]);
+    }
+
+    #[tokio::test]
+    async fn pp_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut high = connect(addr, &["connect", r#"{"min_pp": 500.0}"#]).await;
+        let mut any = connect(addr, &["connect", r#"{"min_pp": 0}"#]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "pp": 500}, {"id": 2, "pp": 499.99}, {"id": 3, "pp": null}, {"id": 4, "pp": 727.27}, {"id": 5}]}"#,
+        );
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut high).await, [1, 4]);
+        assert_eq!(receive_ids(&mut any).await, [1, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn rank_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut top = connect(addr, &["connect", r#"{"max_rank": 50}"#]).await;
+        let mut all = connect(addr, &["connect"]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "rank_global": 1}, {"id": 2, "rank_global": 51}, {"id": 3, "rank_global": null}, {"id": 4, "rank_global": 50}, {"id": 5}]}"#,
+        );
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut top).await, [1, 4]);
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn projection() {
+        let (ctx, addr) = serve().await;
+
+        let mut projected = connect(addr, &["connect", r#"{"fields": ["id", "pp"]}"#]).await;
+        let mut full = connect(addr, &["connect"]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "pp": 1.5, "user": {"id": 2, "pp": 3}}, {"rank": "A", "id": 2}]}"#,
+        );
+        let batch = ctx.broadcast(&mut scores, None);
+
+        assert_eq!(
+            receive_messages(&mut projected).await,
+            [br#"{"id":1,"pp":1.5}"#.as_slice(), br#"{"id":2}"#]
+        );
+
+        let full = receive_messages(&mut full).await;
+        assert_eq!(full[1], br#"{"rank": "A", "id": 2}"#[..]);
+
+        // The connection task already cached the projection
+        let fields = ["pp", "id"].map(Box::from).into();
+        let first = ctx.project(&batch, &fields);
+        assert!(Arc::ptr_eq(&first, &ctx.project(&batch, &fields)));
+    }
+
+    #[tokio::test]
+    async fn update_filter() {
+        let (ctx, addr) = serve().await;
+
+        let mut client = connect(addr, &["connect", r#"{"ruleset": 3}"#]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ =
+            scores(r#"{"scores": [{"id": 1, "ruleset_id": 0}, {"id": 2, "ruleset_id": 3}]}"#);
+        ctx.broadcast(&mut scores_, None);
+        assert_eq!(receive_ids(&mut client).await, [2]);
+
+        client
+            .send(Message::from(r#"{"ruleset": 0}"#))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ =
+            scores(r#"{"scores": [{"id": 3, "ruleset_id": 0}, {"id": 4, "ruleset_id": 3}]}"#);
+        ctx.broadcast(&mut scores_, Some(2));
+        assert_eq!(receive_ids(&mut client).await, [3]);
+
+        client
+            .send(Message::from(r#"{"ruleset": "mania"}"#))
+            .await
+            .unwrap();
+
+        let Some(Ok(Message::Text(text))) = client.next().await else {
+            panic!("expected text message");
+        };
+
+        assert!(text.starts_with("invalid message"), "{text}");
+
+        client
+            .send(Message::from(r#"{"ruleset": 7}"#))
+            .await
+            .unwrap();
+
+        let Some(Ok(Message::Text(text))) = client.next().await else {
+            panic!("expected text message");
+        };
+
+        assert!(text.contains("invalid ruleset 7"), "{text}");
+
+        // The previous filter is kept
+        let mut scores_ =
+            scores(r#"{"scores": [{"id": 5, "ruleset_id": 0}, {"id": 6, "ruleset_id": 3}]}"#);
+        ctx.broadcast(&mut scores_, Some(4));
+        assert_eq!(receive_ids(&mut client).await, [5]);
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        let (ctx, addr) = serve().await;
+
+        let mut history =
+            scores(r#"{"scores": [{"id": 1}, {"id": 2}, {"id": 3}, {"id": 4}, {"id": 5}]}"#);
+
+        ctx.broadcast(&mut history, None);
+
+        let mut client = connect(addr, &[r#"{"replay": 2}"#]).await;
+        assert_eq!(receive_ids(&mut client).await, [4, 5]);
+
+        let mut too_many = connect(addr, &[r#"{"replay": 10}"#]).await;
+        assert_eq!(receive_ids(&mut too_many).await, [1, 2, 3, 4, 5]);
+
+        let mut live = scores(r#"{"scores": [{"id": 6}, {"id": 7}]}"#);
+        ctx.broadcast(&mut live, Some(5));
+
+        assert_eq!(receive_ids(&mut client).await, [6, 7]);
+        assert_eq!(receive_ids(&mut too_many).await, [6, 7]);
+    }
+
+    #[tokio::test]
+    async fn batch() {
+        let (ctx, addr) = serve().await;
+
+        let mut batched =
+            connect_with_query(addr, "/?batch", &["connect", r#"{"ruleset": 3}"#]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(
+            r#"{"scores": [{"id": 1, "ruleset_id": 3}, {"id": 2, "ruleset_id": 0}, {"id": 3, "ruleset_id": 3}]}"#,
+        );
+
+        ctx.broadcast(&mut scores, None);
+
+        let Some(Ok(Message::Binary(mut bytes))) = batched.next().await else {
+            panic!("expected binary message");
+        };
+
+        let mut ids = Vec::new();
+
+        while !bytes.is_empty() {
+            let len = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+            let score: serde_json::Value = serde_json::from_slice(&bytes[4..4 + len]).unwrap();
+            ids.push(score["id"].as_u64().unwrap());
+            bytes = bytes.slice(4 + len..);
+        }
+
+        assert_eq!(ids, [1, 3]);
+    }
+
+    #[tokio::test]
+    async fn resume_from() {
+        let (ctx, addr) = serve().await;
+
+        let mut history = scores(r#"{"scores": [{"id": 10}, {"id": 20}, {"id": 30}]}"#);
+        ctx.broadcast(&mut history, None);
+
+        let mut in_history = connect(addr, &[r#"{"resume_from": 20}"#]).await;
+        assert_eq!(receive_ids(&mut in_history).await, [30]);
+
+        let mut at_edge = connect(addr, &[r#"{"resume_from": 10}"#]).await;
+        assert_eq!(receive_ids(&mut at_edge).await, [20, 30]);
+
+        let mut newest = connect(addr, &[r#"{"resume_from": 18446744073709551615}"#]).await;
+        assert!(receive_ids(&mut newest).await.is_empty());
+
+        // Still served
+        newest.send(Message::from("stats")).await.unwrap();
+        let Some(Ok(Message::Text(_))) = newest.next().await else {
+            panic!("expected text message");
+        };
+
+        let mut too_old = connect(addr, &[r#"{"resume_from": 9}"#]).await;
+
+        let Some(Ok(Message::Text(text))) = too_old.next().await else {
+            panic!("expected text message");
+        };
+
+        assert_eq!(text.as_str(), r#"{"error":"resume_too_old"}"#);
+        assert_eq!(receive_ids(&mut too_old).await, [10, 20, 30]);
+    }
+
+    #[tokio::test]
+    async fn ascending_order() {
+        let (ctx, addr) = serve().await;
+
+        let mut client = connect(addr, &["connect"]).await;
+        let mut batched = connect_with_query(addr, "/?batch", &["connect"]).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(r#"{"scores": [{"id": 789}, {"id": 123}, {"id": 456}]}"#);
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut client).await, [123, 456, 789]);
+
+        let Some(Ok(Message::Binary(bytes))) = batched.next().await else {
+            panic!("expected binary message");
+        };
+
+        let ids: Vec<_> = memchr::memmem::find_iter(&bytes, br#""id": "#)
+            .map(|i| &bytes[i + 6..i + 9])
+            .collect();
+
+        assert_eq!(ids, [b"123", b"456", b"789"]);
+    }
+
+    #[tokio::test]
+    async fn multiple_rulesets() {
+        let api = mock::serve(|query| {
+            let scores = match query {
+                "ruleset=osu" => r#"{"id": 1, "ruleset_id": 0}, {"id": 3, "ruleset_id": 0}"#,
+                "ruleset=mania" => r#"{"id": 2, "ruleset_id": 3}, {"id": 4, "ruleset_id": 3}"#,
+                _ => "",
+            };
+
+            format!(r#"{{"scores": [{scores}]}}"#)
+        })
+        .await;
+
+        let mut config = mock::config(api);
+        config.rulesets = vec![Box::from("osu"), Box::from("mania")];
+
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let rulesets = config.rulesets();
+        let osu = Arc::new(Osu::new(config).unwrap());
+
+        for ruleset in rulesets {
+            let poller = Poller {
+                ruleset,
+                interval: 60,
+                min_interval: None,
+                max_interval: None,
+                interval_factor: 1.5,
+                cursor_id: None,
+                cursor_file: None,
+                cursor_source: CursorSource::default(),
+                gap_threshold: None,
+                skip_repeated_batches: false,
+                backfill: None,
+            };
+
+            tokio::spawn(Context::fetch_scores(
+                Arc::clone(&ctx),
+                Arc::clone(&osu),
+                poller,
+            ));
+        }
+
+        for _ in 0..50 {
+            if ctx.history.lock().unwrap().len() == 4 {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let history = ctx.history.lock().unwrap();
+        let scores: Vec<_> = history
+            .iter()
+            .map(|score| (score.id(), score.mode()))
+            .collect();
+
+        assert_eq!(
+            scores,
+            [(1, Some(0)), (2, Some(3)), (3, Some(0)), (4, Some(3))]
+        );
+    }
+
+    #[tokio::test]
+    async fn backfill() {
+        let api = mock::serve(|query| {
+            let (scores, cursor_string) = match query {
+                "" => ("5, 6", r#""a+b/=""#),
+                "cursor_string=a%2Bb%2F%3D" => ("3, 4", r#""c""#),
+                "cursor_string=c" => ("1, 2", "null"),
+                "cursor[id]=6" => ("6, 7", "null"),
+                _ => ("", "null"),
+            };
+
+            let scores: Vec<_> = scores
+                .split(", ")
+                .filter(|id| !id.is_empty())
+                .map(|id| format!(r#"{{"id": {id}}}"#))
+                .collect();
+
+            format!(
+                r#"{{"scores": [{}], "cursor_string": {cursor_string}}}"#,
+                scores.join(", ")
+            )
+        })
+        .await;
+
+        let (ctx, addr) = serve().await;
+        let mut client = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let poller = Poller {
+            ruleset: None,
+            interval: 60,
+            min_interval: None,
+            max_interval: None,
+            interval_factor: 1.5,
+            cursor_id: None,
+            cursor_file: None,
+            cursor_source: CursorSource::default(),
+            gap_threshold: None,
+            skip_repeated_batches: false,
+            backfill: Some(5),
+        };
+
+        let osu = Arc::new(Osu::new(mock::config(api)).unwrap());
+        tokio::spawn(Context::fetch_scores(Arc::clone(&ctx), osu, poller));
+
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+
+        // Scores as their id and the `live` message as its text
+        let mut received = Vec::new();
+
+        while let Ok(Some(msg)) =
+            tokio::time::timeout(Duration::from_millis(200), client.next()).await
+        {
+            match msg.unwrap() {
+                Message::Binary(bytes) => {
+                    let score: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                    received.push(score["id"].to_string());
+                }
+                Message::Text(text) => received.push(text.to_string()),
+                msg => panic!("unexpected message {msg:?}"),
+            }
+        }
+
+        let live = r#"{"type":"live"}"#;
+        assert_eq!(received, ["2", "3", "4", "5", "6", live, "7"]);
+
+        // Clients connecting afterwards already start off live
+        let mut client = connect(addr, &["connect"]).await;
+        assert_eq!(receive_ids(&mut client).await, [2, 3, 4, 5, 6, 7]);
+    }
+
+    #[tokio::test]
+    async fn denied_ip() {
+        let setup = Setup {
+            denied_ips: vec!["127.0.0.0/8".parse().unwrap()],
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+
+        assert!(tokio_tungstenite::connect_async(format!("ws://{addr}"))
+            .await
+            .is_err());
+        assert_eq!(ctx.client_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn tracing_spans() {
+        use tracing::{span, Subscriber};
+        use tracing_subscriber::{layer, prelude::*, Layer};
+
+        /// Records the names of all created spans.
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<&'static str>>>);
+
+        impl<S: Subscriber> Layer<S> for Recorder {
+            fn on_new_span(
+                &self,
+                attrs: &span::Attributes<'_>,
+                _: &span::Id,
+                _: layer::Context<'_, S>,
+            ) {
+                self.0.lock().unwrap().push(attrs.metadata().name());
+            }
+        }
+
+        let recorder = Recorder::default();
+        let _guard = tracing_subscriber::registry()
+            .with(recorder.clone())
+            .set_default();
+
+        let api = mock::serve(|_| r#"{"scores": [{"id": 1}]}"#.to_owned()).await;
+        let (ctx, addr) = serve().await;
+        let mut client = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let poller = Poller {
+            ruleset: None,
+            interval: 60,
+            min_interval: None,
+            max_interval: None,
+            interval_factor: 1.5,
+            cursor_id: None,
+            cursor_file: None,
+            cursor_source: CursorSource::default(),
+            gap_threshold: None,
+            skip_repeated_batches: false,
+            backfill: None,
+        };
+
+        let osu = Arc::new(Osu::new(mock::config(api)).unwrap());
+        tokio::spawn(Context::fetch_scores(Arc::clone(&ctx), osu, poller));
+
+        assert_eq!(receive_ids(&mut client).await, [1]);
+
+        let names = recorder.0.lock().unwrap();
+
+        for name in ["connection", "poll", "deserialize", "fan_out"] {
+            assert!(names.contains(&name), "missing span `{name}` in {names:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn deflate_declined() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let (ctx, addr) = serve().await;
+
+        let mut req = format!("ws://{addr}").into_client_request().unwrap();
+        req.headers_mut().insert(
+            "sec-websocket-extensions",
+            "permessage-deflate; client_max_window_bits"
+                .parse()
+                .unwrap(),
+        );
+
+        let (mut client, res) = tokio_tungstenite::connect_async(req).await.unwrap();
+        assert!(!res.headers().contains_key("sec-websocket-extensions"));
+
+        client.send(Message::from("connect")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = scores(r#"{"scores": [{"id": 1}]}"#);
+        ctx.broadcast(&mut scores, None);
+
+        assert_eq!(receive_ids(&mut client).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn deflate() {
+        use std::io::Write;
+
+        use flate2::{write::DeflateEncoder, Decompress, FlushDecompress};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        /// Sends a masked text frame, compressing it if specified.
+        async fn send(stream: &mut TcpStream, text: &str, compress: bool) {
+            let mut payload = text.as_bytes().to_vec();
+            let mut first = 0x81;
+
+            if compress {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&payload).unwrap();
+                encoder.flush().unwrap();
+                payload = std::mem::take(encoder.get_mut());
+                payload.truncate(payload.len() - 4);
+                first |= 0x40;
+            }
+
+            let mask = [1, 2, 3, 4];
+            let len = u8::try_from(payload.len()).unwrap();
+            let mut frame = vec![first, 0x80 | len];
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+
+            stream.write_all(&frame).await.unwrap();
+        }
+
+        /// Receives a compressed frame and returns its opcode and decompressed
+        /// payload.
+        async fn receive(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+            let mut header = [0; 2];
+            stream.read_exact(&mut header).await.unwrap();
+            assert_eq!(header[0] & 0x40, 0x40, "frame must be compressed");
+            assert!(header[1] < 126);
+
+            let mut payload = vec![0; usize::from(header[1])];
+            stream.read_exact(&mut payload).await.unwrap();
+            payload.extend_from_slice(&[0x00, 0x00, 0xFF, 0xFF]);
+
+            let mut decompressed = Vec::with_capacity(1024);
+            Decompress::new(false)
+                .decompress_vec(&payload, &mut decompressed, FlushDecompress::Sync)
+                .unwrap();
+
+            (header[0] & 0x0F, decompressed)
+        }
+
+        let setup = Setup {
+            deflate: true,
+            ..Default::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let req = format!(
+            "GET / HTTP/1.1\r\n\
+            Host: {addr}\r\n\
+            Connection: Upgrade\r\n\
+            Upgrade: websocket\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Extensions: permessage-deflate; client_max_window_bits\r\n\r\n"
+        );
+
+        stream.write_all(req.as_bytes()).await.unwrap();
+
+        let mut res = Vec::new();
+
+        while !res.ends_with(b"\r\n\r\n") {
+            res.push(stream.read_u8().await.unwrap());
+        }
+
+        let res = String::from_utf8(res).unwrap().to_lowercase();
+        assert!(res.starts_with("http/1.1 101"));
+        assert!(res.contains(
+            "sec-websocket-extensions: permessage-deflate; \
+                server_no_context_takeover; client_no_context_takeover\r\n"
+        ));
+
+        send(&mut stream, "connect", true).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ = scores(r#"{"scores": [{"id": 1, "user": {"id": 2}}, {"id": 3}]}"#);
+        ctx.broadcast(&mut scores_, None);
+
+        assert_eq!(
+            receive(&mut stream).await,
+            (2, br#"{"id": 1, "user": {"id": 2}}"#.to_vec())
+        );
+        assert_eq!(receive(&mut stream).await, (2, br#"{"id": 3}"#.to_vec()));
+
+        // Clients don't have to compress their messages
+        send(&mut stream, r#"{"ruleset": 3}"#, false).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ =
+            scores(r#"{"scores": [{"id": 4, "ruleset_id": 0}, {"id": 5, "ruleset_id": 3}]}"#);
+        ctx.broadcast(&mut scores_, Some(3));
+
+        assert_eq!(
+            receive(&mut stream).await,
+            (2, br#"{"id": 5, "ruleset_id": 3}"#.to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_timeout() {
+        let setup = Setup {
+            ping_interval: 1,
+            ping_timeout: 1,
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+
+        // Pongs are only sent while the stream is being polled
+        let _unresponsive = connect(addr, &["connect"]).await;
+        let mut responsive = connect(addr, &["connect"]).await;
+        tokio::spawn(async move { while let Some(Ok(_)) = responsive.next().await {} });
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(ctx.client_count(), 2);
+
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert_eq!(ctx.client_count(), 1);
+    }
+
+    /// Skips binary messages until a heartbeat arrives.
+    async fn next_heartbeat(client: &mut Client) -> serde_json::Value {
+        loop {
+            let msg = tokio::time::timeout(Duration::from_secs(2), client.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+
+            if let Message::Text(text) = msg {
+                let heartbeat: serde_json::Value = serde_json::from_str(&text).unwrap();
+                assert_eq!(heartbeat["type"], "heartbeat");
+                assert!(heartbeat["timestamp"].as_u64().unwrap() > 0);
+
+                return heartbeat;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn skip_placeholders() {
+        let (ctx, addr) = serve().await;
+        let mut client = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ = scores(r#"{"scores": [{"id": 1}, {"id": 3}]}"#);
+        scores_.insert(Score::only_id(2));
+        scores_.insert(Score::only_id(4));
+
+        // Placeholders still compare by id
+        let ids: Vec<_> = scores_.range(Score::only_id(2)..).map(Score::id).collect();
+        assert_eq!(ids, [2, 3, 4]);
+
+        let batch = ctx.broadcast(&mut scores_, None);
+        assert_eq!(batch.iter().map(Score::id).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(receive_ids(&mut client).await, [1, 3]);
+        assert_eq!(ctx.latest_id.load(Relaxed), 3);
+
+        let mut late = connect(addr, &["connect"]).await;
+        assert_eq!(receive_ids(&mut late).await, [1, 3]);
+    }
+
+    #[tokio::test]
+    async fn coalesce() {
+        let (ctx, addr) = serve().await;
+
+        let mut coalesced = connect_with_query(addr, "/?coalesce=300", &["connect"]).await;
+        let mut all = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let start = Instant::now();
+
+        let mut scores_ = scores(
+            r#"{"scores": [{"id": 1, "user": {"id": 2}}, {"id": 2, "user": {"id": 3}}, {"id": 3, "user": {"id": 2}}]}"#,
+        );
+        ctx.broadcast(&mut scores_, None);
+
+        let mut scores_ =
+            scores(r#"{"scores": [{"id": 4, "user": {"id": 2}}, {"id": 5, "user": {"id": 2}}]}"#);
+        ctx.broadcast(&mut scores_, Some(3));
+
+        // Intermediate scores of user 2 are dropped once the window ends
+        let msg = coalesced.next().await.unwrap().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(
+            msg,
+            Message::Binary(r#"{"id": 2, "user": {"id": 3}}"#.into())
+        );
+        assert_eq!(receive_ids(&mut coalesced).await, [5]);
+
+        assert_eq!(receive_ids(&mut all).await, [1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn observer() {
+        type Events = Arc<Mutex<Vec<(&'static str, Peer, Option<DisconnectReason>)>>>;
+
+        #[derive(Clone, Default)]
+        struct Recorder(Events);
+
+        impl Recorder {
+            fn push(&self, event: &'static str, peer: Peer, reason: Option<DisconnectReason>) {
+                self.0.lock().unwrap().push((event, peer, reason));
+            }
+        }
+
+        impl ConnectionObserver for Recorder {
+            fn connected(&self, peer: Peer) {
+                self.push("connected", peer, None);
+            }
+
+            fn subscribed(&self, peer: Peer, _: &Filter) {
+                self.push("subscribed", peer, None);
+            }
+
+            fn disconnected(&self, peer: Peer, reason: DisconnectReason) {
+                self.push("disconnected", peer, Some(reason));
+            }
+        }
+
+        async fn wait_for(events: &Events, len: usize) {
+            tokio::time::timeout(Duration::from_secs(5), async {
+                while events.lock().unwrap().len() < len {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        }
+
+        fn local_addr(client: &Client) -> SocketAddr {
+            let MaybeTlsStream::Plain(stream) = client.get_ref() else {
+                panic!("expected plain stream");
+            };
+
+            stream.local_addr().unwrap()
+        }
+
+        let recorder = Recorder::default();
+        let events = Arc::clone(&recorder.0);
+        let (_ctx, addr) =
+            serve_context(Context::new(&Setup::default()).with_observer(recorder)).await;
+
+        let mut client =
+            connect_with_query(addr, "/?hello", &["connect", r#"{"ruleset": 3}"#]).await;
+
+        let Some(Ok(Message::Text(text))) = client.next().await else {
+            panic!("expected hello message");
+        };
+
+        let hello: serde_json::Value = serde_json::from_str(&text).unwrap();
+        let id: u64 = hello["connection_id"].as_str().unwrap().parse().unwrap();
+        let peer = Peer {
+            id,
+            addr: local_addr(&client),
+        };
+
+        wait_for(&events, 2).await;
+        client.send(Message::from("disconnect")).await.unwrap();
+        wait_for(&events, 3).await;
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                ("connected", peer, None),
+                ("subscribed", peer, None),
+                ("disconnected", peer, Some(DisconnectReason::Client)),
+            ]
+        );
+
+        // Closing before the initial message
+        let client = connect(addr, &[]).await;
+        let addr = local_addr(&client);
+        drop(client);
+        wait_for(&events, 5).await;
+
+        let events = events.lock().unwrap();
+        assert_eq!(events[3].0, "connected");
+        assert_eq!(events[3].1.addr, addr);
+        assert_eq!(events[4].2, Some(DisconnectReason::InitialMessage));
+    }
+
+    #[tokio::test]
+    async fn hello() {
+        let (ctx, addr) = serve().await;
+
+        let mut history = scores(r#"{"scores": [{"id": 1}]}"#);
+        ctx.broadcast(&mut history, None);
+
+        let mut first = connect_with_query(addr, "/?hello", &["connect"]).await;
+        let mut second = connect_with_query(addr, "/?hello=true", &["connect"]).await;
+
+        let mut ids = Vec::new();
+
+        for client in [&mut first, &mut second] {
+            let Some(Ok(Message::Text(text))) = client.next().await else {
+                panic!("expected hello message");
+            };
+
+            let hello: serde_json::Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(hello["type"], "hello");
+            ids.push(hello["connection_id"].as_str().unwrap().to_owned());
+
+            assert_eq!(receive_ids(client).await, [1]);
+        }
+
+        assert_ne!(ids[0], ids[1]);
+
+        // Without the query, scores are sent right away
+        let mut plain = connect(addr, &["connect"]).await;
+        assert_eq!(receive_ids(&mut plain).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn heartbeat() {
+        let setup = Setup {
+            heartbeat_interval: Some(1),
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+        let mut client = connect(addr, &["connect"]).await;
+
+        assert!(next_heartbeat(&mut client).await["latest_id"].is_null());
+
+        let mut ids = Vec::new();
+
+        for id in [5, 3, 8] {
+            let mut scores = scores(&format!(r#"{{"scores": [{{"id": {id}}}]}}"#));
+            ctx.broadcast(&mut scores, None);
+
+            ids.push(
+                next_heartbeat(&mut client).await["latest_id"]
+                    .as_u64()
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(ids, [5, 5, 8]);
+    }
+
+    #[tokio::test]
+    async fn overlapping_polls() {
+        let (ctx, addr) = serve().await;
+        let mut client = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // Without a cursor, both batches are broadcast in their entirety
+        let mut first = scores(r#"{"scores": [{"id": 1}, {"id": 2}, {"id": 3}]}"#);
+        let batch = ctx.broadcast(&mut first, None);
+        assert_eq!(batch.len(), 3);
+
+        let mut second = scores(r#"{"scores": [{"id": 2}, {"id": 3}, {"id": 4}]}"#);
+        let batch = ctx.broadcast(&mut second, None);
+        assert_eq!(batch.iter().map(Score::id).collect::<Vec<_>>(), [4]);
+
+        assert_eq!(receive_ids(&mut client).await, [1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn connection_limit() {
+        let setup = Setup {
+            max_connections: Some(2),
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+
+        let mut first = connect(addr, &["connect"]).await;
+        let _second = connect(addr, &["connect"]).await;
+        let mut rejected = connect(addr, &[]).await;
+
+        let Message::Close(Some(frame)) = rejected.next().await.unwrap().unwrap() else {
+            panic!("expected close frame");
+        };
+
+        assert_eq!(frame.code, CloseCode::Again);
+        assert_eq!(frame.reason, "Too many connections");
+        assert_eq!(ctx.client_count(), 2);
+
+        first.close(None).await.unwrap();
+        while first.next().await.is_some() {}
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut third = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(ctx.client_count(), 2);
+
+        let mut scores = scores(r#"{"scores": [{"id": 1}]}"#);
+        ctx.broadcast(&mut scores, None);
+        assert_eq!(receive_ids(&mut third).await, [1]);
+    }
+
+    #[tokio::test]
+    async fn connection_rate() {
+        let setup = Setup {
+            connection_rate: Some(0.1),
+            connection_burst: Some(2),
+            ..Setup::default()
+        };
+
+        let (_, addr) = serve_with(&setup).await;
+        let mut accepted = 0;
+
+        for _ in 0..5 {
+            if tokio_tungstenite::connect_async(format!("ws://{addr}"))
+                .await
+                .is_ok()
+            {
+                accepted += 1;
+            }
+        }
+
+        assert_eq!(accepted, 2);
+    }
+
+    #[tokio::test]
+    async fn proxied_connection_rate() {
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error};
+
+        let setup = Setup {
+            connection_rate: Some(0.1),
+            connection_burst: Some(1),
+            real_ip_header: Some("X-Forwarded-For".into()),
+            trusted_proxies: vec!["127.0.0.1".parse().unwrap()],
+            ..Setup::default()
+        };
+
+        let (_, addr) = serve_with(&setup).await;
+
+        let connect = |forwarded_for: &'static str| async move {
+            let mut req = format!("ws://{addr}").into_client_request().unwrap();
+            let value = forwarded_for.parse().unwrap();
+            req.headers_mut().insert("x-forwarded-for", value);
+
+            tokio_tungstenite::connect_async(req).await
+        };
+
+        assert!(connect("10.0.0.1").await.is_ok());
+
+        let Err(Error::Http(res)) = connect("192.168.0.1, 10.0.0.1").await else {
+            panic!("expected http error");
+        };
+
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // Each real IP address has its own bucket
+        assert!(connect("10.0.0.2").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn auth_tokens() {
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error};
+
+        let setup = Setup {
+            auth_tokens: vec!["old".into(), "new".into()],
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+
+        let connect = |query: &'static str, authorization: Option<&'static str>| async move {
+            let mut req = format!("ws://{addr}{query}").into_client_request().unwrap();
+
+            if let Some(authorization) = authorization {
+                req.headers_mut()
+                    .insert(AUTHORIZATION, authorization.parse().unwrap());
+            }
+
+            tokio_tungstenite::connect_async(req)
+                .await
+                .map(|(client, _)| client)
+        };
+
+        let assert_unauthorized = |res: Result<Client, Error>| {
+            let Err(Error::Http(res)) = res else {
+                panic!("expected http error");
+            };
+
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        };
+
+        let mut header = connect("", Some("Bearer new")).await.unwrap();
+        let mut query = connect("/?batch&token=old", None).await.unwrap();
+
+        assert_unauthorized(connect("", None).await);
+        assert_unauthorized(connect("/?token=", None).await);
+        assert_unauthorized(connect("/?token=older", None).await);
+        assert_unauthorized(connect("", Some("Bearer ne")).await);
+        assert_unauthorized(connect("", Some("new")).await);
+
+        header.send(Message::from("connect")).await.unwrap();
+        query.send(Message::from("connect")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(ctx.client_count(), 2);
+
+        let mut scores = scores(r#"{"scores": [{"id": 1}]}"#);
+        ctx.broadcast(&mut scores, None);
+        assert_eq!(receive_ids(&mut header).await, [1]);
+
+        // SSE and NDJSON streams require a token as well
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let sse_addr = listener.local_addr().unwrap();
+        tokio::spawn(crate::sse::serve(listener, Arc::clone(&ctx)));
+
+        let status = |path: &'static str, authorization: Option<&'static str>| async move {
+            use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+            let mut stream = TcpStream::connect(sse_addr).await.unwrap();
+            let authorization = authorization
+                .map(|value| format!("Authorization: {value}\r\n"))
+                .unwrap_or_default();
+            let req = format!("GET {path} HTTP/1.1\r\nHost: {sse_addr}\r\n{authorization}\r\n");
+            stream.write_all(req.as_bytes()).await.unwrap();
+
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).await.unwrap();
+
+            line
+        };
+
+        assert!(status("/sse", Some("Bearer old")).await.contains("200"));
+        assert!(status("/stream.ndjson?token=new", None)
+            .await
+            .contains("200"));
+        assert!(status("/sse", None).await.contains("401"));
+        assert!(status("/sse?token=older", None).await.contains("401"));
+        assert!(status("/stream.ndjson", None).await.contains("401"));
+        assert!(status("/stream.ndjson", Some("Bearer ne"))
+            .await
+            .contains("401"));
+    }
+
+    #[tokio::test]
+    async fn handshake_timeout() {
+        use tokio::io::AsyncReadExt;
+
+        let setup = Setup {
+            handshake_timeout: 1,
+            initial_message_timeout: 1,
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+
+        // Never starts the websocket handshake
+        let mut idle = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(Duration::from_secs(3), idle.read(&mut buf));
+        assert_eq!(read.await.unwrap().unwrap(), 0);
+
+        // Completes the handshake but never sends an initial message
+        let mut silent = connect(addr, &[]).await;
+
+        let Some(Ok(Message::Text(text))) = silent.next().await else {
+            panic!("expected text message");
+        };
+
+        assert!(text.starts_with("Require initial message"), "{text}");
+
+        let Some(Ok(Message::Close(Some(frame)))) = silent.next().await else {
+            panic!("expected close frame");
+        };
+
+        assert_eq!(frame.code, CloseCode::Policy);
+        assert_eq!(ctx.client_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn invalid_initial_message() {
+        let (ctx, addr) = serve().await;
+
+        // Whether the error lists the accepted initial messages instead of
+        // reporting a JSON error
+        for (initial, code, lists_accepted) in [
+            (r#"{"ruleset": 3}"#, CloseCode::Policy, true),
+            ("disconnect", CloseCode::Policy, true),
+            (r#"{"ruleset": "taiko"}"#, CloseCode::Unsupported, false),
+            ("conect", CloseCode::Unsupported, true),
+        ] {
+            let mut client = connect(addr, &[initial]).await;
+
+            let Some(Ok(Message::Text(text))) = client.next().await else {
+                panic!("expected error message for {initial}");
+            };
+
+            if lists_accepted {
+                for accepted in [
+                    r#"`"connect"`"#,
+                    "score id",
+                    r#"`{"replay": <count>}`"#,
+                    r#"`{"resume_from": <score id>}`"#,
+                ] {
+                    assert!(text.contains(accepted), "{initial}: {text}");
+                }
+            }
+
+            let Some(Ok(Message::Close(Some(frame)))) = client.next().await else {
+                panic!("expected close frame for {initial}");
+            };
+
+            assert_eq!(frame.code, code, "{initial}");
+        }
+
+        assert_eq!(ctx.client_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn slow_client() {
+        let (ctx, addr) = serve().await;
+
+        let mut fast = connect(addr, &["connect"]).await;
+
+        // Never receives so its queue fills up
+        let slow = Peer {
+            id: ctx.new_connection_id(),
+            addr: SocketAddr::from(([127, 0, 0, 1], 1)),
+        };
+        let (slow_tx, mut slow_rx) = mpsc::channel(ctx.client_buffer());
+        ctx.register_client(Replay::All, slow, slow_tx);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let count = ctx.client_buffer() as u64 + 1;
+
+        for id in 1..=count {
+            let mut scores = scores(&format!(r#"{{"scores": [{{"id": {id}}}]}}"#));
+            ctx.broadcast(&mut scores, Some(id - 1));
+
+            // Give the fast client's connection task a chance to keep up
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        assert!(!ctx.clients.pin().contains_key(&slow.id));
+        assert_eq!(ctx.client_count(), 1);
+
+        let mut queued = 0;
+
+        while slow_rx.recv().await.is_some() {
+            queued += 1;
+        }
+
+        assert_eq!(queued, ctx.client_buffer());
+        assert_eq!(
+            receive_ids(&mut fast).await,
+            (1..=count).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn same_addr() {
+        let (ctx, _) = serve().await;
+
+        // E.g. a websocket and an SSE stream from behind the same proxy
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1));
+        let first = Peer {
+            id: ctx.new_connection_id(),
+            addr,
+        };
+        let second = Peer {
+            id: ctx.new_connection_id(),
+            addr,
+        };
+
+        let (_first_rx, _) = ctx.subscribe(Replay::All, first);
+        let (mut second_rx, _) = ctx.subscribe(Replay::All, second);
+        assert_eq!(ctx.client_count(), 2);
+
+        ctx.unsubscribe(first.id);
+        assert_eq!(ctx.client_count(), 1);
+
+        let mut scores = scores(r#"{"scores": [{"id": 1}]}"#);
+        ctx.broadcast(&mut scores, None);
+
+        let batch = second_rx.recv().await.unwrap();
+        assert_eq!(batch.iter().map(|score| score.id).collect::<Vec<_>>(), [1]);
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        let api = mock::serve(|_| r#"{"scores": [{"id": 1}]}"#.to_owned()).await;
+        let (ctx, addr) = serve().await;
+        let mut client = connect(addr, &["connect"]).await;
+
+        let poller = Poller {
+            ruleset: None,
+            interval: 60,
+            min_interval: None,
+            max_interval: None,
+            interval_factor: 1.5,
+            cursor_id: None,
+            cursor_file: None,
+            cursor_source: CursorSource::default(),
+            gap_threshold: None,
+            skip_repeated_batches: false,
+            backfill: None,
+        };
+
+        let osu = Arc::new(Osu::new(mock::config(api)).unwrap());
+        let polling = tokio::spawn(Context::fetch_scores(Arc::clone(&ctx), osu, poller));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        ctx.shutdown();
+
+        tokio::time::timeout(Duration::from_secs(1), polling)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let Message::Binary(score) = client.next().await.unwrap().unwrap() else {
+            panic!("expected score");
+        };
+
+        assert_eq!(score, br#"{"id": 1}"#[..]);
+
+        let Message::Close(Some(frame)) = client.next().await.unwrap().unwrap() else {
+            panic!("expected close frame");
+        };
+
+        assert_eq!(frame.code, CloseCode::Away);
+        assert_eq!(frame.reason, "Server shutting down");
+        assert!(client.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn warmup() {
+        let setup = Setup {
+            warmup_window: Some(5),
+            warmup_jitter_ms: 300,
+            ..Setup::default()
+        };
+
+        let (ctx, addr) = serve_with(&setup).await;
+        let start = Instant::now();
+
+        let connecting = (0..5).map(|_| async {
+            let client = connect(addr, &["connect"]).await;
+
+            (client, start.elapsed())
+        });
+
+        let (mut clients, elapsed): (Vec<_>, Vec<_>) = futures_util::future::join_all(connecting)
+            .await
+            .into_iter()
+            .unzip();
+
+        let earliest = *elapsed.iter().min().unwrap();
+        let latest = *elapsed.iter().max().unwrap();
+        assert!(
+            latest >= earliest + Duration::from_millis(30),
+            "{elapsed:?}"
+        );
+        assert!(latest < Duration::from_secs(2), "{elapsed:?}");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        ctx.shutdown();
+
+        for client in &mut clients {
+            let Message::Close(Some(frame)) = client.next().await.unwrap().unwrap() else {
+                panic!("expected close frame");
+            };
+
+            let secs: u64 = frame
+                .reason
+                .strip_prefix("Server shutting down; retry after ")
+                .and_then(|hint| hint.strip_suffix('s'))
+                .and_then(|secs| secs.parse().ok())
+                .unwrap_or_else(|| panic!("missing hint in {:?}", frame.reason));
+
+            assert!((1..=5).contains(&secs), "{secs}");
+        }
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+        let api = mock::serve(|_| {
+            POLLS.fetch_add(1, Relaxed);
+
+            r#"{"scores": []}"#.to_owned()
+        })
+        .await;
+
+        let path =
+            std::env::temp_dir().join(format!("scores-ws-config-{}.toml", std::process::id()));
+
+        let write_config = |interval: u64, allowed_ips: &str| {
+            let config = format!(
+                "[setup]\n\
+                interval = {interval}\n\
+                allowed_ips = [\"{allowed_ips}\"]\n\
+                [osu]\n\
+                client_id = 0\n\
+                client_secret = \"secret\"\n\
+                base_url = \"http://{api}\"\n\
+                max_retries = 0"
+            );
+
+            std::fs::write(&path, config).unwrap();
+        };
+
+        write_config(60, "10.0.0.0/8");
+        let config = Config::load(&path).unwrap();
+        let ctx = Arc::new(Context::new(&config.setup));
+        let peer = "127.0.0.1:1234".parse().unwrap();
+        assert!(!ctx.is_allowed(peer));
+
+        let poller = Poller {
+            ruleset: None,
+            interval: config.setup.interval,
+            min_interval: None,
+            max_interval: None,
+            interval_factor: 1.5,
+            cursor_id: None,
+            cursor_file: None,
+            cursor_source: CursorSource::default(),
+            gap_threshold: None,
+            skip_repeated_batches: false,
+            backfill: None,
+        };
+
+        let osu = Arc::new(Osu::new(config.osu.clone()).unwrap());
+        tokio::spawn(Context::fetch_scores(Arc::clone(&ctx), osu, poller));
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        assert_eq!(POLLS.load(Relaxed), 1);
+
+        write_config(1, "127.0.0.0/8");
+        let reloaded = Config::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(config.restart_required(&reloaded).is_empty());
+        ctx.reload(&reloaded);
+        assert!(ctx.is_allowed(peer));
+
+        // Polls every second instead of every minute
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert!(POLLS.load(Relaxed) >= 3, "{POLLS:?}");
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn subprotocols() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let (ctx, addr) = serve().await;
+
+        let connect = |protocols: &'static str| async move {
+            let mut req = format!("ws://{addr}").into_client_request().unwrap();
+            req.headers_mut()
+                .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocols));
+
+            let (mut client, res) = tokio_tungstenite::connect_async(req).await.unwrap();
+            client.send(Message::from("connect")).await.unwrap();
+
+            (client, res.headers()[SEC_WEBSOCKET_PROTOCOL].clone())
+        };
+
+        let (mut binary, binary_protocol) = connect("osu-scores-binary").await;
+        let (mut text, text_protocol) = connect("unknown, osu-scores-text").await;
+        let (mut batch, batch_protocol) = connect("osu-scores-batch, osu-scores-text").await;
+
+        assert_eq!(binary_protocol, "osu-scores-binary");
+        assert_eq!(text_protocol, "osu-scores-text");
+        assert_eq!(batch_protocol, "osu-scores-batch");
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores_ = scores(r#"{"scores": [{"id": 1}, {"id": 2}]}"#);
+        ctx.broadcast(&mut scores_, None);
+
+        assert_eq!(
+            receive_messages(&mut binary).await,
+            [&br#"{"id": 1}"#[..], br#"{"id": 2}"#]
+        );
+
+        for expected in [r#"{"id": 1}"#, r#"{"id": 2}"#] {
+            let Some(Ok(Message::Text(msg))) = text.next().await else {
+                panic!("expected text message");
+            };
+
+            assert_eq!(msg, expected);
+        }
+
+        let expected = encode_batch([&br#"{"id": 1}"#[..], br#"{"id": 2}"#]);
+        assert_eq!(receive_messages(&mut batch).await, [expected]);
+    }
+
+    #[test]
+    fn next_cursor_id() {
+        let next = |cursor_id, json| {
+            Context::next_cursor_id(cursor_id, &scores(json), CursorSource::default(), None)
+        };
+
+        assert_eq!(next(None, r#"{"scores": [{"id": 5}, {"id": 6}]}"#), Some(6));
+        assert_eq!(next(None, r#"{"scores": []}"#), None);
+        assert_eq!(
+            next(Some(6), r#"{"scores": [{"id": 8}, {"id": 7}]}"#),
+            Some(8)
+        );
+
+        // Regressing, stale, and empty pages keep the cursor
+        assert_eq!(
+            next(Some(8), r#"{"scores": [{"id": 3}, {"id": 4}]}"#),
+            Some(8)
+        );
+        assert_eq!(next(Some(8), r#"{"scores": [{"id": 8}]}"#), Some(8));
+        assert_eq!(next(Some(8), r#"{"scores": []}"#), Some(8));
+    }
+
+    #[test]
+    fn repeated_batch() {
+        const RESPONSE: &str = r#"{"scores": [{"id": 5}, {"id": 6}]}"#;
+
+        // Without deduplication, only the check prevents sending scores twice
+        let ctx = Context::new(&Setup {
+            dedup_capacity: 0,
+            ..Setup::default()
+        });
+
+        let mut rx = ctx.score_stream();
+        let mut prev_span = None;
+        let mut received = Vec::new();
+
+        for json in [RESPONSE, RESPONSE, r#"{"scores": []}"#, RESPONSE] {
+            let mut scores = scores(json);
+
+            if Context::is_repeated(&mut prev_span, &scores) {
+                scores.clear();
+            }
+
+            ctx.broadcast(&mut scores, None);
+
+            while let Ok(score) = rx.try_recv() {
+                received.push(score.id());
+            }
+        }
+
+        assert_eq!(received, [5, 6]);
+        assert_eq!(prev_span, Some((5, 6, 2)));
+
+        // A shifted window is not a repeat
+        assert!(!Context::is_repeated(
+            &mut prev_span,
+            &scores(r#"{"scores": [{"id": 6}, {"id": 7}]}"#)
+        ));
+        assert!(!Context::is_repeated(
+            &mut prev_span,
+            &scores(r#"{"scores": [{"id": 6}, {"id": 6}, {"id": 7}, {"id": 8}]}"#)
+        ));
+    }
+
+    #[test]
+    fn cursor_source() {
+        // The cursor string encodes `{"id":10}` to tell the strategies apart
+        let json = r#"{"scores": [{"id": 7}, {"id": 5}, {"id": 6}], "cursor": {"id": 10}, "cursor_string": "eyJpZCI6MTB9"}"#;
+        let cursor_string = ScoresDeserializer::new(json.into())
+            .cursor_string()
+            .unwrap();
+        let scores = scores(json);
+
+        let next = |cursor_id, source, cursor_string| {
+            Context::next_cursor_id(cursor_id, &scores, source, cursor_string)
+        };
+
+        assert_eq!(next(None, CursorSource::NewestScoreId, None), Some(7));
+        assert_eq!(next(None, CursorSource::OldestScoreId, None), Some(5));
+        assert_eq!(
+            next(None, CursorSource::CursorString, cursor_string.as_deref()),
+            Some(10)
+        );
+
+        // Falls back to the newest score id
+        assert_eq!(next(None, CursorSource::CursorString, None), Some(7));
+        assert_eq!(
+            next(None, CursorSource::CursorString, Some("invalid")),
+            Some(7)
+        );
+
+        // None of them moves the cursor backwards
+        assert_eq!(next(Some(6), CursorSource::OldestScoreId, None), Some(6));
+        assert_eq!(
+            next(
+                Some(11),
+                CursorSource::CursorString,
+                cursor_string.as_deref()
+            ),
+            Some(11)
+        );
+    }
+
+    #[tokio::test]
+    async fn cursor_messages() {
+        static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+        let api = mock::serve(|_| {
+            let scores = match POLLS.fetch_add(1, Relaxed) {
+                0 => r#"{"id": 5}, {"id": 6}"#,
+                1 => r#"{"id": 8}, {"id": 7}"#,
+                _ => "",
+            };
+
+            format!(r#"{{"scores": [{scores}]}}"#)
+        })
+        .await;
+
+        let (ctx, addr) = serve().await;
+        let mut client = connect_with_query(addr, "/?cursor", &["connect"]).await;
+        let mut other = connect(addr, &["connect"]).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let poller = Poller {
+            ruleset: None,
+            interval: 1,
+            min_interval: None,
+            max_interval: None,
+            interval_factor: 1.5,
+            cursor_id: None,
+            cursor_file: None,
+            cursor_source: CursorSource::default(),
+            gap_threshold: None,
+            skip_repeated_batches: false,
+            backfill: None,
+        };
+
+        let osu = Arc::new(Osu::new(mock::config(api)).unwrap());
+        tokio::spawn(Context::fetch_scores(Arc::clone(&ctx), osu, poller));
+
+        let mut received = Vec::new();
+
+        // Three polls, the last of which doesn't advance the cursor
+        while let Ok(Some(msg)) =
+            tokio::time::timeout(Duration::from_millis(1500), client.next()).await
+        {
+            match msg.unwrap() {
+                Message::Binary(bytes) => {
+                    let score: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+                    received.push(score["id"].to_string());
+                }
+                Message::Text(text) => received.push(text.to_string()),
+                msg => panic!("unexpected message {msg:?}"),
+            }
+        }
+
+        let cursor = |id| format!(r#"{{"type":"cursor","id":{id}}}"#);
+        assert_eq!(received, ["5", "6", &cursor(6), "7", "8", &cursor(8)]);
+        assert!(POLLS.load(Relaxed) >= 3);
+
+        // Clients need to opt in
+        assert_eq!(receive_ids(&mut other).await, [5, 6, 7, 8]);
+
+        // Clients connecting afterwards start off with the current cursor
+        let mut late = connect_with_query(addr, "/?cursor", &["100"]).await;
+        let msg = late.next().await.unwrap().unwrap();
+        assert_eq!(msg.into_text().unwrap().as_str(), cursor(8));
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn monotonic_cursor() {
+        static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+        let api = mock::serve(|_| {
+            let scores = match POLLS.fetch_add(1, Relaxed) {
+                0 => r#"{"id": 5}, {"id": 6}"#,
+                1 => r#"{"id": 8}, {"id": 7}"#,
+                // Regressing
+                2 => r#"{"id": 3}, {"id": 4}"#,
+                // Stale
+                3 => r#"{"id": 8}"#,
+                4 => r#"{"id": 9}"#,
+                _ => "",
+            };
+
+            format!(r#"{{"scores": [{scores}]}}"#)
+        })
+        .await;
+
+        let ctx = Context::new(&Setup::default());
+        let osu = Osu::new(mock::config(api)).unwrap();
+        let mut scores = Scores::new();
+        let mut cursor_id = None;
+        let mut broadcast = Vec::new();
+
+        for expected in [6, 8, 8, 8, 9, 9] {
+            let prev_cursor_id = cursor_id;
+            assert_ne!(
+                Context::poll(
+                    &osu,
+                    &mut scores,
+                    None,
+                    &mut cursor_id,
+                    CursorSource::default()
+                )
+                .await,
+                PollOutcome::Failed
+            );
+            assert_eq!(cursor_id, Some(expected));
+
+            let batch = ctx.broadcast(&mut scores, prev_cursor_id);
+            broadcast.extend(batch.iter().map(Score::id));
+            scores.clear();
+        }
+
+        assert_eq!(broadcast, [5, 6, 7, 8, 9]);
+    }
+    #[tokio::test]
+    async fn empty_poll() {
+        static POLLS: AtomicUsize = AtomicUsize::new(0);
+
+        let api = mock::serve(|_| match POLLS.fetch_add(1, Relaxed) {
+            0 => r#"{"scores": [{"id": 5}]}"#.to_owned(),
+            _ => r#"{"scores": []}"#.to_owned(),
+        })
+        .await;
+
+        let osu = Osu::new(mock::config(api)).unwrap();
+        let mut scores = Scores::new();
+        let mut cursor_id = None;
+
+        let outcome = Context::poll(
+            &osu,
+            &mut scores,
+            None,
+            &mut cursor_id,
+            CursorSource::default(),
+        )
+        .await;
+        assert_eq!(outcome, PollOutcome::Scores);
+        scores.clear();
+
+        let outcome = Context::poll(
+            &osu,
+            &mut scores,
+            None,
+            &mut cursor_id,
+            CursorSource::default(),
+        )
+        .await;
+        assert_eq!(outcome, PollOutcome::Empty);
+        assert!(scores.is_empty());
+        assert_eq!(cursor_id, Some(5));
     }
 }
@@ -0,0 +1,147 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context as _, ContextCompat, Result};
+use serde::Deserialize;
+
+/// What the cursor to continue polling from is derived from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CursorSource {
+    /// The newest id of the received scores.
+    #[default]
+    NewestScoreId,
+    /// The oldest id of the received scores.
+    OldestScoreId,
+    /// The id encoded in the response's `cursor_string`.
+    #[serde(rename = "use-cursor-string")]
+    CursorString,
+}
+
+/// Decodes the score id of a `cursor_string` of the osu!api which is the
+/// base64-encoded JSON of a cursor, e.g. `eyJpZCI6MTIzfQ` for `{"id":123}`.
+pub fn decode_cursor_string(cursor_string: &str) -> Result<u64> {
+    let json = decode_base64(cursor_string).context("Invalid base64")?;
+    let cursor: serde_json::Value = serde_json::from_slice(&json).context("Invalid JSON")?;
+
+    cursor["id"].as_u64().context("Missing id")
+}
+
+/// Accepts both the standard and the URL-safe alphabet, with or without
+/// padding.
+fn decode_base64(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut buf = 0_u32;
+    let mut bits = 0;
+
+    for byte in encoded.trim_end_matches('=').bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+
+        buf = (buf << 6 | u32::from(value)) & 0xFFFF;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buf >> bits).to_le_bytes()[0]);
+        }
+    }
+
+    Some(decoded)
+}
+
+/// File in which the cursor id is persisted so that restarts can resume where
+/// they left off.
+pub struct CursorFile {
+    path: PathBuf,
+}
+
+impl CursorFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns `None` if the file does not exist yet.
+    pub fn load(&self) -> Result<Option<u64>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context("Failed to read cursor file"),
+        };
+
+        content
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid cursor `{content}`"))
+    }
+
+    /// Writes into a temporary file first and then renames it so that the
+    /// file can't end up corrupted.
+    pub fn save(&self, cursor_id: u64) -> Result<()> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        fs::write(&tmp, itoa::Buffer::new().format(cursor_id))
+            .context("Failed to write temporary cursor file")?;
+
+        fs::rename(&tmp, &self.path).context("Failed to rename temporary cursor file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osu::{Scores, ScoresDeserializer, ScoresExt};
+
+    #[test]
+    fn round_trip() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 123}, {"id": 789}, {"id": 456}]}"#;
+
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let cursor_id = scores.latest_id().unwrap();
+
+        let path = std::env::temp_dir().join(format!("scores-ws-cursor-{}", std::process::id()));
+        let file = CursorFile::new(&path);
+
+        assert_eq!(file.load().unwrap(), None);
+        file.save(cursor_id).unwrap();
+        assert_eq!(file.load().unwrap(), Some(789));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn cursor_string() {
+        assert_eq!(decode_cursor_string("eyJpZCI6MTIzfQ").unwrap(), 123);
+        assert_eq!(
+            decode_cursor_string("eyJpZCI6NDk3MzQ5NjQ4NX0=").unwrap(),
+            4_973_496_485
+        );
+
+        // Both alphabets
+        assert_eq!(decode_cursor_string("eyJpZCI6MSwiYSI6In5+In0=").unwrap(), 1);
+        assert_eq!(decode_cursor_string("eyJpZCI6MSwiYSI6In5-In0").unwrap(), 1);
+
+        assert!(decode_cursor_string("eyJpZCI6Pz99").is_err());
+        assert!(decode_cursor_string("eyJpZCI6MTIzfQ!").is_err());
+        assert!(decode_cursor_string("eyJ1c2VyIjoxfQ").is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::{HashSet, VecDeque};
+
+/// The most recently broadcast score ids, up to a fixed capacity.
+///
+/// Once full, inserting an id evicts the oldest one.
+pub struct RecentIds {
+    capacity: usize,
+    order: VecDeque<u64>,
+    ids: HashSet<u64>,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            order: VecDeque::with_capacity(capacity),
+            ids: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Returns `false` if the id is among the recent ids already.
+    pub fn insert(&mut self, id: u64) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        if self.order.len() == self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(id);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evict_oldest() {
+        let mut recent = RecentIds::new(3);
+
+        assert!(recent.insert(1));
+        assert!(recent.insert(2));
+        assert!(!recent.insert(1));
+        assert!(recent.insert(3));
+        assert!(recent.insert(4));
+        assert_eq!(recent.order.len(), 3);
+
+        // 1 was evicted
+        assert!(recent.insert(1));
+        assert!(!recent.insert(3));
+        assert!(!recent.insert(4));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut recent = RecentIds::new(0);
+
+        assert!(recent.insert(1));
+        assert!(recent.insert(1));
+        assert!(recent.order.is_empty());
+    }
+}
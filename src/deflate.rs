@@ -0,0 +1,553 @@
+//! The `permessage-deflate` websocket extension of [RFC 7692].
+//!
+//! tungstenite doesn't implement the extension so [`Deflate`] wraps the
+//! connection's stream instead and rewrites frames in both directions: data
+//! frames written by tungstenite are compressed and compressed frames of the
+//! client are decompressed before tungstenite reads them.
+//!
+//! Both directions are negotiated without context takeover so that every
+//! message is compressed on its own.
+//!
+//! [RFC 7692]: https://www.rfc-editor.org/rfc/rfc7692
+
+use std::{
+    io::{self, Write},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use bytes::{Buf, BytesMut};
+use flate2::{write::DeflateEncoder, Compression, Decompress, FlushDecompress, Status};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Same as tungstenite's default `max_frame_size`.
+const MAX_FRAME_SIZE: usize = 16 << 20;
+
+/// Compressed bytes that may be pending before writes are held back.
+const MAX_PENDING_WRITE: usize = 1 << 20;
+
+/// Every compressed message ends with these bytes but they're stripped from
+/// the frame's payload.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xFF, 0xFF];
+
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const MASKED: u8 = 0x80;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+
+/// The `Sec-WebSocket-Extensions` response header for the first acceptable
+/// `permessage-deflate` offer, if any.
+///
+/// Offers asking for a smaller window than the default are declined since
+/// the compressor always uses the full window.
+pub(crate) fn negotiate<'a>(offers: impl IntoIterator<Item = &'a str>) -> Option<&'static str> {
+    offers.into_iter().find_map(accept_offer)
+}
+
+fn accept_offer(offer: &str) -> Option<&'static str> {
+    let mut params = offer.split(';').map(str::trim);
+
+    if !params.next()?.eq_ignore_ascii_case("permessage-deflate") {
+        return None;
+    }
+
+    let mut seen = [false; 4];
+    let mut server_max_window_bits = false;
+
+    for param in params {
+        let (key, value) = match param.split_once('=') {
+            Some((key, value)) => (key.trim(), Some(value.trim().trim_matches('"'))),
+            None => (param, None),
+        };
+
+        let idx = match (key, value) {
+            ("server_no_context_takeover", None) => 0,
+            ("client_no_context_takeover", None) => 1,
+            ("server_max_window_bits", Some("15")) => {
+                server_max_window_bits = true;
+
+                2
+            }
+            ("client_max_window_bits", None) => 3,
+            ("client_max_window_bits", Some(bits)) => match bits.parse::<u8>() {
+                Ok(8..=15) => 3,
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        // Offers must not contain a parameter twice
+        if std::mem::replace(&mut seen[idx], true) {
+            return None;
+        }
+    }
+
+    let res = if server_max_window_bits {
+        "permessage-deflate; server_no_context_takeover; client_no_context_takeover; \
+            server_max_window_bits=15"
+    } else {
+        "permessage-deflate; server_no_context_takeover; client_no_context_takeover"
+    };
+
+    Some(res)
+}
+
+/// The header of a websocket frame.
+struct Header {
+    first: u8,
+    mask: Option<[u8; 4]>,
+    /// Length of the header itself.
+    len: usize,
+    payload_len: usize,
+}
+
+impl Header {
+    /// Parses the header at the start of `buf` or `None` if it's incomplete.
+    fn parse(buf: &[u8]) -> io::Result<Option<Self>> {
+        let [first, second, ..] = *buf else {
+            return Ok(None);
+        };
+
+        let (payload_len, mut len) = match second & 0x7F {
+            0x7E => match buf.get(2..4) {
+                Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+                None => return Ok(None),
+            },
+            0x7F => match buf.get(2..10) {
+                Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+                None => return Ok(None),
+            },
+            payload_len => (u64::from(payload_len), 2),
+        };
+
+        let payload_len = usize::try_from(payload_len)
+            .ok()
+            .filter(|&payload_len| payload_len <= MAX_FRAME_SIZE)
+            .ok_or_else(|| invalid_data("frame too long"))?;
+
+        let mask = if second & MASKED == 0 {
+            None
+        } else {
+            let Some(mask) = buf.get(len..len + 4) else {
+                return Ok(None);
+            };
+
+            len += 4;
+
+            Some(mask.try_into().unwrap())
+        };
+
+        Ok(Some(Self {
+            first,
+            mask,
+            len,
+            payload_len,
+        }))
+    }
+
+    const fn opcode(&self) -> u8 {
+        self.first & 0x0F
+    }
+
+    const fn is_final(&self) -> bool {
+        self.first & FIN != 0
+    }
+
+    const fn is_compressed(&self) -> bool {
+        self.first & RSV1 != 0
+    }
+
+    const fn is_control(&self) -> bool {
+        self.first & 0x08 != 0
+    }
+
+    /// Writes a frame's header where a mask key of zeros leaves the payload
+    /// as is.
+    fn write(out: &mut BytesMut, first: u8, masked: bool, payload_len: usize) {
+        let mask_bit = if masked { MASKED } else { 0 };
+        out.extend_from_slice(&[first]);
+
+        if let Ok(payload_len @ ..126) = u8::try_from(payload_len) {
+            out.extend_from_slice(&[mask_bit | payload_len]);
+        } else if let Ok(payload_len) = u16::try_from(payload_len) {
+            out.extend_from_slice(&[mask_bit | 0x7E]);
+            out.extend_from_slice(&payload_len.to_be_bytes());
+        } else {
+            out.extend_from_slice(&[mask_bit | 0x7F]);
+            out.extend_from_slice(&(payload_len as u64).to_be_bytes());
+        }
+
+        if masked {
+            out.extend_from_slice(&[0; 4]);
+        }
+    }
+}
+
+/// Compresses and decompresses the messages of a connection.
+struct Codec {
+    encoder: DeflateEncoder<Vec<u8>>,
+    /// Opcode and payload of a compressed message whose final frame is still
+    /// pending.
+    fragments: Option<(u8, Vec<u8>)>,
+}
+
+impl Codec {
+    fn compress(&mut self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        self.encoder.write_all(payload)?;
+        self.encoder.flush()?;
+
+        let mut compressed = std::mem::take(self.encoder.get_mut());
+
+        // Without context takeover each message starts with a fresh state
+        self.encoder.reset(Vec::new())?;
+
+        if compressed.ends_with(&TRAILER) {
+            compressed.truncate(compressed.len() - TRAILER.len());
+        }
+
+        Ok(compressed)
+    }
+
+    // The decompressor never reads more than the payload's length
+    #[allow(clippy::cast_possible_truncation)]
+    fn decompress(mut payload: Vec<u8>) -> io::Result<Vec<u8>> {
+        payload.extend_from_slice(&TRAILER);
+
+        let mut decompress = Decompress::new(false);
+        let mut decompressed = Vec::with_capacity(payload.len() * 4);
+
+        loop {
+            let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+            let input = &payload[total_in as usize..];
+
+            let status = decompress
+                .decompress_vec(input, &mut decompressed, FlushDecompress::Sync)
+                .map_err(|_| invalid_data("invalid compressed message"))?;
+
+            if decompressed.len() > MAX_FRAME_SIZE {
+                return Err(invalid_data("decompressed message too long"));
+            }
+
+            let done = decompress.total_in() as usize == payload.len()
+                && decompressed.len() < decompressed.capacity();
+
+            if done || status == Status::StreamEnd {
+                return Ok(decompressed);
+            }
+
+            if (total_in, total_out) == (decompress.total_in(), decompress.total_out()) {
+                return Err(invalid_data("invalid compressed message"));
+            }
+
+            decompressed.reserve(decompressed.capacity());
+        }
+    }
+}
+
+/// Wraps a websocket's stream to apply the `permessage-deflate` extension
+/// once it's been negotiated.
+///
+/// Until [`Deflate::enable`] is called, bytes pass through unchanged so the
+/// handshake isn't affected.
+pub(crate) struct Deflate<S> {
+    inner: S,
+    codec: Option<Codec>,
+    /// Bytes read from the inner stream that don't form a full frame yet.
+    read_buf: BytesMut,
+    /// Decompressed frames for tungstenite to read.
+    decoded: BytesMut,
+    /// Bytes written by tungstenite that don't form a full frame yet.
+    write_buf: BytesMut,
+    /// Compressed frames for the inner stream.
+    encoded: BytesMut,
+}
+
+impl<S> Deflate<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Self {
+            inner,
+            codec: None,
+            read_buf: BytesMut::new(),
+            decoded: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            encoded: BytesMut::new(),
+        }
+    }
+
+    /// Starts compressing messages with the given level.
+    pub(crate) fn enable(&mut self, level: Compression) {
+        self.codec = Some(Codec {
+            encoder: DeflateEncoder::new(Vec::new(), level),
+            fragments: None,
+        });
+    }
+
+    /// Moves the next full frame of `read_buf` into `decoded`, decompressing
+    /// it if necessary.
+    ///
+    /// Returns `false` if `read_buf` doesn't contain a full frame.
+    fn decode_frame(&mut self) -> io::Result<bool> {
+        let Some(codec) = self.codec.as_mut() else {
+            return Ok(false);
+        };
+
+        let Some(header) = Header::parse(&self.read_buf)? else {
+            return Ok(false);
+        };
+
+        let frame_len = header.len + header.payload_len;
+
+        if self.read_buf.len() < frame_len {
+            return Ok(false);
+        }
+
+        // Control frames may be interleaved with fragments of a message
+        if header.is_control() || (!header.is_compressed() && codec.fragments.is_none()) {
+            self.decoded
+                .extend_from_slice(&self.read_buf.split_to(frame_len));
+
+            return Ok(true);
+        }
+
+        let frame = self.read_buf.split_to(frame_len);
+        let mut payload = frame[header.len..].to_vec();
+
+        if let Some(mask) = header.mask {
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+        }
+
+        match (header.opcode(), codec.fragments.as_mut()) {
+            (OP_TEXT | OP_BINARY, None) if header.is_compressed() => {
+                codec.fragments = Some((header.opcode(), payload));
+            }
+            (OP_CONTINUATION, Some((_, fragments))) if !header.is_compressed() => {
+                fragments.extend_from_slice(&payload);
+
+                if fragments.len() > MAX_FRAME_SIZE {
+                    return Err(invalid_data("compressed message too long"));
+                }
+            }
+            _ => return Err(invalid_data("unexpected compressed frame")),
+        }
+
+        if header.is_final() {
+            let (opcode, fragments) = codec.fragments.take().unwrap();
+            let decompressed = Codec::decompress(fragments)?;
+
+            Header::write(&mut self.decoded, FIN | opcode, true, decompressed.len());
+            self.decoded.extend_from_slice(&decompressed);
+        }
+
+        Ok(true)
+    }
+
+    /// Moves all full frames of `write_buf` into `encoded`, compressing data
+    /// frames.
+    fn encode_frames(&mut self) -> io::Result<()> {
+        let Some(codec) = self.codec.as_mut() else {
+            return Ok(());
+        };
+
+        while let Some(header) = Header::parse(&self.write_buf)? {
+            let frame_len = header.len + header.payload_len;
+
+            if self.write_buf.len() < frame_len {
+                break;
+            }
+
+            let frame = self.write_buf.split_to(frame_len);
+
+            // Fragmented messages are never written so they stay uncompressed
+            let compress = matches!(header.opcode(), OP_TEXT | OP_BINARY)
+                && header.is_final()
+                && !header.is_compressed();
+
+            if compress {
+                let compressed = codec.compress(&frame[header.len..])?;
+                Header::write(
+                    &mut self.encoded,
+                    header.first | RSV1,
+                    false,
+                    compressed.len(),
+                );
+                self.encoded.extend_from_slice(&compressed);
+            } else {
+                self.encoded.extend_from_slice(&frame);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Deflate<S> {
+    /// Writes `encoded` to the inner stream until it's empty or the stream
+    /// is pending.
+    fn poll_write_encoded(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while !self.encoded.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.encoded))?;
+
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            self.encoded.advance(n);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Deflate<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if this.codec.is_none() {
+            return Pin::new(&mut this.inner).poll_read(cx, buf);
+        }
+
+        loop {
+            if !this.decoded.is_empty() {
+                let n = buf.remaining().min(this.decoded.len());
+                buf.put_slice(&this.decoded.split_to(n));
+
+                return Poll::Ready(Ok(()));
+            }
+
+            if this.decode_frame()? {
+                continue;
+            }
+
+            let mut chunk = [0; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk_buf))?;
+
+            if chunk_buf.filled().is_empty() {
+                // Leftovers of an incomplete frame are for tungstenite to
+                // complain about
+                let rest = this.read_buf.split();
+                let n = buf.remaining().min(rest.len());
+                buf.put_slice(&rest[..n]);
+
+                return Poll::Ready(Ok(()));
+            }
+
+            this.read_buf.extend_from_slice(chunk_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Deflate<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.codec.is_none() {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        if let Poll::Ready(res) = this.poll_write_encoded(cx) {
+            res?;
+        } else if this.encoded.len() >= MAX_PENDING_WRITE {
+            return Poll::Pending;
+        }
+
+        this.write_buf.extend_from_slice(buf);
+        this.encode_frames()?;
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_encoded(cx))?;
+
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_encoded(cx))?;
+
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+fn invalid_data(err: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        let accepted = "permessage-deflate; server_no_context_takeover; client_no_context_takeover";
+
+        assert_eq!(super::negotiate(["permessage-deflate"]), Some(accepted));
+        assert_eq!(
+            super::negotiate(["permessage-deflate; client_max_window_bits"]),
+            Some(accepted)
+        );
+        assert_eq!(
+            super::negotiate(["x-webkit-deflate-frame", "permessage-deflate"]),
+            Some(accepted)
+        );
+        assert_eq!(
+            super::negotiate(["permessage-deflate; server_max_window_bits=\"15\""]),
+            Some(
+                "permessage-deflate; server_no_context_takeover; client_no_context_takeover; \
+                    server_max_window_bits=15"
+            )
+        );
+        assert_eq!(
+            super::negotiate([
+                "permessage-deflate; server_max_window_bits=10",
+                "permessage-deflate"
+            ]),
+            Some(accepted)
+        );
+        assert_eq!(
+            super::negotiate(["permessage-deflate; server_max_window_bits=10"]),
+            None
+        );
+        assert_eq!(
+            super::negotiate([
+                "permessage-deflate; client_no_context_takeover; client_no_context_takeover"
+            ]),
+            None
+        );
+        assert_eq!(super::negotiate(["permessage-deflate; unknown"]), None);
+        assert_eq!(super::negotiate([]), None);
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut codec = Codec {
+            encoder: DeflateEncoder::new(Vec::new(), Compression::default()),
+            fragments: None,
+        };
+
+        let score = br#"{"id": 1, "user": {"id": 2}}"#;
+
+        // Repetitive payloads compress to a fraction of their size
+        for payload in [score.to_vec(), score.repeat(1000), score.to_vec()] {
+            let compressed = codec.compress(&payload).unwrap();
+            assert!(!compressed.ends_with(&TRAILER));
+            assert_eq!(Codec::decompress(compressed).unwrap(), payload);
+        }
+    }
+}
@@ -1,10 +1,19 @@
 use std::fmt::{Display, Formatter, Result as FmtResult};
 
+use serde::Deserialize;
+use serde_json::Value;
 use tokio_tungstenite::tungstenite::Message;
 
+use crate::filter::Filter;
+
 pub enum Event {
     Connect,
     Resume { score_id: u64 },
+    Replay { count: usize },
+    ResumeFrom { score_id: u64 },
+    Subscribe(Box<Filter>),
+    Disconnect,
+    Stats,
 }
 
 impl Event {
@@ -14,6 +23,20 @@ impl Event {
             _ => None,
         })
     }
+
+    fn parse_json(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        let mut value: Value = serde_json::from_slice(bytes)?;
+
+        if let Some(count) = value.get_mut("replay").map(Value::take) {
+            return usize::deserialize(count).map(|count| Self::Replay { count });
+        }
+
+        if let Some(score_id) = value.get_mut("resume_from").map(Value::take) {
+            return u64::deserialize(score_id).map(|score_id| Self::ResumeFrom { score_id });
+        }
+
+        Filter::deserialize(value).map(|filter| Self::Subscribe(Box::new(filter)))
+    }
 }
 
 impl TryFrom<Message> for Event {
@@ -28,6 +51,12 @@ impl TryFrom<Message> for Event {
 
         if bytes == b"connect" {
             Ok(Self::Connect)
+        } else if bytes == b"disconnect" {
+            Ok(Self::Disconnect)
+        } else if bytes == b"stats" {
+            Ok(Self::Stats)
+        } else if bytes.first() == Some(&b'{') {
+            Self::parse_json(bytes).map_err(EventError::Json)
         } else if let Some(score_id) = Self::parse_score_id(bytes) {
             Ok(Self::Resume { score_id })
         } else {
@@ -40,15 +69,18 @@ impl TryFrom<Message> for Event {
 #[derive(Debug)]
 pub enum EventError {
     Bytes,
+    Json(serde_json::Error),
     Variant,
 }
 
 impl Display for EventError {
     fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
         match self {
-            EventError::Bytes => {
-                f.write_str("message must be either `\"connect\"` \r a score id to resume from")
-            }
+            EventError::Bytes => f.write_str(
+                "message must be either `\"connect\"`, a score id to resume from, \
+                `{\"replay\": <count>}`, or `{\"resume_from\": <score id>}`",
+            ),
+            EventError::Json(err) => write!(f, "invalid message: {err}"),
             EventError::Variant => f.write_str("message must contain text data"),
         }
     }
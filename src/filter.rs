@@ -0,0 +1,99 @@
+use std::collections::{BTreeSet, HashSet};
+
+use serde::{de::Error as _, Deserialize, Deserializer};
+
+use crate::osu::Score;
+
+/// Specifies which scores a client is subscribed to.
+///
+/// Clients that don't send a filter receive all scores.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(default, deserialize_with = "deserialize_ruleset")]
+    ruleset: Option<u8>,
+    /// Only match scores of these rulesets; empty if all rulesets match.
+    #[serde(default, deserialize_with = "deserialize_rulesets")]
+    rulesets: HashSet<u8>,
+    /// Only match scores that are known to have passed; scores without a
+    /// `"passed"` field are excluded.
+    #[serde(default)]
+    passed_only: bool,
+    /// Only match scores on these beatmaps; empty if all beatmaps match.
+    #[serde(default)]
+    beatmap_ids: HashSet<u64>,
+    /// Only match scores of these users; empty if all users match.
+    #[serde(default)]
+    user_ids: HashSet<u64>,
+    /// Only match scores with at least this much pp; scores without pp are
+    /// excluded.
+    min_pp: Option<f64>,
+    /// Only match scores placed at most this far down the global leaderboard;
+    /// scores without a global rank are excluded.
+    max_rank: Option<u64>,
+    /// Only send these top-level fields of each score.
+    fields: Option<BTreeSet<Box<str>>>,
+}
+
+impl Filter {
+    pub const fn fields(&self) -> Option<&BTreeSet<Box<str>>> {
+        self.fields.as_ref()
+    }
+
+    pub const fn user_ids(&self) -> &HashSet<u64> {
+        &self.user_ids
+    }
+
+    pub fn matches(&self, score: &Score) -> bool {
+        self.ruleset
+            .is_none_or(|ruleset| score.mode() == Some(ruleset))
+            && (self.rulesets.is_empty()
+                || score
+                    .mode()
+                    .is_some_and(|ruleset| self.rulesets.contains(&ruleset)))
+            && (!self.passed_only || score.passed() == Some(true))
+            && (self.beatmap_ids.is_empty()
+                || score
+                    .beatmap_id()
+                    .is_some_and(|beatmap_id| self.beatmap_ids.contains(&beatmap_id)))
+            && (self.user_ids.is_empty()
+                || score
+                    .user_id()
+                    .is_some_and(|user_id| self.user_ids.contains(&user_id)))
+            && self
+                .min_pp
+                .is_none_or(|min_pp| score.pp().is_some_and(|pp| pp >= min_pp))
+            && self
+                .max_rank
+                .is_none_or(|max_rank| score.rank_global().is_some_and(|rank| rank <= max_rank))
+    }
+}
+
+/// Rulesets are identified by 0 (osu!standard) through 3 (mania).
+const MAX_RULESET: u8 = 3;
+
+fn deserialize_ruleset<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u8>, D::Error> {
+    Option::<u8>::deserialize(d)?
+        .map(validate_ruleset::<D>)
+        .transpose()
+}
+
+fn deserialize_rulesets<'de, D: Deserializer<'de>>(d: D) -> Result<HashSet<u8>, D::Error> {
+    let rulesets = HashSet::<u8>::deserialize(d)?;
+
+    for &ruleset in &rulesets {
+        validate_ruleset::<D>(ruleset)?;
+    }
+
+    Ok(rulesets)
+}
+
+fn validate_ruleset<'de, D: Deserializer<'de>>(ruleset: u8) -> Result<u8, D::Error> {
+    if ruleset > MAX_RULESET {
+        return Err(D::Error::custom(format_args!(
+            "invalid ruleset {ruleset}, expected 0 through {MAX_RULESET}"
+        )));
+    }
+
+    Ok(ruleset)
+}
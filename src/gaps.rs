@@ -0,0 +1,114 @@
+use std::{ops::RangeInclusive, sync::atomic::Ordering::Relaxed};
+
+use crate::{metrics::METRICS, osu::Score};
+
+/// Keeps track of the highest broadcast score id to detect ranges of score
+/// ids that were skipped over.
+///
+/// Score ids are not strictly consecutive so only gaps larger than the
+/// threshold are reported.
+pub struct GapDetector {
+    threshold: u64,
+    last_id: Option<u64>,
+}
+
+impl GapDetector {
+    pub const fn new(threshold: u64) -> Self {
+        Self {
+            threshold,
+            last_id: None,
+        }
+    }
+
+    /// Returns the ranges of missing score ids.
+    ///
+    /// `scores` must be sorted by id in ascending order which is the case
+    /// when iterating over [`Scores`](crate::osu::Scores).
+    pub fn detect<'a>(
+        &mut self,
+        scores: impl IntoIterator<Item = &'a Score>,
+    ) -> Vec<RangeInclusive<u64>> {
+        let mut gaps = Vec::new();
+
+        for id in scores.into_iter().map(Score::id) {
+            let Some(last_id) = self.last_id else {
+                self.last_id = Some(id);
+
+                continue;
+            };
+
+            // Scores that were already seen
+            if id <= last_id {
+                continue;
+            }
+
+            if id - last_id - 1 > self.threshold {
+                gaps.push(last_id + 1..=id - 1);
+            }
+
+            self.last_id = Some(id);
+        }
+
+        gaps
+    }
+
+    /// Detects gaps, logs them, and updates the metrics.
+    pub fn report<'a>(&mut self, scores: impl IntoIterator<Item = &'a Score>) {
+        for gap in self.detect(scores) {
+            let missing = gap.end() - gap.start() + 1;
+            warn!(
+                start = gap.start(),
+                end = gap.end(),
+                missing,
+                "Gap in score ids"
+            );
+
+            METRICS.gaps.fetch_add(1, Relaxed);
+            METRICS.missing_ids.fetch_add(missing, Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osu::{Scores, ScoresDeserializer};
+
+    fn scores(ids: &[u64]) -> Scores {
+        let json = ids
+            .iter()
+            .map(|id| format!(r#"{{"id": {id}}}"#))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(format!(r#"{{"scores": [{json}]}}"#).into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        scores
+    }
+
+    #[test]
+    fn sequential() {
+        let mut detector = GapDetector::new(0);
+
+        assert!(detector.detect(&scores(&[1, 2, 3])).is_empty());
+        assert!(detector.detect(&scores(&[4, 5])).is_empty());
+
+        // Already seen ids are no gap
+        assert!(detector.detect(&scores(&[2, 5, 6])).is_empty());
+    }
+
+    #[test]
+    fn gapped() {
+        let mut detector = GapDetector::new(2);
+
+        assert_eq!(detector.detect(&scores(&[10, 12, 20])), [13..=19]);
+
+        // Gap between batches
+        assert_eq!(detector.detect(&scores(&[23, 30, 31])), [24..=29]);
+        assert_eq!(detector.detect(&scores(&[100])), [32..=99]);
+    }
+}
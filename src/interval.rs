@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use tokio::time::{Instant, Interval};
+
+/// Amount of scores of a poll at which polling is considered to fall behind.
+const FULL_PAGE: usize = 1000;
+
+/// Amount of scores of a poll below which polling is considered too frequent.
+const FEW_SCORES: usize = 100;
+
+/// Adapts the poll interval to the amount of scores each poll returns.
+///
+/// A full page of scores shortens the interval towards the floor while few or
+/// no scores lengthen it towards the ceiling.
+pub struct AdaptiveInterval {
+    current: Duration,
+    floor: Duration,
+    ceiling: Duration,
+    factor: f64,
+}
+
+impl AdaptiveInterval {
+    pub fn new(initial: Duration, floor: Duration, ceiling: Duration, factor: f64) -> Self {
+        let ceiling = ceiling.max(floor);
+
+        Self {
+            current: initial.clamp(floor, ceiling),
+            floor,
+            ceiling,
+            factor: factor.max(1.0),
+        }
+    }
+
+    pub const fn current(&self) -> Duration {
+        self.current
+    }
+
+    /// Adjusts the interval based on the amount of scores of the latest poll
+    /// and returns whether it changed.
+    pub fn adjust(&mut self, scores: usize) -> bool {
+        let prev = self.current;
+
+        if scores >= FULL_PAGE {
+            self.current = self.current.div_f64(self.factor).max(self.floor);
+        } else if scores < FEW_SCORES {
+            self.current = self.current.mul_f64(self.factor).min(self.ceiling);
+        }
+
+        self.current != prev
+    }
+}
+
+/// Schedules polls, adapting their period if bounds are specified.
+pub struct PollInterval {
+    adaptive: Option<AdaptiveInterval>,
+    ticks: Interval,
+}
+
+impl PollInterval {
+    /// All arguments are in seconds. The first tick completes at `start`.
+    pub fn new(
+        interval: u64,
+        min_interval: Option<u64>,
+        max_interval: Option<u64>,
+        factor: f64,
+        start: Instant,
+    ) -> Self {
+        let adaptive = (min_interval.is_some() || max_interval.is_some()).then(|| {
+            AdaptiveInterval::new(
+                Duration::from_secs(interval),
+                Duration::from_secs(min_interval.unwrap_or(interval)),
+                Duration::from_secs(max_interval.unwrap_or(interval)),
+                factor,
+            )
+        });
+
+        let period = adaptive
+            .as_ref()
+            .map_or(Duration::from_secs(interval), AdaptiveInterval::current);
+
+        Self {
+            adaptive,
+            ticks: tokio::time::interval_at(start, period),
+        }
+    }
+
+    pub fn period(&self) -> Duration {
+        self.ticks.period()
+    }
+
+    pub async fn tick(&mut self) {
+        self.ticks.tick().await;
+    }
+
+    /// Adapts the period to the amount of scores of the latest poll and
+    /// returns whether it changed.
+    pub fn adjust(&mut self, scores: usize) -> bool {
+        let Some(ref mut adaptive) = self.adaptive else {
+            return false;
+        };
+
+        if !adaptive.adjust(scores) {
+            return false;
+        }
+
+        let period = adaptive.current();
+        self.ticks = tokio::time::interval_at(Instant::now() + period, period);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adjust() {
+        let secs = Duration::from_secs;
+        let mut interval = AdaptiveInterval::new(secs(60), secs(15), secs(150), 2.0);
+
+        let expected = [
+            (1000, 30, true),
+            (2500, 15, true),
+            (1000, 15, false),
+            (500, 15, false),
+            (50, 30, true),
+            (0, 60, true),
+            (99, 120, true),
+            (3, 150, true),
+            (0, 150, false),
+            (100, 150, false),
+            (1000, 75, true),
+        ];
+
+        for (scores, current, changed) in expected {
+            assert_eq!(interval.adjust(scores), changed, "{scores} scores");
+            assert_eq!(interval.current(), secs(current), "{scores} scores");
+        }
+    }
+
+    #[test]
+    fn clamp_initial() {
+        let secs = Duration::from_secs;
+        let interval = AdaptiveInterval::new(secs(5), secs(15), secs(150), 2.0);
+        assert_eq!(interval.current(), secs(15));
+
+        let interval = AdaptiveInterval::new(secs(500), secs(15), secs(150), 2.0);
+        assert_eq!(interval.current(), secs(150));
+    }
+}
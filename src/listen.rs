@@ -0,0 +1,98 @@
+//! Binding the listeners that serve websockets, metrics, and Server-Sent
+//! Events.
+
+use eyre::{Context as _, Result};
+use tokio::net::TcpListener;
+
+use crate::config::Setup;
+
+/// All listeners specified by the [`Setup`].
+///
+/// Each of them is `None` if it's not specified or during a dry run.
+pub struct Listeners {
+    pub websocket: Option<TcpListener>,
+    /// Serve through [`metrics::serve`](crate::metrics::serve).
+    pub metrics: Option<TcpListener>,
+    /// Serve through [`sse::serve`](crate::sse::serve).
+    pub sse: Option<TcpListener>,
+}
+
+/// Binds the websocket listener as well as the metrics and SSE listeners if
+/// their port is specified.
+///
+/// Nothing is bound during a dry run.
+pub async fn bind_listeners(setup: &Setup) -> Result<Listeners> {
+    let websocket = if let Some(addr) = setup.websocket_addr() {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind websocket listener on {addr}"))?;
+        let addr = listener.local_addr().context("Missing local address")?;
+        let scheme = if setup.tls_cert.is_some() {
+            "wss"
+        } else {
+            "ws"
+        };
+        info!("Listening on {scheme}://{addr}...");
+
+        Some(listener)
+    } else {
+        info!("Dry run, polling without serving or sending any scores...");
+
+        None
+    };
+
+    let metrics = match setup.metrics_addr() {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to bind metrics listener on {addr}"))?;
+            info!("Serving metrics on {addr}/metrics...");
+
+            Some(listener)
+        }
+        None => None,
+    };
+
+    let sse = match setup.sse_addr() {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .await
+                .with_context(|| format!("Failed to bind SSE listener on {addr}"))?;
+            info!("Serving Server-Sent Events on {addr}/sse and NDJSON on {addr}/stream.ndjson...");
+
+            Some(listener)
+        }
+        None => None,
+    };
+
+    Ok(Listeners {
+        websocket,
+        metrics,
+        sse,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpStream;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn bind() {
+        let setup = Setup {
+            port: 0,
+            metrics_port: Some(0),
+            sse_port: Some(0),
+            ..Setup::default()
+        };
+
+        let listeners = bind_listeners(&setup).await.unwrap();
+
+        for listener in [listeners.websocket, listeners.metrics, listeners.sse] {
+            let listener = listener.unwrap();
+            let addr = listener.local_addr().unwrap();
+            assert!(TcpStream::connect(addr).await.is_ok());
+        }
+    }
+}
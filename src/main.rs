@@ -1,86 +1,237 @@
-//! Fetches all osu! scores from the api and sends them through websockets.
-//!
-//! ## Usage
-//!
-//! 1. Download the [latest release]
-//!
-//! 2. Input your client id and secret for the osu!api in `config.toml` and modify
-//!    the rest of the config to your liking.
-//!
-//! 3. Run `scores-ws`
-//!
-//! 4. Connect to `scores-ws` via websocket at `ws://127.0.0.1:{port of your config}`
-//!    and listen for scores. Check out the [examples] folder for some examples.
-//!
-//! ## How it works
-//!
-//! `scores-ws` uses your osu!api client id & secret to fetch from the [scores endpoint].
-//! Cursor management, score deduplication, rate limiting, and everything else is
-//! handled automatically!
-//!
-//! To connect to the websocket, `scores-ws` requires you to send an initial message.
-//! That message may either contain
-//! - the string `"connect"` in which case it'll start off sending you all scores it
-//!   has fetched so far (in its history).
-//! - a score id in which case it'll send you all scores from that score id onwards.
-//!
-//! At any point you can send the string `"disconnect"` to the websocket. This will
-//! make the websocket respond with a score id and close the connection. This score
-//! id may be used later on to resume from with a new websocket connection.
-//!
-//! [latest release]: https://github.com/MaxOhn/scores-ws/releases/latest
-//! [examples]: https://github.com/MaxOhn/scores-ws/tree/main/examples
-//! [scores endpoint]: https://osu.ppy.sh/docs/index.html#scores
-
 #![warn(clippy::pedantic, clippy::missing_const_for_fn)]
 
-#[macro_use]
-extern crate eyre;
-
 #[macro_use]
 extern crate tracing;
 
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    fs,
+    io::{self, Read},
+    net::SocketAddr,
+    pin::pin,
     sync::Arc,
+    time::Duration,
 };
 
 use eyre::{Context as _, Result};
-use osu::Osu;
-use tokio::net::TcpListener;
-use tracing_subscriber::EnvFilter;
-
-use crate::{config::Config, context::Context};
+use scores_ws::{
+    audit::AuditLog,
+    config::{Config, Setup},
+    context::Context,
+    listen, metrics, osu, poll, sse, tls,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
 
-mod config;
-mod context;
-mod event;
-mod osu;
+/// How long to wait for pollers and connections to finish once shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let Config { setup, osu } = Config::parse();
+    if std::env::args().nth(1).as_deref() == Some("validate") {
+        return validate(std::env::args().nth(2).as_deref());
+    }
 
-    let filter = EnvFilter::new(format!("scores_ws={},off", setup.log));
-    tracing_subscriber::fmt().with_env_filter(filter).init();
+    let mut config = Config::parse();
+    apply_overrides(&mut config.setup)?;
 
-    let osu = Osu::new(osu).context("Failed to create osu! client")?;
-    let ctx = Arc::new(Context::new(&setup));
+    init_tracing(&config.setup);
 
-    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, setup.port);
-    let listener = TcpListener::bind(addr).await.unwrap();
-    info!("Listening on {addr}...");
+    #[cfg(unix)]
+    let reload_config = config.clone();
+    let Config { setup, osu } = config;
 
-    tokio::spawn(Context::fetch_scores(
-        Arc::clone(&ctx),
-        osu,
-        setup.interval,
-        setup.resume_score_id,
-    ));
+    let ctx = match (&setup.tls_cert, &setup.tls_key) {
+        (Some(cert), Some(key)) => {
+            let acceptor = tls::acceptor(cert, key).context("Failed to set up TLS")?;
 
-    while let Ok(conn) = listener.accept().await {
-        tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+            Context::new(&setup).with_tls(acceptor)
+        }
+        (None, None) => Context::new(&setup),
+        _ => eyre::bail!("`tls_cert` and `tls_key` must be specified together"),
+    };
+
+    let ctx = match AuditLog::from_setup(&setup).context("Failed to open audit log")? {
+        Some(audit) => ctx.with_audit_log(audit),
+        None => ctx,
+    };
+
+    let ctx = Arc::new(ctx);
+    let listeners = listen::bind_listeners(&setup).await?;
+
+    if let Some(listener) = listeners.metrics {
+        tokio::spawn(metrics::serve(listener, Arc::clone(&ctx)));
     }
 
+    if let Some(listener) = listeners.sse {
+        tokio::spawn(sse::serve(listener, Arc::clone(&ctx)));
+    }
+
+    let pollers = poll::spawn(&ctx, &setup, osu)?;
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_hangup(Arc::clone(&ctx), reload_config));
+
+    let mut connections = JoinSet::new();
+    let mut shutdown = pin!(shutdown_signal());
+
+    loop {
+        tokio::select! {
+            res = accept(listeners.websocket.as_ref()) => match res {
+                Ok(conn) => {
+                    connections.spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+                }
+                Err(err) => {
+                    error!(?err, "Failed to accept connection");
+
+                    break;
+                }
+            },
+            // Clean up finished connection tasks
+            Some(_) = connections.join_next() => {}
+            () = &mut shutdown => break,
+        }
+    }
+
+    info!("Shutting down...");
+    ctx.shutdown();
+
+    let drain = async {
+        pollers.join_all().await;
+        connections.join_all().await;
+    };
+
+    if tokio::time::timeout(SHUTDOWN_TIMEOUT, drain).await.is_err() {
+        warn!("Timed out while shutting down");
+    }
+
+    ctx.close_audit_log();
+
     Ok(())
 }
+
+/// Accepts the next connection of `listener`, or never completes without one.
+async fn accept(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Reports the score ids of the captured osu!api response at `path`, or of
+/// stdin if it's `None` or `-`, without starting the server.
+fn validate(path: Option<&str>) -> Result<()> {
+    let response = match path {
+        None | Some("-") => {
+            let mut response = Vec::new();
+            io::stdin()
+                .read_to_end(&mut response)
+                .context("Failed to read stdin")?;
+
+            response
+        }
+        Some(path) => fs::read(path).with_context(|| format!("Failed to read `{path}`"))?,
+    };
+
+    osu::validate(response.into(), &mut io::stdout().lock())
+}
+
+/// Environment variables and command line arguments take precedence over the
+/// config file.
+fn apply_overrides(setup: &mut Setup) -> Result<()> {
+    setup
+        .apply_env(std::env::vars())
+        .context("Invalid environment variables")?;
+    setup
+        .apply_args(std::env::args().skip(1))
+        .context("Invalid command line arguments")
+}
+
+/// Reloads the config file upon `SIGHUP`, applying the settings that can
+/// change while running and warning about those that require a restart.
+///
+/// `running` is the config that `scores-ws` was started with.
+#[cfg(unix)]
+async fn reload_on_hangup(ctx: Arc<Context>, running: Config) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(signal) => signal,
+        Err(err) => return error!(?err, "Failed to listen for SIGHUP"),
+    };
+
+    while hangup.recv().await.is_some() {
+        let config = Config::load(Config::PATH).and_then(|mut config| {
+            apply_overrides(&mut config.setup)?;
+
+            Ok(config)
+        });
+
+        let config = match config {
+            Ok(config) => config,
+            Err(err) => {
+                error!(?err, "Failed to reload config, keeping the current one");
+
+                continue;
+            }
+        };
+
+        for key in running.restart_required(&config) {
+            warn!(key, "Changed setting only takes effect after a restart");
+        }
+
+        ctx.reload(&config);
+        info!("Reloaded config");
+    }
+}
+
+/// `RUST_LOG` takes precedence over the configured log level.
+fn init_tracing(setup: &Setup) {
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(format!("scores_ws={},off", setup.log)));
+
+    let fmt = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+
+    match setup.log_format.as_ref() {
+        "pretty" => fmt.pretty().init(),
+        "json" => fmt.json().init(),
+        _ => fmt.init(),
+    }
+}
+
+/// Resolves on `SIGINT` or `SIGTERM`.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!(?err, "Failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                error!(?err, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
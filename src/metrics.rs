@@ -0,0 +1,515 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::{
+    header::CONTENT_TYPE, server::conn::http1, service::service_fn, Method, Request, Response,
+    StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+
+use crate::context::Context;
+
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+const APPLICATION_JSON: &str = "application/json";
+
+/// Upper bounds in seconds of the poll duration histogram's buckets.
+const POLL_BUCKETS: [f64; 9] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Global metrics, exposed in Prometheus' text format through [`serve`].
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    /// Scores that have been broadcast to all clients.
+    pub scores_broadcast: AtomicU64,
+    /// Scores that have been sent to individual clients.
+    pub scores_forwarded: AtomicU64,
+    /// Scores that have not been sent to individual clients because they
+    /// didn't match their filter.
+    pub scores_filtered: AtomicU64,
+    /// Scores that have been deserialized from the osu!api.
+    pub scores_parsed: AtomicU64,
+    /// Bytes of osu!api responses that have been deserialized.
+    pub bytes_parsed: AtomicU64,
+    /// Scores that have been skipped because they failed to deserialize.
+    pub parse_failures: AtomicU64,
+    /// Score objects that have been followed by neither a comma nor a closing
+    /// bracket.
+    pub missing_separators: AtomicU64,
+    /// Successful polls of the osu!api that contained no scores.
+    pub empty_polls: AtomicU64,
+    /// Detected gaps in the score ids.
+    pub gaps: AtomicU64,
+    /// Score ids within detected gaps.
+    pub missing_ids: AtomicU64,
+    polls: Histogram,
+    api_errors: Mutex<BTreeMap<u16, u64>>,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            scores_broadcast: AtomicU64::new(0),
+            scores_forwarded: AtomicU64::new(0),
+            scores_filtered: AtomicU64::new(0),
+            scores_parsed: AtomicU64::new(0),
+            bytes_parsed: AtomicU64::new(0),
+            parse_failures: AtomicU64::new(0),
+            missing_separators: AtomicU64::new(0),
+            empty_polls: AtomicU64::new(0),
+            gaps: AtomicU64::new(0),
+            missing_ids: AtomicU64::new(0),
+            polls: Histogram::new(),
+            api_errors: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe_poll(&self, duration: Duration) {
+        self.polls.observe(duration);
+    }
+
+    /// Counts a response of the osu!api with an unsuccessful status code.
+    pub fn api_error(&self, status: StatusCode) {
+        *self
+            .api_errors
+            .lock()
+            .unwrap()
+            .entry(status.as_u16())
+            .or_default() += 1;
+    }
+
+    fn render(&self, clients: usize) -> String {
+        let mut out = String::new();
+
+        counter(
+            &mut out,
+            "scores_ws_scores_broadcast_total",
+            "Scores broadcast to all clients",
+            &self.scores_broadcast,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_scores_forwarded_total",
+            "Scores sent to individual clients",
+            &self.scores_forwarded,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_scores_filtered_total",
+            "Scores not sent to individual clients due to their filter",
+            &self.scores_filtered,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_scores_parsed_total",
+            "Scores deserialized from the osu!api",
+            &self.scores_parsed,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_bytes_parsed_total",
+            "Bytes of osu!api responses deserialized",
+            &self.bytes_parsed,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_parse_failures_total",
+            "Scores skipped because they failed to deserialize",
+            &self.parse_failures,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_missing_separators_total",
+            "Score objects followed by neither a comma nor a closing bracket",
+            &self.missing_separators,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_empty_polls_total",
+            "Successful osu!api polls without any scores",
+            &self.empty_polls,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_gaps_total",
+            "Detected gaps in the score ids",
+            &self.gaps,
+        );
+
+        counter(
+            &mut out,
+            "scores_ws_missing_ids_total",
+            "Score ids within detected gaps",
+            &self.missing_ids,
+        );
+
+        let _ = writeln!(out, "# HELP scores_ws_clients Connected clients");
+        let _ = writeln!(out, "# TYPE scores_ws_clients gauge");
+        let _ = writeln!(out, "scores_ws_clients {clients}");
+
+        self.polls.render(
+            &mut out,
+            "scores_ws_poll_duration_seconds",
+            "Duration of polling the osu!api",
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP scores_ws_api_errors_total Unsuccessful osu!api responses by status code"
+        );
+        let _ = writeln!(out, "# TYPE scores_ws_api_errors_total counter");
+
+        for (status, count) in self.api_errors.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "scores_ws_api_errors_total{{status=\"{status}\"}} {count}"
+            );
+        }
+
+        out
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} counter");
+    let _ = writeln!(out, "{name} {}", value.load(Relaxed));
+}
+
+struct Histogram {
+    /// Cumulative counts for each of [`POLL_BUCKETS`].
+    buckets: [AtomicU64; POLL_BUCKETS.len()],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            buckets: [ZERO; POLL_BUCKETS.len()],
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+
+        for (bucket, bound) in self.buckets.iter().zip(POLL_BUCKETS) {
+            if secs <= bound {
+                bucket.fetch_add(1, Relaxed);
+            }
+        }
+
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        self.sum_micros.fetch_add(micros, Relaxed);
+        self.count.fetch_add(1, Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+
+        for (bucket, bound) in self.buckets.iter().zip(POLL_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{le=\"{bound}\"}} {}",
+                bucket.load(Relaxed)
+            );
+        }
+
+        let count = self.count.load(Relaxed);
+        let sum = Duration::from_micros(self.sum_micros.load(Relaxed)).as_secs_f64();
+
+        let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {count}");
+    }
+}
+
+/// Serves the metrics on `GET /metrics`, the health on `GET /healthz`, and
+/// stats on `GET /stats`.
+pub async fn serve(listener: TcpListener, ctx: Arc<Context>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        let ctx = Arc::clone(&ctx);
+
+        let service = service_fn(move |req: Request<_>| {
+            let res = handle_request(&req, &ctx);
+
+            async move { Ok::<_, Infallible>(res) }
+        });
+
+        tokio::spawn(async move {
+            let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+
+            if let Err(err) = conn.await {
+                debug!(?err, "Failed to serve metrics connection");
+            }
+        });
+    }
+}
+
+fn handle_request<B>(req: &Request<B>, ctx: &Context) -> Response<Full<Bytes>> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => {}
+        (&Method::GET, "/healthz") => return health(ctx),
+        (&Method::GET, "/stats") => return stats(ctx),
+        _ => {
+            let mut res = Response::new(Full::default());
+            *res.status_mut() = StatusCode::NOT_FOUND;
+
+            return res;
+        }
+    }
+
+    let body = METRICS.render(ctx.client_count());
+
+    let mut res = Response::new(Full::from(body));
+    res.headers_mut()
+        .insert(CONTENT_TYPE, TEXT_FORMAT.parse().unwrap());
+
+    res
+}
+
+/// Responds with 200 if the last successful poll is recent enough and with
+/// 503 otherwise.
+fn health(ctx: &Context) -> Response<Full<Bytes>> {
+    let (status, code) = if ctx.is_healthy() {
+        ("ready", StatusCode::OK)
+    } else {
+        ("unhealthy", StatusCode::SERVICE_UNAVAILABLE)
+    };
+
+    let body = match ctx.since_last_poll() {
+        Some(elapsed) => format!(
+            r#"{{"status":"{status}","last_poll_secs":{}}}"#,
+            elapsed.as_secs()
+        ),
+        None => format!(r#"{{"status":"{status}","last_poll_secs":null}}"#),
+    };
+
+    let mut res = Response::new(Full::from(body));
+    *res.status_mut() = code;
+    res.headers_mut()
+        .insert(CONTENT_TYPE, APPLICATION_JSON.parse().unwrap());
+
+    res
+}
+
+/// Responds with a summary of the metrics as JSON.
+fn stats(ctx: &Context) -> Response<Full<Bytes>> {
+    let latest_id = ctx
+        .latest_id()
+        .map_or_else(|| "null".to_owned(), |id| id.to_string());
+
+    let body = format!(
+        r#"{{"latest_id":{latest_id},"total_broadcast":{},"connected_clients":{},"uptime_secs":{}}}"#,
+        METRICS.scores_broadcast.load(Relaxed),
+        ctx.client_count(),
+        ctx.uptime().as_secs(),
+    );
+
+    let mut res = Response::new(Full::from(body));
+    res.headers_mut()
+        .insert(CONTENT_TYPE, APPLICATION_JSON.parse().unwrap());
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{
+        config::Setup,
+        osu::{Scores, ScoresDeserializer},
+    };
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let req = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(req.as_bytes()).await.unwrap();
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).await.unwrap();
+
+        res
+    }
+
+    #[tokio::test]
+    async fn health() {
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        let res = get(addr, "/healthz").await;
+        assert!(res.starts_with("HTTP/1.1 503"), "{res}");
+        assert!(
+            res.ends_with(r#"{"status":"unhealthy","last_poll_secs":null}"#),
+            "{res}"
+        );
+
+        ctx.record_poll(Instant::now());
+
+        let res = get(addr, "/healthz").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(
+            res.ends_with(r#"{"status":"ready","last_poll_secs":0}"#),
+            "{res}"
+        );
+
+        let timeout = Duration::from_secs(Setup::default().health_timeout + 1);
+        ctx.record_poll(Instant::now().checked_sub(timeout).unwrap());
+
+        let res = get(addr, "/healthz").await;
+        assert!(res.starts_with("HTTP/1.1 503"), "{res}");
+        assert!(res.contains(r#""status":"unhealthy""#), "{res}");
+        assert!(!res.contains("null"), "{res}");
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        let json = |res: &str| -> serde_json::Value {
+            assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+            assert!(res.contains("content-type: application/json"), "{res}");
+            let (_, body) = res.split_once("\r\n\r\n").unwrap();
+
+            serde_json::from_str(body).unwrap()
+        };
+
+        let stats = json(&get(addr, "/stats").await);
+        assert!(stats["latest_id"].is_null());
+        assert_eq!(stats["connected_clients"], 0);
+        assert!(stats["uptime_secs"].is_u64());
+
+        let peer = crate::observer::Peer {
+            id: ctx.new_connection_id(),
+            addr: "127.0.0.1:1".parse().unwrap(),
+        };
+        let _rx = ctx.subscribe(crate::context::Replay::All, peer);
+
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(br#"{"scores": [{"id": 7}, {"id": 9}]}"#[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        ctx.broadcast(&mut scores, None);
+
+        let stats = json(&get(addr, "/stats").await);
+        assert_eq!(stats["latest_id"], 9);
+        assert_eq!(stats["connected_clients"], 1);
+
+        // Other tests broadcast through the same global metrics
+        assert!(stats["total_broadcast"].as_u64().unwrap() >= 2);
+    }
+
+    #[tokio::test]
+    async fn scrape() {
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(br#"{"scores": [{"id": 1}, {"id": 2}]}"#[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        METRICS.observe_poll(Duration::from_millis(300));
+        METRICS.api_error(StatusCode::TOO_MANY_REQUESTS);
+        ctx.broadcast(&mut scores, None);
+
+        let res = get(addr, "/metrics").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+
+        for name in [
+            "scores_ws_scores_broadcast_total",
+            "scores_ws_scores_forwarded_total",
+            "scores_ws_scores_parsed_total",
+            "scores_ws_bytes_parsed_total",
+            "scores_ws_parse_failures_total",
+            "scores_ws_missing_separators_total",
+            "scores_ws_empty_polls_total",
+            "scores_ws_clients 0",
+            "scores_ws_poll_duration_seconds_bucket{le=\"0.5\"}",
+            "scores_ws_poll_duration_seconds_count",
+            "scores_ws_api_errors_total{status=\"429\"}",
+        ] {
+            assert!(res.contains(name), "missing `{name}`");
+        }
+    }
+    #[tokio::test]
+    async fn separate_listener() {
+        let setup = Setup {
+            port: 0,
+            metrics_host: Some(std::net::Ipv4Addr::LOCALHOST.into()),
+            metrics_port: Some(0),
+            ..Setup::default()
+        };
+
+        let ctx = Arc::new(Context::new(&setup));
+
+        let listener = TcpListener::bind(setup.listen_addr(setup.port))
+            .await
+            .unwrap();
+        let score_addr = listener.local_addr().unwrap();
+        let score_ctx = Arc::clone(&ctx);
+
+        tokio::spawn(async move {
+            while let Ok(conn) = listener.accept().await {
+                tokio::spawn(Context::handle_connection(Arc::clone(&score_ctx), conn));
+            }
+        });
+
+        let listener = TcpListener::bind(setup.metrics_addr().unwrap())
+            .await
+            .unwrap();
+        let metrics_addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        assert_ne!(score_addr.port(), metrics_addr.port());
+
+        let res = get(metrics_addr, "/healthz").await;
+        assert!(res.contains(r#""status":"unhealthy""#), "{res}");
+
+        let res = tokio::time::timeout(Duration::from_secs(5), get(score_addr, "/healthz"))
+            .await
+            .unwrap();
+        assert!(!res.contains("status"), "{res}");
+        assert!(!res.starts_with("HTTP/1.1 200"), "{res}");
+
+        ctx.shutdown();
+    }
+}
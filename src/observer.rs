@@ -0,0 +1,51 @@
+//! Notifications about the lifecycle of websocket connections, e.g. to feed
+//! them into an audit system.
+
+use std::net::SocketAddr;
+
+use crate::filter::Filter;
+
+/// A websocket connection.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Peer {
+    /// Same as the id of the connection's tracing span and of its `hello`
+    /// message.
+    pub id: u64,
+    pub addr: SocketAddr,
+}
+
+/// Why a websocket connection ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DisconnectReason {
+    /// The client closed the connection or sent `"disconnect"`.
+    Client,
+    /// The client didn't send a valid initial message in time.
+    InitialMessage,
+    /// The client didn't respond to a ping in time.
+    PingTimeout,
+    /// The client lagged behind too far.
+    Lagged,
+    /// A message could not be sent to the client.
+    SendFailed,
+    /// The server is shutting down.
+    Shutdown,
+}
+
+/// Gets notified about websocket connections once registered through
+/// [`Context::with_observer`].
+///
+/// The methods are called within the connection's task so they should return
+/// quickly.
+///
+/// [`Context::with_observer`]: crate::context::Context::with_observer
+pub trait ConnectionObserver: Send + Sync {
+    /// The peer completed the websocket handshake.
+    fn connected(&self, peer: Peer);
+
+    /// The peer replaced its filter.
+    fn subscribed(&self, peer: Peer, filter: &Filter);
+
+    /// The connection of a previously connected peer ended.
+    fn disconnected(&self, peer: Peer, reason: DisconnectReason);
+}
@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+/// Subprotocol for the default encoding of one binary message per score.
+pub const BINARY_SUBPROTOCOL: &str = "osu-scores-binary";
+/// Subprotocol for one text message per score.
+pub const TEXT_SUBPROTOCOL: &str = "osu-scores-text";
+/// Subprotocol for the same length-prefixed batches as `?batch`.
+pub const BATCH_SUBPROTOCOL: &str = "osu-scores-batch";
+
+/// Options specified through the query of the websocket url, e.g.
+/// `ws://127.0.0.1:7277?batch`.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Copy, Clone, Default)]
+pub struct ConnectOptions {
+    /// Whether scores should be sent in length-prefixed batches rather than
+    /// one message per score.
+    pub batch: bool,
+    /// Whether scores should be sent as text rather than binary messages.
+    pub text: bool,
+    /// Whether the connection id should be sent right after the handshake.
+    pub hello: bool,
+    /// Whether the pollers' cursor should be sent whenever it advances.
+    pub cursor: bool,
+    /// If specified, only the most recent score of each user within this
+    /// window is sent.
+    pub coalesce: Option<Duration>,
+}
+
+impl ConnectOptions {
+    pub fn from_query(query: &str) -> Self {
+        let mut options = Self::default();
+
+        for pair in query.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+            match key {
+                "batch" => options.batch = Self::parse_flag(value),
+                "hello" => options.hello = Self::parse_flag(value),
+                "cursor" => options.cursor = Self::parse_flag(value),
+                "coalesce" => {
+                    options.coalesce = value
+                        .parse()
+                        .ok()
+                        .filter(|&millis| millis > 0)
+                        .map(Duration::from_millis);
+                }
+                _ => {}
+            }
+        }
+
+        options
+    }
+
+    /// Applies the first supported subprotocol among the comma-separated ones
+    /// requested by the client and returns it, if any.
+    pub fn negotiate<'a>(
+        &mut self,
+        protocols: impl IntoIterator<Item = &'a str>,
+    ) -> Option<&'static str> {
+        let requested = protocols
+            .into_iter()
+            .flat_map(|protocols| protocols.split(','))
+            .map(str::trim);
+
+        for protocol in requested {
+            match protocol {
+                BINARY_SUBPROTOCOL => return Some(BINARY_SUBPROTOCOL),
+                TEXT_SUBPROTOCOL => {
+                    self.text = true;
+
+                    return Some(TEXT_SUBPROTOCOL);
+                }
+                BATCH_SUBPROTOCOL => {
+                    self.batch = true;
+
+                    return Some(BATCH_SUBPROTOCOL);
+                }
+                _ => {}
+            }
+        }
+
+        None
+    }
+
+    fn parse_flag(value: &str) -> bool {
+        matches!(value, "" | "1" | "true")
+    }
+}
@@ -1,53 +1,193 @@
-use std::sync::atomic::{AtomicPtr, Ordering::SeqCst};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering::SeqCst},
+        Arc, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use bytes::Bytes;
 use eyre::{Context, ContextCompat, Result};
+use tokio::sync::Mutex;
+
+/// Tokens are refreshed once they expire within this many seconds.
+const EXPIRY_MARGIN_SECS: u64 = 60;
 
 pub struct Authorization {
-    // We use an atomic pointer to allow mutation through immutable reference.
-    // Since atomic pointers only support thin pointers, we need to keep the
-    // str boxed which means double indirection but that's fine.
-    ptr: AtomicPtr<Box<str>>,
+    /// The `Authorization` header value. Readers clone the `Arc` so that a
+    /// refresh never frees a token that's still in use.
+    token: RwLock<Arc<str>>,
+    /// Unix timestamp in seconds at which the token expires.
+    expires_at: AtomicU64,
+    /// Incremented whenever the token is refreshed.
+    generation: AtomicU64,
+    /// Ensures only one refresh happens at a time.
+    refresh_lock: Mutex<()>,
 }
 
 impl Authorization {
-    pub fn as_str(&self) -> &str {
-        let ptr = self.ptr.load(SeqCst);
+    pub fn as_str(&self) -> Arc<str> {
+        Arc::clone(&self.token.read().unwrap())
+    }
 
-        unsafe { (*ptr).as_ref() }
+    pub fn generation(&self) -> u64 {
+        self.generation.load(SeqCst)
     }
 
-    pub fn parse(&self, bytes: &[u8]) -> Result<()> {
+    /// Whether the token is missing or expires within the next minute.
+    pub fn expires_soon(&self) -> bool {
+        now_secs() + EXPIRY_MARGIN_SECS >= self.expires_at.load(SeqCst)
+    }
+
+    /// Refreshes the token with the response of `fetch`.
+    ///
+    /// If the token has been refreshed since `generation`, e.g. by a concurrent
+    /// caller, `fetch` is not called at all.
+    pub async fn refresh<F, Fut>(&self, generation: u64, fetch: F) -> Result<()>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Bytes>>,
+    {
+        let _guard = self.refresh_lock.lock().await;
+
+        if self.generation() != generation {
+            return Ok(());
+        }
+
+        let bytes = fetch().await?;
+
+        self.parse(&bytes).context("Failed to parse authorization")
+    }
+
+    fn parse(&self, bytes: &[u8]) -> Result<()> {
         const KEY: &[u8] = br#""access_token":"#;
 
         let idx = memchr::memmem::find(bytes, KEY).context("missing `\"access_token\"`")?;
-        let bytes = &bytes[idx + KEY.len()..];
-        let mut iter = memchr::memchr_iter(b'"', bytes);
+        let bytes_ = &bytes[idx + KEY.len()..];
+        let mut iter = memchr::memchr_iter(b'"', bytes_);
         let (start, end) = iter.next().zip(iter.next()).context("missing quotes")?;
 
-        let token = std::str::from_utf8(&bytes[start + 1..end])
+        let token = std::str::from_utf8(&bytes_[start + 1..end])
             .context("access token is not valid utf-8")?;
 
-        let authorization = format!("Bearer {token}");
-        let ptr = Box::into_raw(Box::new(authorization.into_boxed_str()));
+        let expires_in = Self::parse_expires_in(bytes).context("missing `\"expires_in\"`")?;
+
+        *self.token.write().unwrap() = Arc::from(format!("Bearer {token}"));
 
-        let old = self.ptr.swap(ptr, SeqCst);
-        unsafe { old.drop_in_place() };
+        self.expires_at.store(now_secs() + expires_in, SeqCst);
+        self.generation.fetch_add(1, SeqCst);
 
         Ok(())
     }
+
+    fn parse_expires_in(bytes: &[u8]) -> Option<u64> {
+        const KEY: &[u8] = br#""expires_in":"#;
+
+        let idx = memchr::memmem::find(bytes, KEY)?;
+
+        let digits = bytes[idx + KEY.len()..]
+            .iter()
+            .skip_while(|byte| byte.is_ascii_whitespace())
+            .take_while(|byte| byte.is_ascii_digit());
+
+        digits.fold(None, |n, byte| {
+            Some(n.unwrap_or(0) * 10 + u64::from(byte & 0xF))
+        })
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
 }
 
 impl Default for Authorization {
     fn default() -> Self {
         Self {
-            ptr: AtomicPtr::new(Box::into_raw(Box::default())),
+            token: RwLock::new(Arc::from("")),
+            expires_at: AtomicU64::new(0),
+            generation: AtomicU64::new(0),
+            refresh_lock: Mutex::new(()),
         }
     }
 }
 
-impl Drop for Authorization {
-    fn drop(&mut self) {
-        let ptr = self.ptr.load(SeqCst);
-        unsafe { ptr.drop_in_place() };
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicUsize, Arc},
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn refresh_near_expiry_once() {
+        let auth = Arc::new(Authorization::default());
+        assert!(auth.expires_soon());
+
+        let expiring = br#"{"token_type":"Bearer","expires_in":30,"access_token":"old"}"#;
+        auth.parse(expiring).unwrap();
+        assert_eq!(&*auth.as_str(), "Bearer old");
+        assert!(auth.expires_soon());
+
+        let fetched = Arc::new(AtomicUsize::new(0));
+        let generation = auth.generation();
+
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let auth = Arc::clone(&auth);
+                let fetched = Arc::clone(&fetched);
+
+                tokio::spawn(async move {
+                    let fetch = || async {
+                        fetched.fetch_add(1, SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+
+                        Ok(Bytes::from_static(
+                            br#"{"token_type":"Bearer","expires_in":86400,"access_token":"new"}"#,
+                        ))
+                    };
+
+                    auth.refresh(generation, fetch).await
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+
+        assert_eq!(fetched.load(SeqCst), 1);
+        assert_eq!(&*auth.as_str(), "Bearer new");
+        assert!(!auth.expires_soon());
+    }
+
+    #[test]
+    fn refresh_while_reading() {
+        let auth = Authorization::default();
+        auth.parse(br#"{"expires_in":86400,"access_token":"0"}"#)
+            .unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..10_000 {
+                        let token = auth.as_str();
+                        assert!(token.starts_with("Bearer "), "{token}");
+                    }
+                });
+            }
+
+            for i in 1..=10_000 {
+                let json = format!(r#"{{"expires_in":86400,"access_token":"{i}"}}"#);
+                auth.parse(json.as_bytes()).unwrap();
+            }
+        });
+
+        assert_eq!(&*auth.as_str(), "Bearer 10000");
+        assert_eq!(auth.generation(), 10_001);
     }
 }
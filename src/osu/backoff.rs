@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Exponential backoff with jitter.
+///
+/// The n-th delay is `base * 2^n`, capped at `cap`, of which a random amount
+/// of up to half is subtracted so that retries don't happen in lockstep.
+pub struct Backoff<R = StdRng> {
+    base: Duration,
+    cap: Duration,
+    max_retries: Option<u32>,
+    attempt: u32,
+    rng: R,
+}
+
+impl Backoff {
+    pub fn new(base: Duration, cap: Duration, max_retries: Option<u32>) -> Self {
+        Self::with_rng(base, cap, max_retries, StdRng::from_entropy())
+    }
+}
+
+impl<R: Rng> Backoff<R> {
+    pub const fn with_rng(base: Duration, cap: Duration, max_retries: Option<u32>, rng: R) -> Self {
+        Self {
+            base,
+            cap,
+            max_retries,
+            attempt: 0,
+            rng,
+        }
+    }
+
+    /// Returns the delay before the next retry or `None` if the maximum
+    /// amount of retries has been reached.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_retries.is_some_and(|max| self.attempt >= max) {
+            return None;
+        }
+
+        let factor = 2_u32.checked_pow(self.attempt).unwrap_or(u32::MAX);
+        let delay = self.base.saturating_mul(factor).min(self.cap);
+        self.attempt += 1;
+
+        Some(delay.mul_f64(self.rng.gen_range(0.5..=1.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progression() {
+        const BASE: Duration = Duration::from_millis(500);
+        const CAP: Duration = Duration::from_secs(30);
+
+        let rng = StdRng::seed_from_u64(727);
+        let mut backoff = Backoff::with_rng(BASE, CAP, Some(10), rng);
+
+        let upper_bounds = [500, 1000, 2000, 4000, 8000, 16_000, 30_000, 30_000];
+
+        for upper in upper_bounds.map(Duration::from_millis) {
+            let delay = backoff.next_delay().unwrap();
+            assert!(
+                upper / 2 <= delay && delay <= upper,
+                "{delay:?} for {upper:?}"
+            );
+        }
+
+        // Same seed, same delays
+        let mut a = Backoff::with_rng(BASE, CAP, None, StdRng::seed_from_u64(1));
+        let mut b = Backoff::with_rng(BASE, CAP, None, StdRng::seed_from_u64(1));
+
+        for _ in 0..5 {
+            assert_eq!(a.next_delay(), b.next_delay());
+        }
+
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_none());
+    }
+}
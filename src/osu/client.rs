@@ -1,22 +1,36 @@
-use std::{borrow::Cow, cmp, time::Duration};
+use std::{
+    fmt::Write,
+    sync::{atomic::Ordering::Relaxed, Mutex},
+    time::{Duration, Instant},
+};
 
 use bytes::Bytes;
 use eyre::{Context as _, Result};
-use http_body_util::{BodyExt, Full};
+use http_body_util::{BodyExt, Full, Limited};
 use hyper::{
     header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, USER_AGENT},
-    Request, StatusCode,
+    HeaderMap, Request, StatusCode,
 };
 use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
 use hyper_util::{
-    client::legacy::{connect::HttpConnector, Builder, Client},
+    client::legacy::{Builder, Client},
     rt::TokioExecutor,
 };
 use memchr::memmem;
+use tracing::field;
 
-use crate::config::OsuConfig;
+use crate::{
+    config::{ClientCredentials, OsuConfig},
+    metrics::METRICS,
+};
 
-use super::{authorization::Authorization, Scores, ScoresDeserializer};
+use super::{
+    backoff::Backoff,
+    credentials::{Credential, Credentials},
+    proxy::ProxyConnector,
+    ratelimit::RateLimited,
+    Scores, ScoresDeserializer,
+};
 
 const MY_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 const APPLICATION_JSON: &str = "application/json";
@@ -26,40 +40,63 @@ type Body = Full<Bytes>;
 
 pub struct Osu {
     config: OsuConfig,
-    authorization: Authorization,
-    client: Client<HttpsConnector<HttpConnector>, Body>,
+    credentials: Credentials,
+    client: Client<HttpsConnector<ProxyConnector>, Body>,
+    /// Base and cap of the backoff between retries.
+    backoff: Mutex<(Duration, Duration)>,
 }
 
 impl Osu {
-    pub fn new(config: OsuConfig) -> Result<Self> {
-        #[cfg(feature = "ring")]
-        let crypto_provider = rustls::crypto::ring::default_provider();
-        #[cfg(all(feature = "aws", not(feature = "ring")))]
-        let crypto_provider = rustls::crypto::aws_lc_rs::default_provider();
-        #[cfg(not(any(feature = "ring", feature = "aws")))]
-        let crypto_provider = rustls::crypto::CryptoProvider::get_default()
-            .expect("No default crypto provider installed or configured via crate features")
-            .clone();
+    pub fn new(mut config: OsuConfig) -> Result<Self> {
+        let proxy = ProxyConnector::new(
+            config.proxy.as_deref(),
+            Duration::from_millis(config.connect_timeout_ms),
+        )?;
 
         let https = HttpsConnectorBuilder::new()
-            .with_provider_and_webpki_roots(crypto_provider)
+            .with_provider_and_webpki_roots(crate::tls::crypto_provider())
             .context("Failed to configure https connector")?
-            .https_only()
+            // Plain http is only expected for testing purposes
+            .https_or_http()
             .enable_http2()
-            .build();
+            .wrap_connector(proxy);
 
         let client = Builder::new(TokioExecutor::new())
             .http2_only(true)
             .build(https);
 
+        let primary = ClientCredentials {
+            client_id: config.client_id,
+            client_secret: config.client_secret.clone(),
+        };
+
+        let credentials = Credentials::new(
+            std::iter::once(primary).chain(std::mem::take(&mut config.credentials)),
+        );
+
+        let backoff = Mutex::new((
+            Duration::from_millis(config.backoff_base_ms),
+            Duration::from_millis(config.backoff_cap_ms),
+        ));
+
         Ok(Self {
             config,
+            credentials,
             client,
-            authorization: Authorization::default(),
+            backoff,
         })
     }
 
-    async fn fetch_response(&self, req: Request<Body>) -> Result<(Bytes, StatusCode)> {
+    /// Applies to retries of fetches that start afterwards.
+    pub(crate) fn set_backoff(&self, base: Duration, cap: Duration) {
+        *self.backoff.lock().unwrap() = (base, cap);
+    }
+
+    async fn fetch_response(
+        &self,
+        credential: &Credential,
+        req: Request<Body>,
+    ) -> Result<(Bytes, StatusCode, HeaderMap)> {
         let response = self
             .client
             .request(req)
@@ -68,32 +105,48 @@ impl Osu {
 
         let (parts, incoming) = response.into_parts();
 
-        let bytes = incoming
+        let bytes = Limited::new(incoming, self.config.max_response_size)
             .collect()
             .await
+            .map_err(|err| eyre!(err))
             .context("Failed to collect bytes")?
             .to_bytes();
 
-        Ok((bytes, parts.status))
+        credential.ratelimit.update(&parts.headers);
+
+        if parts.status != StatusCode::OK {
+            METRICS.api_error(parts.status);
+        }
+
+        Ok((bytes, parts.status, parts.headers))
     }
 
-    async fn reauthorize(&self) -> Result<()> {
-        const URL: &str = "https://osu.ppy.sh/oauth/token";
+    /// Re-authorizes unless the token has already been refreshed since
+    /// `generation`.
+    async fn reauthorize(&self, credential: &Credential, generation: u64) -> Result<()> {
+        credential
+            .authorization
+            .refresh(generation, || self.fetch_token(credential))
+            .await
+    }
 
-        info!("Re-authorizing...");
+    async fn fetch_token(&self, credential: &Credential) -> Result<Bytes> {
+        let url = format!("{}/oauth/token", self.config.base_url);
 
-        let OsuConfig {
+        let Credential {
             client_id,
             client_secret,
-            ruleset: _,
-        } = &self.config;
+            ..
+        } = credential;
+
+        info!(client_id, "Re-authorizing...");
 
         let body = format!(
             "client_id={client_id}&client_secret={client_secret}\
             &grant_type=client_credentials&scope=public"
         );
 
-        let req = Request::post(URL)
+        let req = Request::post(url)
             .header(USER_AGENT, MY_USER_AGENT)
             .header(ACCEPT, APPLICATION_JSON)
             .header(CONTENT_TYPE, APPLICATION_URL_ENCODED)
@@ -101,25 +154,22 @@ impl Osu {
             .body(Full::from(body))
             .context("Failed to create token request")?;
 
-        let (bytes, status_code) = self
-            .fetch_response(req)
+        let (bytes, status_code, headers) = self
+            .fetch_response(credential, req)
             .await
             .context("Failed to fetch response")?;
 
         match status_code {
-            StatusCode::OK => self
-                .authorization
-                .parse(&bytes)
-                .context("Failed to parse authorization"),
+            StatusCode::OK => Ok(bytes),
             StatusCode::UNAUTHORIZED => {
+                credential.bench(None);
+
                 bail!(
                     "Received 401 error while authorizing, make sure your \
                     client id and secret are valid: {bytes:?}"
                 )
             }
-            StatusCode::TOO_MANY_REQUESTS => {
-                bail!("Received 429 error, try reducing your interval: {bytes:?}")
-            }
+            StatusCode::TOO_MANY_REQUESTS => Err(Self::rate_limited(credential, &headers)),
             StatusCode::SERVICE_UNAVAILABLE => {
                 bail!("Received 503 error, osu! servers likely temporarily down: {bytes:?}")
             }
@@ -127,104 +177,315 @@ impl Osu {
         }
     }
 
-    pub async fn fetch_scores(&self, scores: &mut Scores, cursor_id: Option<u64>) -> FetchResult {
-        const URL: &str = "https://osu.ppy.sh/api/v2/scores";
+    /// The url of the scores endpoint.
+    ///
+    /// Without a ruleset, the osu!api responds with scores of all rulesets.
+    fn scores_url(&self, ruleset: Option<&str>, cursor: Cursor<'_>) -> String {
+        let mut url = format!("{}/api/v2/scores", self.config.base_url);
 
-        async fn fetch_inner(
-            osu: &Osu,
-            scores: &mut Scores,
-            just_authorized: bool,
-            cursor_id: Option<u64>,
-        ) -> Result<FetchResult> {
-            let mut url = Cow::Borrowed(URL);
-
-            if let Some(ruleset) = osu.config.ruleset.as_deref() {
-                let url = url.to_mut();
-                url.push_str("?ruleset=");
-                url.push_str(ruleset);
-            }
-
-            if let Some(cursor_id) = cursor_id {
-                let is_without_query = matches!(url, Cow::Borrowed(_));
-                let url = url.to_mut();
+        if let Some(ruleset) = ruleset {
+            url.push_str("?ruleset=");
+            percent_encode(&mut url, ruleset);
+        }
 
-                if is_without_query {
-                    url.push('?');
-                } else {
-                    url.push('&');
-                }
+        let separator = if ruleset.is_some() { '&' } else { '?' };
 
+        match cursor {
+            Cursor::Id(Some(cursor_id)) => {
+                url.push(separator);
                 url.push_str("cursor[id]=");
                 url.push_str(itoa::Buffer::new().format(cursor_id));
             }
+            Cursor::String(Some(cursor_string)) => {
+                url.push(separator);
+                url.push_str("cursor_string=");
+                percent_encode(&mut url, cursor_string);
+            }
+            Cursor::Id(None) | Cursor::String(None) => {}
+        }
+
+        url
+    }
 
-            let req = Request::get(url.as_ref())
-                .header(USER_AGENT, MY_USER_AGENT)
-                // doesn't seem to affect the response data format
-                // .header("x-api-version", 0_usize)
-                .header(ACCEPT, APPLICATION_JSON)
-                .header(AUTHORIZATION, osu.authorization.as_str())
-                .header(CONTENT_LENGTH, 0_usize)
-                .body(Full::default())
-                .context("Failed to create request")?;
-
-            let (bytes, status_code) = osu
-                .fetch_response(req)
+    /// Also returns the response's `cursor_string` if `with_cursor_string` is
+    /// set.
+    async fn fetch_scores_once(
+        &self,
+        credential: &Credential,
+        scores: &mut Scores,
+        just_authorized: bool,
+        ruleset: Option<&str>,
+        cursor: Cursor<'_>,
+        with_cursor_string: bool,
+    ) -> Result<(FetchResult, Option<Box<str>>)> {
+        let url = self.scores_url(ruleset, cursor);
+        let authorization = &credential.authorization;
+
+        // Refresh proactively rather than waiting for a 401
+        if authorization.expires_soon() {
+            self.reauthorize(credential, authorization.generation())
                 .await
-                .context("Failed to fetch response")?;
+                .context("Failed to refresh authorization")?;
+        }
 
-            match status_code {
-                StatusCode::OK => {
-                    ScoresDeserializer::new(bytes).deserialize(scores)?;
+        let generation = authorization.generation();
 
-                    Ok(FetchResult::Ok)
-                }
-                StatusCode::UNAUTHORIZED => {
-                    if just_authorized {
-                        bail!("Received 401 error after authorizing: {bytes:?}");
-                    }
+        let req = Request::get(url)
+            .header(USER_AGENT, MY_USER_AGENT)
+            // doesn't seem to affect the response data format
+            // .header("x-api-version", 0_usize)
+            .header(ACCEPT, APPLICATION_JSON)
+            .header(AUTHORIZATION, &*authorization.as_str())
+            .header(CONTENT_LENGTH, 0_usize)
+            .body(Full::default())
+            .context("Failed to create request")?;
 
-                    osu.reauthorize().await.context("Failed to re-authorize")?;
+        let (bytes, status_code, headers) = self
+            .fetch_response(credential, req)
+            .await
+            .context("Failed to fetch response")?;
 
-                    return Box::pin(fetch_inner(osu, scores, true, cursor_id)).await;
+        match status_code {
+            StatusCode::OK => self
+                .deserialize_scores(bytes, scores, with_cursor_string)
+                .map(|cursor_string| (FetchResult::Ok, cursor_string)),
+            StatusCode::UNAUTHORIZED => {
+                if just_authorized {
+                    credential.bench(None);
+
+                    bail!("Received 401 error after authorizing: {bytes:?}");
                 }
-                StatusCode::UNPROCESSABLE_ENTITY
-                    if memmem::rfind(&bytes, br#""error":"cursor is too old""#).is_some() =>
-                {
-                    if let Some(cursor_id) = cursor_id {
+
+                self.reauthorize(credential, generation)
+                    .await
+                    .context("Failed to re-authorize")?;
+
+                let fetch_fut = self.fetch_scores_once(
+                    credential,
+                    scores,
+                    true,
+                    ruleset,
+                    cursor,
+                    with_cursor_string,
+                );
+
+                return Box::pin(fetch_fut).await;
+            }
+            StatusCode::UNPROCESSABLE_ENTITY
+                if memmem::rfind(&bytes, br#""error":"cursor is too old""#).is_some() =>
+            {
+                match cursor {
+                    Cursor::Id(Some(cursor_id)) => {
                         warn!("Score id {cursor_id} too old to fetch from");
-                    } else {
+                    }
+                    Cursor::String(Some(cursor_string)) => {
+                        warn!("Cursor string {cursor_string} too old to fetch from");
+                    }
+                    Cursor::Id(None) | Cursor::String(None) => {
                         debug!("\"cursor too old\" without a cursor id");
                     }
-
-                    Ok(FetchResult::CursorTooOld)
-                }
-                StatusCode::TOO_MANY_REQUESTS => {
-                    bail!("Received 429 error, try reducing your interval: {bytes:?}")
                 }
-                StatusCode::SERVICE_UNAVAILABLE => {
-                    bail!("Received 503 error, osu! servers likely temporarily down: {bytes:?}")
-                }
-                _ => bail!("Status code: {status_code}, Response: {bytes:?}"),
+
+                Ok((FetchResult::CursorTooOld, None))
             }
+            StatusCode::TOO_MANY_REQUESTS => Err(Self::rate_limited(credential, &headers)),
+            StatusCode::SERVICE_UNAVAILABLE => {
+                bail!("Received 503 error, osu! servers likely temporarily down: {bytes:?}")
+            }
+            _ => bail!("Status code: {status_code}, Response: {bytes:?}"),
         }
+    }
+
+    /// Benches the credential that received a 429 response.
+    fn rate_limited(credential: &Credential, headers: &HeaderMap) -> eyre::Report {
+        let err = RateLimited::new(headers);
+        credential.bench(err.retry_after);
+
+        err.into()
+    }
+
+    /// Deserializes the scores of a successful response.
+    ///
+    /// Also returns the response's `cursor_string` if `with_cursor_string` is
+    /// set.
+    fn deserialize_scores(
+        &self,
+        bytes: Bytes,
+        scores: &mut Scores,
+        with_cursor_string: bool,
+    ) -> Result<Option<Box<str>>> {
+        let prev_len = scores.len();
+        let bytes_len = bytes.len();
+
+        let span = debug_span!(
+            "deserialize",
+            bytes = bytes_len,
+            scores = field::Empty,
+            parse_us = field::Empty,
+        );
+
+        let deserializer = ScoresDeserializer::new(bytes)
+            .max_scores(self.config.max_scores)
+            .max_depth(self.config.max_depth);
+
+        let cursor_string = if with_cursor_string {
+            deserializer.cursor_string()?
+        } else {
+            None
+        };
+
+        let skipped = span.in_scope(|| {
+            let start = Instant::now();
+            let res = deserializer.deserialize_lenient(scores);
+            span.record("parse_us", start.elapsed().as_micros());
+            span.record("scores", scores.len() - prev_len);
+
+            res
+        });
+
+        let skipped = skipped.inspect_err(|_| {
+            METRICS.parse_failures.fetch_add(1, Relaxed);
+        })?;
+
+        METRICS
+            .scores_parsed
+            .fetch_add((scores.len() - prev_len) as u64, Relaxed);
+        METRICS.bytes_parsed.fetch_add(bytes_len as u64, Relaxed);
+        METRICS.parse_failures.fetch_add(skipped as u64, Relaxed);
+
+        if skipped > 0 {
+            warn!("Skipped {skipped} score(s) that failed to deserialize");
+        }
+
+        Ok(cursor_string)
+    }
+
+    pub async fn fetch_scores(
+        &self,
+        scores: &mut Scores,
+        ruleset: Option<&str>,
+        cursor_id: Option<u64>,
+    ) -> FetchResult {
+        info!(?ruleset, ?cursor_id, "Fetching scores...");
+
+        let (res, _) = self
+            .fetch_with_retries(scores, ruleset, Cursor::Id(cursor_id), false)
+            .await;
+
+        res
+    }
+
+    /// Same as [`Osu::fetch_scores`] but also returns the response's
+    /// `cursor_string`.
+    pub async fn fetch_scores_with_cursor_string(
+        &self,
+        scores: &mut Scores,
+        ruleset: Option<&str>,
+        cursor_id: Option<u64>,
+    ) -> (FetchResult, Option<Box<str>>) {
+        info!(?ruleset, ?cursor_id, "Fetching scores...");
+
+        self.fetch_with_retries(scores, ruleset, Cursor::Id(cursor_id), true)
+            .await
+    }
 
-        info!(?cursor_id, "Fetching scores...");
+    /// Fetches the page of scores that `cursor_string` points to, or the most
+    /// recent scores if it's `None`.
+    ///
+    /// Also returns the response's `cursor_string` for the next page.
+    pub async fn fetch_page(
+        &self,
+        scores: &mut Scores,
+        ruleset: Option<&str>,
+        cursor_string: Option<&str>,
+    ) -> (FetchResult, Option<Box<str>>) {
+        info!(?ruleset, ?cursor_string, "Fetching page of scores...");
+
+        self.fetch_with_retries(scores, ruleset, Cursor::String(cursor_string), true)
+            .await
+    }
 
-        let mut backoff = 2;
+    async fn fetch_with_retries(
+        &self,
+        scores: &mut Scores,
+        ruleset: Option<&str>,
+        cursor: Cursor<'_>,
+        with_cursor_string: bool,
+    ) -> (FetchResult, Option<Box<str>>) {
+        let (base, cap) = *self.backoff.lock().unwrap();
+        let mut backoff = Backoff::new(base, cap, self.config.max_retries);
 
         loop {
-            let fetch_fut = fetch_inner(self, scores, false, cursor_id);
+            let credential = self.credentials.next();
+
+            if let Some(delay) = credential.ratelimit.delay() {
+                warn!("Few requests remaining in the ratelimit, waiting {delay:?}...");
+                tokio::time::sleep(delay).await;
+            }
+
+            let fetch_fut = self.fetch_scores_once(
+                credential,
+                scores,
+                false,
+                ruleset,
+                cursor,
+                with_cursor_string,
+            );
 
-            match tokio::time::timeout(Duration::from_secs(10), fetch_fut).await {
+            let timeout = Duration::from_millis(self.config.request_timeout_ms);
+
+            let retry_after = match tokio::time::timeout(timeout, fetch_fut).await {
                 Ok(Ok(res)) => return res,
-                Ok(Err(err)) => error!(?err, "Failed to fetch scores"),
-                Err(_) => error!("Timeout while awaiting scores"),
+                Ok(Err(err)) => {
+                    error!(?err, "Failed to fetch scores");
+
+                    err.downcast_ref::<RateLimited>()
+                        .and_then(|err| err.retry_after)
+                }
+                Err(_) => {
+                    error!(?timeout, "Timeout while awaiting scores");
+
+                    None
+                }
+            };
+
+            if credential.is_benched() && self.credentials.any_available() {
+                info!("Retrying with other credentials...");
+
+                continue;
             }
 
-            info!("Retrying in {backoff}s...");
-            tokio::time::sleep(Duration::from_secs(backoff)).await;
-            backoff = cmp::min(120, backoff * 2);
+            let Some(delay) = backoff.next_delay() else {
+                warn!("Reached maximum amount of retries, skipping poll");
+
+                return (FetchResult::Failed, None);
+            };
+
+            // The osu!api knows best how long we should wait
+            let delay = retry_after.unwrap_or(delay);
+
+            info!("Retrying in {delay:.2?}...");
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Where to start fetching scores from.
+#[derive(Copy, Clone)]
+enum Cursor<'a> {
+    Id(Option<u64>),
+    /// The `cursor_string` of a previous response.
+    String(Option<&'a str>),
+}
+
+/// Appends `value` to `url` while percent-encoding everything but unreserved
+/// characters.
+fn percent_encode(url: &mut String, value: &str) {
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            url.push(char::from(byte));
+        } else {
+            let _ = write!(url, "%{byte:02X}");
         }
     }
 }
@@ -234,4 +495,176 @@ pub enum FetchResult {
     #[default]
     Ok,
     CursorTooOld,
+    /// The maximum amount of retries has been reached.
+    Failed,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Mutex,
+    };
+
+    use super::*;
+    use crate::osu::mock;
+
+    #[tokio::test]
+    async fn oversized_response() {
+        let api = mock::serve(|_| {
+            let scores = vec![r#"{"id": 1, "padding": "................"}"#; 100];
+
+            format!(r#"{{"scores": [{}]}}"#, scores.join(","))
+        })
+        .await;
+
+        let mut config = mock::config(api);
+        config.max_response_size = 1024;
+        let osu = Osu::new(config).unwrap();
+
+        let mut scores = Scores::new();
+        let res = osu.fetch_scores(&mut scores, None, None).await;
+
+        assert!(matches!(res, FetchResult::Failed));
+        assert!(scores.is_empty());
+
+        let mut config = mock::config(api);
+        config.max_scores = 10;
+        let osu = Osu::new(config).unwrap();
+
+        let res = osu.fetch_scores(&mut scores, None, None).await;
+
+        assert!(matches!(res, FetchResult::Failed));
+        assert!(scores.is_empty());
+
+        let osu = Osu::new(mock::config(api)).unwrap();
+        let res = osu.fetch_scores(&mut scores, None, None).await;
+
+        assert!(matches!(res, FetchResult::Ok));
+        assert_eq!(scores.len(), 1);
+    }
+
+    #[test]
+    fn scores_url() {
+        let mut config: OsuConfig = toml::from_str("").unwrap();
+        config.base_url = "https://osu.ppy.sh".into();
+        let osu = Osu::new(config).unwrap();
+
+        let url = |ruleset, cursor| osu.scores_url(ruleset, cursor);
+
+        assert_eq!(
+            url(None, Cursor::Id(None)),
+            "https://osu.ppy.sh/api/v2/scores"
+        );
+        assert_eq!(
+            url(Some("mania"), Cursor::Id(None)),
+            "https://osu.ppy.sh/api/v2/scores?ruleset=mania"
+        );
+        assert_eq!(
+            url(Some("fruits"), Cursor::Id(Some(727))),
+            "https://osu.ppy.sh/api/v2/scores?ruleset=fruits&cursor[id]=727"
+        );
+        assert_eq!(
+            url(None, Cursor::String(Some("a+b"))),
+            "https://osu.ppy.sh/api/v2/scores?cursor_string=a%2Bb"
+        );
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+        // Only the first request hangs
+        let api = mock::serve_delayed(|_| {
+            let delay = match REQUESTS.fetch_add(1, Relaxed) {
+                0 => Duration::from_secs(30),
+                _ => Duration::ZERO,
+            };
+
+            (delay, r#"{"scores": [{"id": 1}]}"#.to_owned())
+        })
+        .await;
+
+        let mut config = mock::config(api);
+        config.request_timeout_ms = 200;
+        config.backoff_base_ms = 10;
+        config.backoff_cap_ms = 10;
+        config.max_retries = Some(1);
+        let osu = Osu::new(config).unwrap();
+
+        let mut scores = Scores::new();
+        let fetch = osu.fetch_scores(&mut scores, None, None);
+        let res = tokio::time::timeout(Duration::from_secs(5), fetch)
+            .await
+            .unwrap();
+
+        assert!(matches!(res, FetchResult::Ok));
+        assert_eq!(scores.len(), 1);
+        assert_eq!(REQUESTS.load(Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn rotate_credentials() {
+        static AUTHORIZATIONS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        static THROTTLED: AtomicBool = AtomicBool::new(false);
+
+        let api = mock::serve_authorized(|authorization| {
+            AUTHORIZATIONS
+                .lock()
+                .unwrap()
+                .push(authorization.to_owned());
+
+            if authorization == "Bearer token1" && THROTTLED.load(Relaxed) {
+                return (StatusCode::TOO_MANY_REQUESTS, String::new());
+            }
+
+            (StatusCode::OK, r#"{"scores": [{"id": 1}]}"#.to_owned())
+        })
+        .await;
+
+        let mut config = mock::config(api);
+        config.credentials = vec![ClientCredentials {
+            client_id: 1,
+            client_secret: Box::default(),
+        }];
+
+        let osu = Osu::new(config).unwrap();
+        let mut scores = Scores::new();
+
+        for _ in 0..4 {
+            let res = osu.fetch_scores(&mut scores, None, None).await;
+            assert!(matches!(res, FetchResult::Ok));
+        }
+
+        let authorizations = std::mem::take(&mut *AUTHORIZATIONS.lock().unwrap());
+        assert_eq!(
+            authorizations,
+            [
+                "Bearer token0",
+                "Bearer token1",
+                "Bearer token0",
+                "Bearer token1"
+            ]
+        );
+
+        THROTTLED.store(true, Relaxed);
+
+        for _ in 0..3 {
+            let res = osu.fetch_scores(&mut scores, None, None).await;
+            assert!(matches!(res, FetchResult::Ok));
+        }
+
+        // The throttled credentials are benched and the request is retried
+        // with the other credentials right away
+        let authorizations = std::mem::take(&mut *AUTHORIZATIONS.lock().unwrap());
+        assert_eq!(
+            authorizations,
+            [
+                "Bearer token0",
+                "Bearer token1",
+                "Bearer token0",
+                "Bearer token0"
+            ]
+        );
+    }
 }
@@ -0,0 +1,151 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::config::ClientCredentials;
+
+use super::{authorization::Authorization, ratelimit::RateLimit};
+
+/// How long credentials are benched if the osu!api doesn't specify it.
+const BENCH_DURATION: Duration = Duration::from_mins(1);
+
+/// A client id and secret with its own token and ratelimit.
+pub struct Credential {
+    pub client_id: u64,
+    pub client_secret: Box<str>,
+    pub authorization: Authorization,
+    pub ratelimit: RateLimit,
+    /// While set and in the future, the credential is skipped.
+    benched_until: Mutex<Option<Instant>>,
+}
+
+impl Credential {
+    /// Skips the credential for `duration` or, if not specified, a minute.
+    pub fn bench(&self, duration: Option<Duration>) {
+        let duration = duration.unwrap_or(BENCH_DURATION);
+        warn!(
+            client_id = self.client_id,
+            "Benching credentials for {duration:?}"
+        );
+
+        *self.benched_until.lock().unwrap() = Some(Instant::now() + duration);
+    }
+
+    pub fn is_benched(&self) -> bool {
+        self.benched_until().is_some()
+    }
+
+    fn benched_until(&self) -> Option<Instant> {
+        self.benched_until
+            .lock()
+            .unwrap()
+            .filter(|&until| until > Instant::now())
+    }
+}
+
+/// Rotates requests among multiple credentials in round-robin order.
+pub struct Credentials {
+    list: Box<[Credential]>,
+    next: AtomicUsize,
+}
+
+impl Credentials {
+    /// `credentials` must not be empty.
+    pub fn new(credentials: impl IntoIterator<Item = ClientCredentials>) -> Self {
+        let list: Box<[_]> = credentials
+            .into_iter()
+            .map(|credentials| Credential {
+                client_id: credentials.client_id,
+                client_secret: credentials.client_secret,
+                authorization: Authorization::default(),
+                ratelimit: RateLimit::default(),
+                benched_until: Mutex::new(None),
+            })
+            .collect();
+
+        assert!(!list.is_empty(), "missing credentials");
+
+        Self {
+            list,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the next credential that is not benched.
+    ///
+    /// If all are benched, the one whose bench ends first is returned.
+    pub fn next(&self) -> &Credential {
+        let start = self.next.fetch_add(1, Relaxed);
+        let len = self.list.len();
+
+        let mut earliest: Option<(usize, Instant)> = None;
+
+        for offset in 0..len {
+            let idx = (start + offset) % len;
+
+            let Some(until) = self.list[idx].benched_until() else {
+                // Continue the rotation after the chosen credential
+                self.next.store(idx + 1, Relaxed);
+
+                return &self.list[idx];
+            };
+
+            if earliest.is_none_or(|(_, earliest)| until < earliest) {
+                earliest = Some((idx, until));
+            }
+        }
+
+        let (idx, _) = earliest.expect("credentials are not empty");
+
+        &self.list[idx]
+    }
+
+    /// Whether any credential is not benched.
+    pub fn any_available(&self) -> bool {
+        self.list.iter().any(|credential| !credential.is_benched())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(ids: &[u64]) -> Credentials {
+        Credentials::new(ids.iter().map(|&client_id| ClientCredentials {
+            client_id,
+            client_secret: Box::default(),
+        }))
+    }
+
+    #[test]
+    fn round_robin() {
+        let credentials = credentials(&[1, 2, 3]);
+        let ids: Vec<_> = (0..6).map(|_| credentials.next().client_id).collect();
+
+        assert_eq!(ids, [1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn skip_benched() {
+        let credentials = credentials(&[1, 2, 3]);
+        credentials.list[1].bench(None);
+
+        let ids: Vec<_> = (0..4).map(|_| credentials.next().client_id).collect();
+        assert_eq!(ids, [1, 3, 1, 3]);
+        assert!(credentials.any_available());
+
+        credentials.list[0].bench(Some(Duration::from_secs(30)));
+        credentials.list[2].bench(Some(Duration::from_mins(2)));
+        assert!(!credentials.any_available());
+
+        // All are benched so the one that becomes available first is used
+        assert_eq!(credentials.next().client_id, 1);
+
+        credentials.list[0].bench(Some(Duration::ZERO));
+        assert_eq!(credentials.next().client_id, 1);
+    }
+}
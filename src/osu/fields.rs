@@ -0,0 +1,357 @@
+use std::fmt;
+
+use eyre::Result;
+
+/// Iterator over the top-level fields of a JSON object.
+///
+/// Values are not deserialized but only skipped over so that keys within
+/// nested objects or within string values can't be mistaken for top-level
+/// keys. Each item consists of the raw key, without quotes, and the raw value.
+pub struct Fields<'a> {
+    bytes: &'a [u8],
+    idx: usize,
+    done: bool,
+    max_depth: u32,
+}
+
+impl<'a> Fields<'a> {
+    /// `bytes` are expected to start with the object's opening brace.
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            idx: 1,
+            done: false,
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    /// Fail with [`NestedTooDeeply`] if objects and arrays are nested more
+    /// than `max_depth` deep within a value.
+    ///
+    /// Values may never be nested deeper than [`MAX_DEPTH`].
+    #[must_use]
+    pub const fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = if max_depth < MAX_DEPTH {
+            max_depth
+        } else {
+            MAX_DEPTH
+        };
+
+        self
+    }
+
+    /// Index of the object's closing brace.
+    ///
+    /// Only meaningful once the iterator is exhausted without error.
+    pub const fn end(&self) -> usize {
+        self.idx
+    }
+
+    /// Returns the index at which the value of the top-level `key` starts.
+    ///
+    /// Unlike iterating, the value of `key` itself is not skipped over.
+    pub fn value_start(mut self, key: &[u8]) -> Result<Option<usize>> {
+        while let Some(next_key) = self.next_key()? {
+            if next_key == key {
+                return Ok(Some(self.idx));
+            }
+
+            self.idx = value_end(self.bytes, self.idx, self.max_depth)?;
+        }
+
+        Ok(None)
+    }
+
+    fn next_field(&mut self) -> Result<Option<(&'a [u8], &'a [u8])>> {
+        let Some(key) = self.next_key()? else {
+            return Ok(None);
+        };
+
+        let value_start = self.idx;
+        self.idx = value_end(self.bytes, value_start, self.max_depth)?;
+
+        Ok(Some((key, &self.bytes[value_start..self.idx])))
+    }
+
+    /// Moves the index to the start of the next field's value and returns
+    /// the field's key.
+    fn next_key(&mut self) -> Result<Option<&'a [u8]>> {
+        self.skip_whitespace();
+
+        match self.bytes.get(self.idx) {
+            Some(b'}') => return Ok(None),
+            Some(b',') => {
+                self.idx += 1;
+                self.skip_whitespace();
+            }
+            Some(_) => {}
+            None => return Err(UnexpectedEnd.into()),
+        }
+
+        match self.bytes.get(self.idx) {
+            Some(b'"') => {}
+            Some(_) => bail!("Expected key at index {}", self.idx),
+            None => return Err(UnexpectedEnd.into()),
+        }
+
+        let key_end = string_end(self.bytes, self.idx)?;
+        let key = &self.bytes[self.idx + 1..key_end];
+        self.idx = key_end + 1;
+        self.skip_whitespace();
+
+        match self.bytes.get(self.idx) {
+            Some(b':') => {}
+            Some(_) => bail!("Expected colon after key at index {}", self.idx),
+            None => return Err(UnexpectedEnd.into()),
+        }
+
+        self.idx += 1;
+        self.skip_whitespace();
+
+        Ok(Some(key))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.idx)
+            .is_some_and(|&byte| is_whitespace(byte))
+        {
+            self.idx += 1;
+        }
+    }
+}
+
+/// The bytes end before the object does, e.g. because they're truncated.
+#[derive(Debug)]
+pub struct UnexpectedEnd;
+
+impl fmt::Display for UnexpectedEnd {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unexpected end of input")
+    }
+}
+
+impl std::error::Error for UnexpectedEnd {}
+
+/// Objects and arrays are nested deeper than allowed.
+#[derive(Debug)]
+pub struct NestedTooDeeply {
+    /// Index of the opening bracket that exceeds the depth.
+    pub idx: usize,
+    pub max_depth: u32,
+}
+
+impl fmt::Display for NestedTooDeeply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "nested deeper than {} at index {}",
+            self.max_depth, self.idx
+        )
+    }
+}
+
+impl std::error::Error for NestedTooDeeply {}
+
+/// Whitespace as defined by JSON which, unlike [`u8::is_ascii_whitespace`],
+/// excludes form feeds.
+pub const fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, b' ' | b'\t' | b'\n' | b'\r')
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(&'a [u8], &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let res = self.next_field().transpose();
+        self.done = !matches!(res, Some(Ok(_)));
+
+        res
+    }
+}
+
+/// Whether the byte following `preceding` is escaped i.e. whether
+/// `preceding` ends on an odd amount of backslashes.
+fn is_escaped(preceding: &[u8]) -> bool {
+    preceding
+        .iter()
+        .rev()
+        .take_while(|&&byte| byte == b'\\')
+        .count()
+        % 2
+        == 1
+}
+
+/// Returns the index of the quote closing the string that starts at `start`.
+fn string_end(bytes: &[u8], start: usize) -> Result<usize> {
+    memchr::memchr_iter(b'"', &bytes[start + 1..])
+        .map(|i| start + 1 + i)
+        .find(|&i| !is_escaped(&bytes[..i]))
+        .ok_or_else(|| UnexpectedEnd.into())
+}
+
+/// Objects and arrays may be nested at most this deep within a value, same
+/// as `serde_json`'s default recursion limit.
+pub const MAX_DEPTH: u32 = u128::BITS;
+
+/// Returns the index right after the value that starts at `start`.
+fn value_end(bytes: &[u8], start: usize, max_depth: u32) -> Result<usize> {
+    match bytes.get(start) {
+        Some(b'"') => string_end(bytes, start).map(|end| end + 1),
+        Some(b'{' | b'[') => nested_end(bytes, start, max_depth),
+        Some(_) => bytes[start..]
+            .iter()
+            .position(|&byte| matches!(byte, b',' | b'}' | b']') || is_whitespace(byte))
+            .map(|len| start + len)
+            .ok_or_else(|| UnexpectedEnd.into()),
+        None => Err(UnexpectedEnd.into()),
+    }
+}
+
+/// Returns the index right after the object or array that starts at `start`.
+///
+/// Both objects and arrays are tracked so that each closing bracket must
+/// match the kind of the innermost open one, e.g. `[{]}` is rejected.
+fn nested_end(bytes: &[u8], start: usize, max_depth: u32) -> Result<usize> {
+    // Each bit denotes whether the respective depth is an array
+    let mut kinds = 0_u128;
+    let mut depth = 0;
+    let mut i = start;
+
+    // memchr searches for at most three bytes at once but five are relevant
+    // here. Searching braces and brackets separately is slower than checking
+    // each byte since quotes and braces are so frequent within scores.
+    while let Some(offset) = bytes[i..]
+        .iter()
+        .position(|&byte| matches!(byte, b'"' | b'{' | b'}' | b'[' | b']'))
+    {
+        i += offset;
+
+        match bytes[i] {
+            b'"' => i = string_end(bytes, i)?,
+            open @ (b'{' | b'[') => {
+                if depth >= max_depth {
+                    return Err(NestedTooDeeply { idx: i, max_depth }.into());
+                }
+
+                kinds = (kinds << 1) | u128::from(open == b'[');
+                depth += 1;
+            }
+            close => {
+                let is_array = kinds & 1 == 1;
+
+                ensure!(
+                    depth > 0 && is_array == (close == b']'),
+                    "Mismatched bracket at index {i}"
+                );
+
+                kinds >>= 1;
+                depth -= 1;
+
+                if depth == 0 {
+                    return Ok(i + 1);
+                }
+            }
+        }
+
+        i += 1;
+    }
+
+    Err(UnexpectedEnd.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_nesting() {
+        let bytes = br#"{"mods":[{"acronym":"DT","settings":{"speed_change":1.5,"x":[[],{"id":9}]}},{"acronym":"]}"}],"statistics":{"great":[1,{"id":8}]},"id":4}"#;
+
+        let fields: Vec<_> = Fields::new(bytes).collect::<Result<_>>().unwrap();
+        let keys: Vec<_> = fields.iter().map(|(key, _)| *key).collect();
+
+        assert_eq!(keys, [&b"mods"[..], b"statistics", b"id"]);
+        assert_eq!(fields[2].1, b"4");
+
+        for bytes in [
+            &br#"{"mods":[{"acronym":"DT"]},"id":1}"#[..],
+            br#"{"mods":{"a":[1}],"id":1}"#,
+            br#"{"mods":[[[]],"id":1}"#,
+        ] {
+            let res: Result<Vec<_>> = Fields::new(bytes).collect();
+            assert!(res.is_err(), "{:?}", std::str::from_utf8(bytes));
+        }
+    }
+
+    #[test]
+    fn value_start() {
+        let bytes = br#"{"a": {"b": 1}, "b" : [1, "]"], "c": 3}"#;
+
+        assert_eq!(Fields::new(bytes).value_start(b"b").unwrap(), Some(22));
+        assert_eq!(Fields::new(bytes).value_start(b"d").unwrap(), None);
+
+        // The value itself is not skipped over so it may be truncated
+        assert_eq!(
+            Fields::new(br#"{"a": 1, "b": [1"#)
+                .value_start(b"b")
+                .unwrap(),
+            Some(14)
+        );
+        assert!(Fields::new(br#"{"a": [1, "b": 2}"#)
+            .value_start(b"b")
+            .is_err());
+    }
+
+    #[test]
+    fn max_depth() {
+        let nested =
+            |depth: usize| format!(r#"{{"a":{}{}}}"#, "[".repeat(depth), "]".repeat(depth));
+
+        let bytes = nested(MAX_DEPTH as usize);
+        assert!(Fields::new(bytes.as_bytes()).all(|res| res.is_ok()));
+
+        let bytes = nested(MAX_DEPTH as usize + 1);
+        assert!(Fields::new(bytes.as_bytes()).any(|res| res.is_err()));
+
+        let bytes = nested(3);
+        assert!(Fields::new(bytes.as_bytes())
+            .max_depth(3)
+            .all(|res| res.is_ok()));
+
+        let err = Fields::new(bytes.as_bytes())
+            .max_depth(2)
+            .find_map(Result::err)
+            .unwrap();
+        let err = err.downcast_ref::<NestedTooDeeply>().unwrap();
+        assert_eq!((err.idx, err.max_depth), (7, 2));
+
+        // The depth can't exceed the maximum
+        let bytes = nested(MAX_DEPTH as usize + 1);
+        assert!(Fields::new(bytes.as_bytes())
+            .max_depth(u32::MAX)
+            .any(|res| res.is_err()));
+    }
+
+    #[test]
+    fn top_level_fields() {
+        let bytes = br#"{ "a": {"id": 1}, "b" :"\"id\":2" ,"c":[{"id":3}], "id": 4 }"#;
+
+        let fields: Vec<_> = Fields::new(bytes).collect::<Result<_>>().unwrap();
+
+        assert_eq!(
+            fields,
+            [
+                (b"a".as_slice(), br#"{"id": 1}"#.as_slice()),
+                (b"b", br#""\"id\":2""#),
+                (b"c", br#"[{"id":3}]"#),
+                (b"id", b"4"),
+            ]
+        );
+    }
+}
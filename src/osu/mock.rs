@@ -0,0 +1,227 @@
+//! A mocked osu!api for unit and integration tests.
+
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Incoming, header::AUTHORIZATION, server::conn::http2, service::service_fn, Request,
+    Response, StatusCode,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::net::TcpListener;
+
+use crate::config::OsuConfig;
+
+const TOKEN: &str = r#"{"token_type":"Bearer","expires_in":86400,"access_token":"mock"}"#;
+
+/// Serves authorization requests and responds to all other requests with the
+/// body returned by `scores` which receives the request's query.
+pub async fn serve(scores: fn(&str) -> String) -> SocketAddr {
+    serve_requests(move |req| async move {
+        let body = match req.uri().path() {
+            "/oauth/token" => TOKEN.to_owned(),
+            _ => scores(req.uri().query().unwrap_or_default()),
+        };
+
+        Response::new(Full::new(Bytes::from(body)))
+    })
+    .await
+}
+
+/// Same as [`serve`] except that `scores` also returns how long to wait
+/// before responding.
+pub async fn serve_delayed(scores: fn(&str) -> (Duration, String)) -> SocketAddr {
+    serve_requests(move |req| async move {
+        let body = if req.uri().path() == "/oauth/token" {
+            TOKEN.to_owned()
+        } else {
+            let (delay, body) = scores(req.uri().query().unwrap_or_default());
+            tokio::time::sleep(delay).await;
+
+            body
+        };
+
+        Response::new(Full::new(Bytes::from(body)))
+    })
+    .await
+}
+
+/// Same as [`serve`] except that the access token `token{client_id}` is
+/// issued to each client id and `scores` receives the request's
+/// authorization header instead, responding with a status code and body.
+pub async fn serve_authorized(scores: fn(&str) -> (StatusCode, String)) -> SocketAddr {
+    serve_requests(move |req| async move {
+        let (status, body) = if req.uri().path() == "/oauth/token" {
+            let body = req.into_body().collect().await.unwrap().to_bytes();
+            let body = std::str::from_utf8(&body).unwrap();
+
+            let client_id = body
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("client_id="))
+                .unwrap();
+
+            let token = format!(
+                r#"{{"token_type":"Bearer","expires_in":86400,"access_token":"token{client_id}"}}"#
+            );
+
+            (StatusCode::OK, token)
+        } else {
+            let authorization = req.headers()[AUTHORIZATION].to_str().unwrap();
+
+            scores(authorization)
+        };
+
+        let mut res = Response::new(Full::new(Bytes::from(body)));
+        *res.status_mut() = status;
+
+        res
+    })
+    .await
+}
+
+/// A response of the scores endpoint of [`MockApi`].
+#[derive(Clone)]
+pub enum Reply {
+    /// Responds with scores of these ids and a cursor of the last one.
+    Scores(Vec<u64>),
+    /// Responds with this status code and an empty body.
+    Status(StatusCode),
+}
+
+#[derive(Default)]
+struct State {
+    /// Replies that are yet to be sent. The last one is repeated.
+    replies: VecDeque<Reply>,
+    /// Queries of all requests to the scores endpoint.
+    queries: Vec<String>,
+}
+
+/// Same as [`serve`] except that requests of the scores endpoint are
+/// responded to with scripted replies in order and their queries are kept.
+pub struct MockApi {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl MockApi {
+    pub async fn serve(replies: impl IntoIterator<Item = Reply>) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            replies: replies.into_iter().collect(),
+            queries: Vec::new(),
+        }));
+
+        let addr = serve_requests({
+            let state = Arc::clone(&state);
+
+            move |req| {
+                let res = Self::respond(&state, &req);
+
+                async move { res }
+            }
+        })
+        .await;
+
+        Self { addr, state }
+    }
+
+    fn respond<B>(state: &Mutex<State>, req: &Request<B>) -> Response<Full<Bytes>> {
+        if req.uri().path() == "/oauth/token" {
+            return Response::new(Full::new(Bytes::from_static(TOKEN.as_bytes())));
+        }
+
+        let mut state = state.lock().unwrap();
+        let query = req.uri().query().unwrap_or_default().to_owned();
+        state.queries.push(query);
+
+        let reply = if state.replies.len() > 1 {
+            state.replies.pop_front()
+        } else {
+            state.replies.front().cloned()
+        };
+
+        match reply {
+            Some(Reply::Scores(ids)) => Response::new(Full::new(Bytes::from(scores(&ids)))),
+            Some(Reply::Status(status)) => {
+                let mut res = Response::new(Full::new(Bytes::new()));
+                *res.status_mut() = status;
+
+                res
+            }
+            None => Response::new(Full::new(Bytes::from(scores(&[])))),
+        }
+    }
+
+    /// Queries of all requests to the scores endpoint so far.
+    pub fn queries(&self) -> Vec<String> {
+        self.state.lock().unwrap().queries.clone()
+    }
+
+    /// A config that targets this osu!api and doesn't retry.
+    pub fn config(&self) -> OsuConfig {
+        config(self.addr)
+    }
+}
+
+/// A response of the scores endpoint containing scores of the given ids.
+fn scores(ids: &[u64]) -> String {
+    let scores: Vec<_> = ids
+        .iter()
+        .map(|id| format!(r#"{{"id":{id},"ruleset_id":0,"user_id":2}}"#))
+        .collect();
+
+    let cursor = ids
+        .iter()
+        .max()
+        .map_or_else(|| "null".to_owned(), |id| format!(r#"{{"id":{id}}}"#));
+
+    format!(
+        r#"{{"scores":[{}],"cursor":{cursor},"cursor_string":null}}"#,
+        scores.join(",")
+    )
+}
+
+async fn serve_requests<F, Fut>(handle: F) -> SocketAddr
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = Response<Full<Bytes>>> + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let service = service_fn(move |req| {
+        let res = handle(req);
+
+        async move { Ok::<_, Infallible>(res.await) }
+    });
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let conn = http2::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service.clone());
+
+            tokio::spawn(conn);
+        }
+    });
+
+    addr
+}
+
+/// A config that targets the mocked osu!api at `addr` and doesn't retry.
+pub fn config(addr: SocketAddr) -> OsuConfig {
+    let config = format!(
+        "client_id = 0\n\
+        client_secret = \"\"\n\
+        base_url = \"http://{addr}\"\n\
+        max_retries = 0"
+    );
+
+    toml::from_str(&config).unwrap()
+}
@@ -1,8 +1,30 @@
+#[cfg(feature = "server")]
 mod authorization;
+#[cfg(feature = "server")]
+mod backoff;
+#[cfg(feature = "server")]
 mod client;
+#[cfg(feature = "server")]
+mod credentials;
+mod fields;
+#[cfg(feature = "server")]
+#[doc(hidden)]
+pub mod mock;
+#[cfg(feature = "server")]
+mod proxy;
+#[cfg(feature = "server")]
+mod ratelimit;
 mod scores;
+mod validate;
 
+#[cfg(feature = "server")]
+pub use self::client::{FetchResult, Osu};
+#[cfg(feature = "server")]
+pub(crate) use self::{backoff::Backoff, scores::text_message};
 pub use self::{
-    client::{FetchResult, Osu},
-    scores::{Deserializer as ScoresDeserializer, Score, Scores},
+    scores::{
+        deserialize_stream, drain_since, encode_batch, Deserializer as ScoresDeserializer,
+        ParseError, Score, Scores, ScoresExt,
+    },
+    validate::validate,
 };
@@ -0,0 +1,157 @@
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::{Duration, SystemTime},
+};
+
+use hyper::{
+    header::{HeaderValue, RETRY_AFTER},
+    HeaderMap,
+};
+
+const RATELIMIT_REMAINING: &str = "x-ratelimit-remaining";
+
+/// If fewer requests than this remain, requests will be delayed.
+const LOW_REMAINING: u64 = 10;
+
+/// Returned as error for `429 Too Many Requests` responses.
+#[derive(Debug)]
+pub struct RateLimited {
+    /// The delay requested by the `Retry-After` header, if specified and valid.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimited {
+    pub fn new(headers: &HeaderMap) -> Self {
+        let retry_after = headers
+            .get(RETRY_AFTER)
+            .and_then(|value| parse_retry_after(value, SystemTime::now()));
+
+        Self { retry_after }
+    }
+}
+
+impl Display for RateLimited {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("Received 429 error, try reducing your interval")?;
+
+        if let Some(retry_after) = self.retry_after {
+            write!(f, "; retry after {retry_after:?}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StdError for RateLimited {}
+
+/// Parses the value of a `Retry-After` header which is either an amount of
+/// seconds or an HTTP-date.
+fn parse_retry_after(value: &HeaderValue, now: SystemTime) -> Option<Duration> {
+    let value = value.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+
+    // A date in the past means we can retry right away
+    Some(date.duration_since(now).unwrap_or_default())
+}
+
+/// Keeps track of the remaining requests as specified by the osu!api's
+/// `X-RateLimit-Remaining` header.
+pub struct RateLimit {
+    remaining: AtomicU64,
+}
+
+impl RateLimit {
+    pub fn update(&self, headers: &HeaderMap) {
+        let remaining = headers
+            .get(RATELIMIT_REMAINING)
+            .and_then(|value| value.to_str().ok()?.parse().ok());
+
+        if let Some(remaining) = remaining {
+            self.remaining.store(remaining, Relaxed);
+        }
+    }
+
+    /// How long to wait before the next request.
+    ///
+    /// The closer the remaining requests get to zero, the longer the delay
+    /// until it reaches a minute which is the osu!api's ratelimit window.
+    pub fn delay(&self) -> Option<Duration> {
+        let remaining = self.remaining.load(Relaxed);
+
+        (remaining < LOW_REMAINING)
+            .then(|| Duration::from_secs(60 * (LOW_REMAINING - remaining) / LOW_REMAINING))
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            remaining: AtomicU64::new(u64::MAX),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_after_seconds() {
+        let value = HeaderValue::from_static("120");
+
+        assert_eq!(
+            parse_retry_after(&value, SystemTime::now()),
+            Some(Duration::from_mins(2))
+        );
+    }
+
+    #[test]
+    fn retry_after_date() {
+        let value = HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT");
+        let now = httpdate::parse_http_date("Wed, 21 Oct 2015 07:27:30 GMT").unwrap();
+
+        assert_eq!(
+            parse_retry_after(&value, now),
+            Some(Duration::from_secs(30))
+        );
+
+        let later = now + Duration::from_mins(1);
+        assert_eq!(parse_retry_after(&value, later), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_malformed() {
+        let value = HeaderValue::from_static("soon");
+        assert_eq!(parse_retry_after(&value, SystemTime::now()), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, value);
+        assert!(RateLimited::new(&headers).retry_after.is_none());
+    }
+
+    #[test]
+    fn remaining() {
+        let ratelimit = RateLimit::default();
+        assert_eq!(ratelimit.delay(), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from_static("500"));
+        ratelimit.update(&headers);
+        assert_eq!(ratelimit.delay(), None);
+
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from_static("5"));
+        ratelimit.update(&headers);
+        assert_eq!(ratelimit.delay(), Some(Duration::from_secs(30)));
+
+        headers.insert(RATELIMIT_REMAINING, HeaderValue::from_static("0"));
+        ratelimit.update(&headers);
+        assert_eq!(ratelimit.delay(), Some(Duration::from_mins(1)));
+    }
+}
@@ -1,12 +1,80 @@
-use bytes::Bytes;
-use eyre::{Context as _, ContextCompat, Result};
-use memchr::memmem;
-use tokio_tungstenite::tungstenite::Message;
+use bytes::{BufMut, Bytes, BytesMut};
+use eyre::{Context as _, ContextCompat, Report, Result};
+use futures_util::{stream, Stream, StreamExt};
+#[cfg(feature = "server")]
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
 
-use std::{cmp::Ordering, collections::BTreeSet, ops::ControlFlow};
+use std::{
+    cmp::Ordering,
+    collections::BTreeSet,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{ControlFlow, Range},
+};
+
+use super::fields::{is_whitespace, Fields, NestedTooDeeply, UnexpectedEnd, MAX_DEPTH};
+#[cfg(feature = "server")]
+use {crate::metrics::METRICS, std::sync::atomic::Ordering::Relaxed};
 
 pub type Scores = BTreeSet<Score>;
 
+/// Convenience methods for [`Scores`].
+pub trait ScoresExt {
+    /// The id of the oldest score, i.e. the lowest id.
+    fn oldest_id(&self) -> Option<u64>;
+
+    /// The id of the newest score, i.e. the highest id.
+    fn latest_id(&self) -> Option<u64>;
+}
+
+impl ScoresExt for Scores {
+    fn oldest_id(&self) -> Option<u64> {
+        self.first().map(Score::id)
+    }
+
+    fn latest_id(&self) -> Option<u64> {
+        self.last().map(Score::id)
+    }
+}
+
+/// Removes all scores with an id greater than `id` from `scores` and returns
+/// them.
+///
+/// Since the set is ordered by score id, it's split rather than filtered
+/// score by score.
+pub fn drain_since(scores: &mut Scores, id: u64) -> Scores {
+    match id.checked_add(1) {
+        Some(next) => scores.split_off(&Score::only_id(next)),
+        None => Scores::new(),
+    }
+}
+
+/// Encodes scores into a single buffer.
+///
+/// For each score, its length is written as `u32` in little-endian, followed
+/// by the score's JSON bytes.
+pub fn encode_batch<B: AsRef<[u8]>>(scores: impl IntoIterator<Item = B>) -> Bytes {
+    let mut buf = BytesMut::new();
+
+    for score in scores {
+        let bytes = score.as_ref();
+        let len = u32::try_from(bytes.len()).expect("score exceeds u32::MAX bytes");
+        buf.put_u32_le(len);
+        buf.put_slice(bytes);
+    }
+
+    buf.freeze()
+}
+
+/// Wraps JSON bytes into a text websocket message without copying them.
+///
+/// The osu!api responds with UTF-8 but if the bytes are invalid nonetheless,
+/// a binary message is created instead.
+#[cfg(feature = "server")]
+pub(crate) fn text_message(bytes: Bytes) -> Message {
+    Utf8Bytes::try_from(bytes.clone()).map_or(Message::Binary(bytes), Message::Text)
+}
+
 /// Deserializes the osu!api response.
 ///
 /// The format is expected to be of the following form:
@@ -22,106 +90,460 @@ pub type Scores = BTreeSet<Score>;
 /// `cursor_string` works the same as `cursor` but with an encoded number.
 /// Since the cursors' number actually is the *newest* score id, we don't
 /// really want that since we're interested in the *oldest* one. Hence, we skip
-/// deserializing them entirely and only handle scores; then use the scores'
-/// oldest id as cursor.
+/// deserializing them entirely and only handle scores; then derive the cursor
+/// from the scores' ids. Only if the `cursor_source` of the config says so,
+/// the `cursor_string` is read through [`Deserializer::cursor_string`].
 pub struct Deserializer {
     bytes: Bytes,
     idx: usize,
+    max_scores: usize,
+    max_depth: u32,
+    /// Index of the scores array if it's known beforehand.
+    array_offset: Option<usize>,
 }
 
 impl Deserializer {
     pub const fn new(bytes: Bytes) -> Self {
-        Self { bytes, idx: 0 }
+        Self {
+            bytes,
+            idx: 0,
+            max_scores: usize::MAX,
+            max_depth: MAX_DEPTH,
+            array_offset: None,
+        }
     }
 
-    pub fn deserialize(mut self, scores: &mut Scores) -> Result<()> {
-        const SCORES: &[u8] = br#""scores":"#;
+    /// Deserializes the scores array that starts at `offset` instead of
+    /// searching for the `"scores"` key, e.g. because its location is already
+    /// known from a previous pass.
+    ///
+    /// `offset` must point at the array's opening bracket or at whitespace
+    /// preceding it.
+    pub fn from_array_offset(bytes: Bytes, offset: usize) -> Result<Self, ParseError> {
+        match bytes.get(offset) {
+            Some(&byte) if byte == b'[' || is_whitespace(byte) => {}
+            Some(_) => return Err(ParseError::ExpectedArray { offset }),
+            None => {
+                return Err(ParseError::UnexpectedEnd {
+                    offset: bytes.len(),
+                })
+            }
+        }
+
+        Ok(Self {
+            array_offset: Some(offset),
+            ..Self::new(bytes)
+        })
+    }
 
-        let start = memmem::find(&self.bytes, SCORES).context("Missing scores")?;
-        self.idx = start + SCORES.len();
+    /// Fail if the response contains more than `max_scores` score objects.
+    ///
+    /// In that case, none of the scores will be added.
+    #[must_use]
+    pub const fn max_scores(mut self, max_scores: usize) -> Self {
+        self.max_scores = max_scores;
 
-        self.deserialize_scores(scores)
-            .with_context(|| format!("Failed to deserialize scores; Bytes:\n{:?}", self.bytes))
+        self
     }
 
-    fn deserialize_scores(&mut self, scores: &mut Scores) -> Result<()> {
-        let start = Self::skip_whitespace_until(&self.bytes[self.idx..], |byte| byte == b'[')
-            .context("Failed to skip until opening bracket")?;
+    /// Fail if objects and arrays within a score object are nested more than
+    /// `max_depth` deep, protecting against degenerate responses.
+    ///
+    /// Values are never allowed to be nested more than 128 deep.
+    #[must_use]
+    pub const fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
 
-        self.idx += start + 1;
+        self
+    }
+
+    /// Returns the response's `cursor_string` which can be passed to the
+    /// osu!api to fetch the next page.
+    pub fn cursor_string(&self) -> Result<Option<Box<str>>> {
+        let start = memchr::memchr(b'{', &self.bytes).context("Missing opening brace")?;
 
-        match self.bytes[self.idx] {
-            b'{' => {}
-            b']' => {
-                self.idx += 1;
+        for field in Fields::new(&self.bytes[start..]) {
+            let (key, value) = field.context("Invalid response")?;
 
-                return Ok(());
+            if key == b"cursor_string" {
+                return serde_json::from_slice(value).context("Invalid cursor_string");
             }
-            _ => bail!("Expected opening brace or closing bracket"),
         }
 
-        let mut parentheses = memchr::memchr2_iter(b'{', b'}', &self.bytes[self.idx..]);
+        Ok(None)
+    }
 
-        // The first opening brace is already handled. We don't want to skip it
-        // via index offset because all future iterator items would be affected
-        // by that offset too which would make things more complicated.
-        parentheses.next();
+    pub fn deserialize(self, scores: &mut Scores) -> Result<()> {
+        self.deserialize_inner(scores, false).map(|_| ())
+    }
 
-        let mut init = 0;
-        let mut prev_depth = 1;
-        let mut prev_idx = init;
-        let mut id = None;
+    /// Same as [`Deserializer::deserialize`] but instead of failing entirely,
+    /// score objects that can't be deserialized, e.g. due to a missing id, are
+    /// skipped. Unexpected bytes between score objects are skipped as well,
+    /// continuing with the next score object.
+    ///
+    /// Returns the amount of skipped score objects.
+    pub fn deserialize_lenient(self, scores: &mut Scores) -> Result<usize> {
+        self.deserialize_inner(scores, true)
+    }
 
-        for i in parentheses {
-            let curr_depth = match self.bytes[self.idx + i] {
-                b'{' => prev_depth + 1,
-                b'}' => prev_depth - 1,
-                _ => unreachable!(),
-            };
+    /// Same as [`Deserializer::deserialize`] but instead of collecting the
+    /// scores, each score is passed to `on_score` as soon as it's been
+    /// deserialized, in the order of the response.
+    ///
+    /// Scores preceding an error have been passed already.
+    ///
+    /// The server itself doesn't broadcast scores this way because it sends
+    /// the scores of each poll in ascending order of their id, which is only
+    /// known once the whole response has been deserialized.
+    pub fn deserialize_each(mut self, on_score: impl FnMut(Score)) -> Result<()> {
+        self.deserialize_scores(on_score, false)
+            .map(|_| ())
+            .map_err(|err| self.wrap_err(err))
+    }
+
+    fn deserialize_inner(mut self, scores: &mut Scores, lenient: bool) -> Result<usize> {
+        // Only add scores if the whole response could be deserialized
+        let mut new_scores = Scores::new();
+
+        let skipped = self
+            .deserialize_scores(
+                |score| {
+                    new_scores.insert(score);
+                },
+                lenient,
+            )
+            .map_err(|err| self.wrap_err(err))?;
+
+        scores.append(&mut new_scores);
+
+        Ok(skipped)
+    }
+
+    fn wrap_err(&self, err: ParseError) -> Report {
+        let excerpt = Self::excerpt(&self.bytes, err.offset().unwrap_or(0));
+
+        Report::new(err).wrap_err(format!("Failed to deserialize scores near {excerpt:?}"))
+    }
+
+    /// Returns a few bytes around `offset` so that errors stay readable and
+    /// don't contain the entire response.
+    fn excerpt(bytes: &[u8], offset: usize) -> String {
+        const RADIUS: usize = 32;
+
+        let start = offset.saturating_sub(RADIUS).min(bytes.len());
+        let end = offset.saturating_add(RADIUS).min(bytes.len());
+
+        String::from_utf8_lossy(&bytes[start..end]).into_owned()
+    }
+
+    /// Returns the index of the `"scores"` value.
+    ///
+    /// Only keys of the top-level object are considered so that occurrences
+    /// within nested objects or string values are skipped.
+    fn find_scores(bytes: &[u8]) -> Result<usize, ParseError> {
+        let start = memchr::memchr(b'{', bytes).ok_or(ParseError::MissingScores)?;
+
+        let idx = Fields::new(&bytes[start..])
+            .value_start(b"scores")
+            .map_err(|err| {
+                if err.is::<UnexpectedEnd>() {
+                    ParseError::UnexpectedEnd {
+                        offset: bytes.len(),
+                    }
+                } else {
+                    ParseError::InvalidJson {
+                        offset: start,
+                        reason: err.to_string().into(),
+                    }
+                }
+            })?
+            .ok_or(ParseError::MissingScores)?;
+
+        Ok(start + idx)
+    }
+
+    fn deserialize_scores(
+        &mut self,
+        mut on_score: impl FnMut(Score),
+        lenient: bool,
+    ) -> Result<usize, ParseError> {
+        self.idx = match self.array_offset {
+            Some(offset) => offset,
+            None => Self::find_scores(&self.bytes)?,
+        };
+        self.skip_whitespace();
+        self.expect_more()?;
+
+        // Occasionally returned instead of an empty array
+        if let Some(rest) = self.bytes[self.idx..].strip_prefix(b"null") {
+            match rest.first() {
+                Some(&byte) if matches!(byte, b',' | b'}') || is_whitespace(byte) => {
+                    self.idx += 4;
+
+                    return Ok(0);
+                }
+                Some(_) => {}
+                None => {
+                    return Err(ParseError::UnexpectedEnd {
+                        offset: self.bytes.len(),
+                    })
+                }
+            }
+        }
+
+        let start = Self::skip_whitespace_until(&self.bytes[self.idx..], |byte| byte == b'[')
+            .map_err(|_| ParseError::ExpectedArray { offset: self.idx })?;
+
+        self.idx += start + 1;
+        self.skip_whitespace();
+        self.expect_more()?;
+
+        if self.bytes.get(self.idx) == Some(&b']') {
+            self.idx += 1;
+
+            return Ok(0);
+        }
+
+        let mut skipped = 0;
+        let mut count = 0;
+
+        loop {
+            self.skip_whitespace();
+            self.expect_more()?;
+            let offset = self.idx;
 
-            if id.is_none() && prev_depth == 1 {
-                const ID: &[u8] = br#""id":"#;
+            if self.bytes[offset] != b'{' {
+                return Err(ParseError::ExpectedObject { offset });
+            }
 
-                let slice = &self.bytes[self.idx + prev_idx..self.idx + i];
+            count += 1;
 
-                if let Some(id_idx) = memmem::find(slice, ID) {
-                    let n = Self::peek_u64(&slice[id_idx + ID.len()..])
-                        .context("Failed to peek u64")?;
+            if count > self.max_scores {
+                return Err(ParseError::TooManyScores {
+                    offset,
+                    max: self.max_scores,
+                });
+            }
 
-                    id = Some(n);
+            match self.next_score()? {
+                Ok(score) => on_score(score),
+                Err(err) if lenient => {
+                    warn!(?err, offset, "Skipping score");
+                    skipped += 1;
+                }
+                Err(err) => {
+                    return Err(ParseError::InvalidScore {
+                        offset,
+                        reason: format!("{err:#}").into(),
+                    })
                 }
             }
 
-            match curr_depth {
-                1 => {
-                    if prev_depth == 0 {
-                        init = i;
+            self.skip_whitespace();
+
+            match self.bytes.get(self.idx) {
+                Some(b',') => self.idx += 1,
+                Some(b']') => {
+                    self.idx += 1;
+
+                    break;
+                }
+                Some(_) => {
+                    let offset = self.idx;
+                    #[cfg(feature = "server")]
+                    METRICS.missing_separators.fetch_add(1, Relaxed);
+
+                    if !lenient {
+                        return Err(ParseError::ExpectedCommaOrBracket { offset });
                     }
 
-                    prev_idx = i;
+                    warn!(offset, "Skipping unexpected bytes after score");
+
+                    if !self.resync()? {
+                        break;
+                    }
                 }
-                0 => {
-                    let bytes = self.bytes.slice(self.idx + init..=self.idx + i);
+                None => return Err(ParseError::UnexpectedEnd { offset: self.idx }),
+            }
+        }
+
+        Ok(skipped)
+    }
 
-                    let id = id
-                        .take()
-                        .with_context(|| format!("Missing id within bytes {bytes:?}"))?;
+    /// Moves the index to the next object of the scores array, skipping any
+    /// other values or bytes in between.
+    ///
+    /// Returns `false` if the array ends before another object does begin, in
+    /// which case the index is moved past the closing bracket.
+    fn resync(&mut self) -> Result<bool, ParseError> {
+        let mut depth = 0_usize;
+        let mut in_string = false;
+        let mut escaped = false;
 
-                    scores.insert(Score { bytes, id });
+        while let Some(&byte) = self.bytes.get(self.idx) {
+            if in_string {
+                if escaped {
+                    escaped = false;
+                } else if byte == b'\\' {
+                    escaped = true;
+                } else if byte == b'"' {
+                    in_string = false;
+                }
+            } else {
+                match byte {
+                    b'"' => in_string = true,
+                    b'{' if depth == 0 => return Ok(true),
+                    b']' if depth == 0 => {
+                        self.idx += 1;
 
-                    match self.bytes[self.idx + i + 1] {
-                        b',' => {}
-                        b']' => break,
-                        _ => bail!("Expected comma or closing bracket"),
+                        return Ok(false);
                     }
+                    b'[' | b'{' => depth += 1,
+                    b']' | b'}' => depth = depth.saturating_sub(1),
+                    _ => {}
                 }
-                _ => {}
             }
 
-            prev_depth = curr_depth;
+            self.idx += 1;
         }
 
-        Ok(())
+        Err(ParseError::UnexpectedEnd { offset: self.idx })
+    }
+
+    /// Fails if the current index is at the end of the bytes, e.g. because
+    /// the response was truncated.
+    const fn expect_more(&self) -> Result<(), ParseError> {
+        if self.idx < self.bytes.len() {
+            Ok(())
+        } else {
+            Err(ParseError::UnexpectedEnd { offset: self.idx })
+        }
+    }
+
+    /// Deserializes a single score object.
+    #[cfg(feature = "serde")]
+    fn deserialize_score(bytes: Bytes) -> Result<Score> {
+        Self::new(bytes).next_score()?
+    }
+
+    /// Walks the top-level fields of the score object starting at the current
+    /// index so that keys within nested objects or string values are not
+    /// considered.
+    ///
+    /// Each score is only scanned once. The outer error indicates that the
+    /// JSON itself is invalid while the inner error indicates that the score
+    /// object is not as expected, e.g. due to a missing id, in which case the
+    /// index is still moved past the object.
+    fn next_score(&mut self) -> Result<Result<Score>, ParseError> {
+        let bytes = &self.bytes[self.idx..];
+        let mut fields = Fields::new(bytes).max_depth(self.max_depth);
+        let mut parsed = ScoreFields::default();
+        let mut res = Ok(());
+
+        for field in &mut fields {
+            let (key, value) = field.map_err(|err| {
+                if err.is::<UnexpectedEnd>() {
+                    ParseError::UnexpectedEnd {
+                        offset: self.bytes.len(),
+                    }
+                } else if let Some(err) = err.downcast_ref::<NestedTooDeeply>() {
+                    ParseError::NestedTooDeeply {
+                        offset: self.idx + err.idx,
+                        max: err.max_depth,
+                    }
+                } else {
+                    ParseError::InvalidJson {
+                        offset: self.idx,
+                        reason: err.to_string().into(),
+                    }
+                }
+            })?;
+
+            if res.is_ok() {
+                res = parsed.parse(key, value, bytes);
+            }
+        }
+
+        let end = self.idx + fields.end();
+        let bytes = self.bytes.slice(self.idx..=end);
+        self.idx = end + 1;
+
+        Ok(res.and_then(|()| parsed.into_score(bytes)))
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.idx)
+            .is_some_and(|&byte| is_whitespace(byte))
+        {
+            self.idx += 1;
+        }
+    }
+
+    fn peek_bool(bytes: &[u8]) -> Result<Option<bool>> {
+        match bytes {
+            b"true" => Ok(Some(true)),
+            b"false" => Ok(Some(false)),
+            b"null" => Ok(None),
+            _ => bail!("Expected boolean"),
+        }
+    }
+
+    /// Returns the content of a string value without its quotes.
+    fn peek_str(bytes: &[u8]) -> Result<Option<&[u8]>> {
+        if bytes == b"null" {
+            return Ok(None);
+        }
+
+        let content = bytes
+            .strip_prefix(b"\"")
+            .and_then(|bytes| bytes.strip_suffix(b"\""))
+            .context("Expected string")?;
+
+        std::str::from_utf8(content).context("Invalid utf-8")?;
+
+        Ok(Some(content))
+    }
+
+    /// Returns the range of `slice` within `bytes`.
+    fn range_within(bytes: &[u8], slice: &[u8]) -> Range<usize> {
+        let start = slice.as_ptr() as usize - bytes.as_ptr() as usize;
+
+        start..start + slice.len()
+    }
+
+    /// Peeks the `"id"` field of a nested object such as `"user"`.
+    fn peek_object_id(bytes: &[u8]) -> Result<Option<u64>> {
+        if bytes == b"null" {
+            return Ok(None);
+        }
+
+        ensure!(bytes.first() == Some(&b'{'), "Expected object");
+
+        for field in Fields::new(bytes) {
+            if let (b"id", value) = field? {
+                return Self::peek_u64(value).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn peek_mode(bytes: &[u8]) -> Result<Option<u8>> {
+        if bytes == b"null" {
+            return Ok(None);
+        }
+
+        // Signed so that negative values are reported as out of range
+        let mode = Self::peek_i64(bytes)?;
+
+        u8::try_from(mode)
+            .ok()
+            .filter(|mode| *mode <= 3)
+            .map(Some)
+            .with_context(|| format!("Expected value between 0 and 3, got {mode}"))
     }
 
     fn skip_whitespace_until(bytes: &[u8], until: fn(u8) -> bool) -> Result<usize> {
@@ -129,39 +551,320 @@ impl Deserializer {
             .iter()
             .enumerate()
             .try_fold((), |(), (idx, &byte)| match byte {
-                b' ' => ControlFlow::Continue(()),
+                _ if is_whitespace(byte) => ControlFlow::Continue(()),
                 _ if until(byte) => ControlFlow::Break(Ok(idx)),
                 _ => ControlFlow::Break(Err(eyre!("Unexpected character `{}`", byte as char))),
             })
             .break_value()
-            .context("`until` condition never met")?
+            .context("Unexpected end of input")?
     }
 
     fn peek_u64(bytes: &[u8]) -> Result<u64> {
         let start = Self::skip_whitespace_until(bytes, |byte| byte.is_ascii_digit())
             .context("Failed to skip until digit")?;
 
-        let n = bytes[start..]
+        Self::fold_digits(&bytes[start..]).context("Number exceeds u64")
+    }
+
+    fn peek_i64(bytes: &[u8]) -> Result<i64> {
+        let start =
+            Self::skip_whitespace_until(bytes, |byte| byte == b'-' || byte.is_ascii_digit())
+                .context("Failed to skip until digit or minus")?;
+
+        let Some(digits) = bytes[start..].strip_prefix(b"-") else {
+            let n = Self::fold_digits(&bytes[start..]).context("Number exceeds u64")?;
+
+            return i64::try_from(n).context("Number exceeds i64");
+        };
+
+        ensure!(
+            digits.first().is_some_and(u8::is_ascii_digit),
+            "Expected digit after minus"
+        );
+
+        Self::fold_digits(digits)
+            .and_then(|n| 0_i64.checked_sub_unsigned(n))
+            .context("Number exceeds i64")
+    }
+
+    /// Folds the leading digits of `bytes` into a number.
+    ///
+    /// Returns `None` on overflow or if there are more digits than any `u64`
+    /// could have, in which case at most one more digit is consumed.
+    fn fold_digits(bytes: &[u8]) -> Option<u64> {
+        /// Number of digits of `u64::MAX`.
+        const MAX_DIGITS: usize = 20;
+
+        let len = bytes
             .iter()
-            .copied()
-            .take_while(u8::is_ascii_digit)
-            .fold(0, |n, byte| n * 10 + u64::from(byte & 0xF));
+            .take(MAX_DIGITS + 1)
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+
+        if len > MAX_DIGITS {
+            return None;
+        }
+
+        bytes[..len].iter().try_fold(0_u64, |n, byte| {
+            n.checked_mul(10)?.checked_add(u64::from(byte & 0xF))
+        })
+    }
+
+    fn peek_f64(bytes: &[u8]) -> Result<Option<f64>> {
+        if bytes == b"null" {
+            return Ok(None);
+        }
+
+        // Rust's float parsing would also accept e.g. `inf`, `NaN`, or `1.`
+        ensure!(Self::is_number(bytes), "Expected number");
+
+        let n = std::str::from_utf8(bytes)
+            .context("Invalid utf-8")?
+            .parse()
+            .context("Invalid float")?;
+
+        Ok(Some(n))
+    }
+
+    /// Whether `bytes` is a number as specified by JSON, i.e. an integer
+    /// without leading zeros, optionally followed by a fractional part and
+    /// an exponent.
+    fn is_number(bytes: &[u8]) -> bool {
+        fn digits(bytes: &[u8]) -> usize {
+            bytes
+                .iter()
+                .take_while(|byte| byte.is_ascii_digit())
+                .count()
+        }
+
+        let bytes = bytes.strip_prefix(b"-").unwrap_or(bytes);
+
+        let rest = match digits(bytes) {
+            0 => return false,
+            1 => &bytes[1..],
+            _ if bytes[0] == b'0' => return false,
+            len => &bytes[len..],
+        };
+
+        let rest = match rest.strip_prefix(b".") {
+            Some(fraction) => match digits(fraction) {
+                0 => return false,
+                len => &fraction[len..],
+            },
+            None => rest,
+        };
+
+        let Some(exponent) = rest.strip_prefix(b"e").or_else(|| rest.strip_prefix(b"E")) else {
+            return rest.is_empty();
+        };
+
+        let exponent = exponent
+            .strip_prefix(b"+")
+            .or_else(|| exponent.strip_prefix(b"-"))
+            .unwrap_or(exponent);
+
+        let len = digits(exponent);
+
+        len > 0 && len == exponent.len()
+    }
+}
+
+/// Deserializes each item of `bodies` as a full osu!api response and yields
+/// their scores, e.g. to process cached responses without the network.
+///
+/// Scores of each response are yielded in ascending order of their id. If a
+/// response can't be deserialized, an error is yielded in place of its scores
+/// and the stream continues with the next response.
+pub fn deserialize_stream(bodies: impl Stream<Item = Bytes>) -> impl Stream<Item = Result<Score>> {
+    bodies.flat_map(|body| {
+        let mut scores = Scores::new();
+
+        let err = Deserializer::new(body).deserialize(&mut scores).err();
+
+        stream::iter(scores.into_iter().map(Ok).chain(err.map(Err)))
+    })
+}
+
+/// Why a response could not be deserialized.
+///
+/// Offsets are byte indices into the response.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ParseError {
+    /// The response does not contain a `"scores"` key.
+    MissingScores,
+    /// The value of `"scores"` is not an array.
+    ExpectedArray { offset: usize },
+    /// An element of the scores array is not an object.
+    ExpectedObject { offset: usize },
+    /// A score object is followed by neither a comma nor a closing bracket.
+    ExpectedCommaOrBracket { offset: usize },
+    /// The response contains more than `max` score objects.
+    TooManyScores { offset: usize, max: usize },
+    /// The score object contains an object or array at `offset` that is
+    /// nested more than `max` deep.
+    NestedTooDeeply { offset: usize, max: u32 },
+    /// The score object at `offset` is not valid JSON.
+    InvalidJson { offset: usize, reason: Box<str> },
+    /// The score object at `offset` is valid JSON but not a valid score, e.g.
+    /// due to a missing id.
+    InvalidScore { offset: usize, reason: Box<str> },
+    /// The response ends at `offset` before the scores array does, e.g.
+    /// because it was truncated.
+    UnexpectedEnd { offset: usize },
+}
+
+impl ParseError {
+    pub const fn offset(&self) -> Option<usize> {
+        match self {
+            Self::MissingScores => None,
+            Self::ExpectedArray { offset }
+            | Self::ExpectedObject { offset }
+            | Self::ExpectedCommaOrBracket { offset }
+            | Self::TooManyScores { offset, .. }
+            | Self::NestedTooDeeply { offset, .. }
+            | Self::InvalidJson { offset, .. }
+            | Self::InvalidScore { offset, .. }
+            | Self::UnexpectedEnd { offset } => Some(*offset),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingScores => f.write_str("missing scores"),
+            Self::ExpectedArray { offset } => write!(f, "expected array at offset {offset}"),
+            Self::ExpectedObject { offset } => {
+                write!(f, "expected opening brace at offset {offset}")
+            }
+            Self::ExpectedCommaOrBracket { offset } => {
+                write!(f, "expected comma or closing bracket at offset {offset}")
+            }
+            Self::TooManyScores { offset, max } => {
+                write!(f, "more than {max} scores at offset {offset}")
+            }
+            Self::NestedTooDeeply { offset, max } => {
+                write!(f, "nested deeper than {max} at offset {offset}")
+            }
+            Self::InvalidJson { offset, reason } => {
+                write!(f, "invalid JSON in score at offset {offset}: {reason}")
+            }
+            Self::InvalidScore { offset, reason } => {
+                write!(f, "invalid score at offset {offset}: {reason}")
+            }
+            Self::UnexpectedEnd { offset } => {
+                write!(f, "unexpected end of input at offset {offset}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The fields of a score that are deserialized.
+#[derive(Default)]
+struct ScoreFields {
+    id: Option<u64>,
+    mode: Option<u8>,
+    user_id: Option<u64>,
+    beatmap_id: Option<u64>,
+    pp: Option<f64>,
+    ended_at: Option<Range<usize>>,
+    passed: Option<bool>,
+    rank_global: Option<u64>,
+    legacy_score_id: Option<u64>,
+}
+
+impl ScoreFields {
+    /// `score` are the score's bytes that contain `value`.
+    fn parse(&mut self, key: &[u8], value: &[u8], score: &[u8]) -> Result<()> {
+        match key {
+            b"id" => self.id = Some(Deserializer::peek_u64(value).context("Failed to peek u64")?),
+            b"ruleset_id" => {
+                self.mode = Deserializer::peek_mode(value).context("Invalid ruleset id")?;
+            }
+            b"user" => {
+                self.user_id = Deserializer::peek_object_id(value).context("Invalid user")?;
+            }
+            b"beatmap_id" if value != b"null" => {
+                self.beatmap_id =
+                    Some(Deserializer::peek_u64(value).context("Invalid beatmap id")?);
+            }
+            // The top-level `"beatmap_id"` is preferred if both are present
+            b"beatmap" if self.beatmap_id.is_none() => {
+                self.beatmap_id = Deserializer::peek_object_id(value).context("Invalid beatmap")?;
+            }
+            b"ended_at" => {
+                self.ended_at = Deserializer::peek_str(value)
+                    .context("Invalid ended_at")?
+                    .map(|ended_at| Deserializer::range_within(score, ended_at));
+            }
+            b"passed" => self.passed = Deserializer::peek_bool(value).context("Invalid passed")?,
+            b"pp" => self.pp = Deserializer::peek_f64(value).context("Invalid pp")?,
+            b"rank_global" if value != b"null" => {
+                self.rank_global =
+                    Some(Deserializer::peek_u64(value).context("Invalid global rank")?);
+            }
+            b"legacy_score_id" if value != b"null" => {
+                self.legacy_score_id =
+                    Some(Deserializer::peek_u64(value).context("Invalid legacy score id")?);
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
 
-        Ok(n)
+    fn into_score(self, bytes: Bytes) -> Result<Score> {
+        Ok(Score {
+            id: self.id.context("Missing id")?,
+            bytes,
+            mode: self.mode,
+            user_id: self.user_id,
+            beatmap_id: self.beatmap_id,
+            pp: self.pp,
+            ended_at: self.ended_at,
+            passed: self.passed,
+            rank_global: self.rank_global,
+            legacy_score_id: self.legacy_score_id,
+        })
     }
 }
 
+#[derive(Clone)]
 #[cfg_attr(test, derive(Debug))]
 pub struct Score {
     bytes: Bytes,
     pub id: u64,
+    mode: Option<u8>,
+    user_id: Option<u64>,
+    beatmap_id: Option<u64>,
+    pp: Option<f64>,
+    /// Range of the `"ended_at"` value within `bytes`.
+    ended_at: Option<Range<usize>>,
+    passed: Option<bool>,
+    rank_global: Option<u64>,
+    legacy_score_id: Option<u64>,
 }
 
 impl Score {
+    /// A placeholder without any JSON bytes that is only meant to compare
+    /// with other scores, e.g. to get a range of a [`Scores`] set.
+    ///
+    /// Placeholders are never sent to clients.
     pub const fn only_id(id: u64) -> Self {
         Self {
             bytes: Bytes::new(),
             id,
+            mode: None,
+            user_id: None,
+            beatmap_id: None,
+            pp: None,
+            ended_at: None,
+            passed: None,
+            rank_global: None,
+            legacy_score_id: None,
         }
     }
 
@@ -169,9 +872,146 @@ impl Score {
         self.id
     }
 
+    /// Whether the score was created through [`Score::only_id`].
+    pub const fn is_placeholder(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// The score's ruleset id i.e. `0` for osu!standard, `1` for taiko, `2` for
+    /// catch, and `3` for mania.
+    ///
+    /// `None` if the score did not contain a `"ruleset_id"` field.
+    pub const fn mode(&self) -> Option<u8> {
+        self.mode
+    }
+
+    /// The id of the user that set the score.
+    ///
+    /// `None` if the score did not contain a `"user"` object with an `"id"`.
+    pub const fn user_id(&self) -> Option<u64> {
+        self.user_id
+    }
+
+    /// The id of the beatmap on which the score was set.
+    ///
+    /// `None` if the score contained neither a `"beatmap_id"` nor a
+    /// `"beatmap"` object with an `"id"`.
+    pub const fn beatmap_id(&self) -> Option<u64> {
+        self.beatmap_id
+    }
+
+    /// The score's pp value.
+    ///
+    /// `None` if the score's `"pp"` is `null` or missing, e.g. because the
+    /// map is not ranked.
+    ///
+    /// The value is the `f64` closest to the response's decimal number and is
+    /// not rounded any further so e.g. `727.27` is only represented up to
+    /// `f64`'s precision of roughly 15 significant digits.
+    pub const fn pp(&self) -> Option<f64> {
+        self.pp
+    }
+
+    /// Whether the score passed the map.
+    ///
+    /// `None` if the score did not contain a `"passed"` boolean.
+    pub const fn passed(&self) -> Option<bool> {
+        self.passed
+    }
+
+    /// The score's position on the beatmap's global leaderboard, starting
+    /// at 1.
+    ///
+    /// `None` if the score's `"rank_global"` is `null` or missing which is
+    /// the case for most scores of the osu!api.
+    pub const fn rank_global(&self) -> Option<u64> {
+        self.rank_global
+    }
+
+    /// The id of the score on the legacy osu!stable servers.
+    ///
+    /// Unrelated to [`Score::id`] and `None` if the score's
+    /// `"legacy_score_id"` is `null` or missing, e.g. because the score was
+    /// set on lazer.
+    pub const fn legacy_score_id(&self) -> Option<u64> {
+        self.legacy_score_id
+    }
+
+    /// The ISO-8601 timestamp at which the score was set, e.g.
+    /// `2025-01-02T12:34:56Z`.
+    ///
+    /// `None` if the score did not contain an `"ended_at"` string.
+    pub fn ended_at(&self) -> Option<&str> {
+        let range = self.ended_at.clone()?;
+
+        // Validated during deserialization
+        std::str::from_utf8(&self.bytes[range]).ok()
+    }
+
+    /// The buffer of the score's raw JSON bytes.
+    ///
+    /// Cloning it is cheap and does not copy the bytes so it can be forwarded
+    /// through other transports. This is the same buffer that
+    /// [`Score::as_message`] wraps.
+    pub const fn raw(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Creates a JSON object that only contains the given top-level fields of
+    /// the score, in their original order.
+    ///
+    /// Fields that the score does not contain are omitted.
+    pub fn project(&self, fields: &BTreeSet<Box<str>>) -> Bytes {
+        let mut buf = BytesMut::with_capacity(self.bytes.len());
+        buf.put_u8(b'{');
+
+        // The bytes were validated during deserialization
+        for (key, value) in Fields::new(&self.bytes).flatten() {
+            let Ok(key_str) = std::str::from_utf8(key) else {
+                continue;
+            };
+
+            if !fields.contains(key_str) {
+                continue;
+            }
+
+            if buf.len() > 1 {
+                buf.put_u8(b',');
+            }
+
+            buf.put_u8(b'"');
+            buf.put_slice(key);
+            buf.put_slice(b"\":");
+            buf.put_slice(value);
+        }
+
+        buf.put_u8(b'}');
+
+        buf.freeze()
+    }
+
+    /// Wraps the score's raw JSON bytes into a binary websocket message
+    /// without copying them.
+    #[cfg(feature = "server")]
     pub fn as_message(&self) -> Message {
+        debug_assert!(!self.is_placeholder(), "placeholder score {}", self.id);
+
         Message::Binary(self.bytes.clone())
     }
+
+    /// Same as [`Score::as_message`] but wraps the bytes into a text message.
+    #[cfg(feature = "server")]
+    pub fn as_text_message(&self) -> Message {
+        debug_assert!(!self.is_placeholder(), "placeholder score {}", self.id);
+
+        text_message(self.bytes.clone())
+    }
+}
+
+impl AsRef<[u8]> for Score {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
 }
 
 impl PartialEq for Score {
@@ -194,21 +1034,59 @@ impl Ord for Score {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Only hashes the id to be consistent with [`PartialEq`].
+impl Hash for Score {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
 
-    const SCORES: &[u8] = br#"{"scores": [{"id": 123}, {"id":456, "user": {"id": 2}}, {"user": {"id":2}, "id": 789}], "cursor": {"id": 789}, "cursor_string": "abc"}"#;
+/// Serializes the score's JSON bytes verbatim.
+///
+/// Since serde has no notion of raw JSON, this only works with `serde_json`.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Score {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error;
 
-    impl PartialEq<(&[u8], u64)> for &Score {
-        fn eq(&self, (bytes, id): &(&[u8], u64)) -> bool {
-            self.bytes == bytes && self.id == *id
-        }
+        let json = std::str::from_utf8(&self.bytes).map_err(S::Error::custom)?;
+        let raw: &serde_json::value::RawValue =
+            serde_json::from_str(json).map_err(S::Error::custom)?;
+
+        raw.serialize(serializer)
     }
+}
 
-    #[test]
-    fn deserialize() {
-        let mut scores = Scores::new();
+/// Deserializes a score from a JSON object the same way as [`Deserializer`].
+///
+/// Since serde has no notion of raw JSON, this only works with `serde_json`.
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Score {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let raw = Box::<serde_json::value::RawValue>::deserialize(deserializer)?;
+        let bytes = Bytes::from(String::from(Box::<str>::from(raw)));
+
+        Deserializer::deserialize_score(bytes).map_err(|err| D::Error::custom(format!("{err:#}")))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const SCORES: &[u8] = br#"{"scores": [{"id": 123}, {"id":456, "user": {"id": 2}}, {"user": {"id":2}, "id": 789}], "cursor": {"id": 789}, "cursor_string": "abc"}"#;
+
+    impl PartialEq<(&[u8], u64)> for &Score {
+        fn eq(&self, (bytes, id): &(&[u8], u64)) -> bool {
+            self.bytes == bytes && self.id == *id
+        }
+    }
+
+    #[test]
+    fn deserialize() {
+        let mut scores = Scores::new();
 
         Deserializer::new(SCORES.into())
             .deserialize(&mut scores)
@@ -227,4 +1105,895 @@ mod tests {
         );
         assert!(iter.next().is_none());
     }
+
+    #[test]
+    fn deserialize_each() {
+        let mut ids = Vec::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize_each(|score| ids.push(score.id()))
+            .unwrap();
+
+        assert_eq!(ids, [123, 456, 789]);
+
+        // Scores are passed on before the rest of the response is parsed
+        let json = br#"{"scores": [{"id": 1}, {"id": 2}, {"id": 3} {"id": 4}]}"#;
+        let mut ids = Vec::new();
+
+        assert!(Deserializer::new(json[..].into())
+            .deserialize_each(|score| ids.push(score.id()))
+            .is_err());
+
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn deserialize_from_array_offset() {
+        const PRETTY: &[u8] = b"{\n  \"scores\" :\n  [\n    {\"id\": 2},\n    {\"id\": 1}\n  ]\n}";
+
+        for json in [SCORES, PRETTY, br#"{"scores": []}"#] {
+            let mut scanned = Scores::new();
+            Deserializer::new(json.into())
+                .deserialize(&mut scanned)
+                .unwrap();
+
+            let bracket = memchr::memchr(b'[', json).unwrap();
+
+            // Pointing at the bracket itself or at the whitespace before it
+            for offset in [bracket, bracket - 1] {
+                let mut located = Scores::new();
+                Deserializer::from_array_offset(json.into(), offset)
+                    .unwrap()
+                    .deserialize(&mut located)
+                    .unwrap();
+
+                let bytes: Vec<_> = located.iter().map(Score::raw).collect();
+                let expected: Vec<_> = scanned.iter().map(Score::raw).collect();
+                assert_eq!(bytes, expected);
+            }
+        }
+
+        assert!(matches!(
+            Deserializer::from_array_offset(SCORES.into(), 1),
+            Err(ParseError::ExpectedArray { offset: 1 })
+        ));
+        assert!(matches!(
+            Deserializer::from_array_offset(SCORES.into(), SCORES.len()),
+            Err(ParseError::UnexpectedEnd { .. })
+        ));
+
+        // Still fails on an invalid array
+        let json: &[u8] = br#"{"scores": [{"id": 1} {"id": 2}]}"#;
+        assert!(Deserializer::from_array_offset(json.into(), 11)
+            .unwrap()
+            .deserialize(&mut Scores::new())
+            .is_err());
+    }
+
+    #[test]
+    fn deserialize_top_level_scores() {
+        for json in [
+            &br#"{"\"scores": [{"id": 9}], "scores": [{"id": 1}]}"#[..],
+            br#"{"meta": {"scores": [{"id": 9}]}, "scores": [{"id": 1}]}"#,
+            br#"{"comment": "\"scores\": [{\"id\": 9}]", "scores": [{"id": 1}]}"#,
+            br#"{"list": ["scores", {"scores": []}], "scores" : [{"id": 1}]}"#,
+        ] {
+            let mut scores = Scores::new();
+
+            Deserializer::new(json.into())
+                .deserialize(&mut scores)
+                .unwrap();
+
+            let ids: Vec<_> = scores.iter().map(Score::id).collect();
+            assert_eq!(ids, [1], "{}", String::from_utf8_lossy(json));
+        }
+
+        assert!(matches!(
+            Deserializer::new(br#"{"meta": {"scores": [{"id": 1}]}}"#[..].into())
+                .deserialize_scores(|_| {}, false),
+            Err(ParseError::MissingScores)
+        ));
+    }
+
+    #[test]
+    fn deserialize_truncated_before_scores() {
+        const SCORES: &str =
+            r#"{"cursor_string": "abc", "meta": {"scores": []}, "scores": [{"id": 1}]}"#;
+
+        let key = SCORES.rfind(r#""scores""#).unwrap();
+
+        // Cut anywhere before the top-level `"scores"` key is complete
+        for len in 1..key + r#""scores":"#.len() {
+            let res = Deserializer::new(SCORES.as_bytes()[..len].into())
+                .deserialize_scores(|_| {}, false);
+
+            assert!(
+                matches!(res, Err(ParseError::UnexpectedEnd { offset }) if offset == len),
+                "{len}: {res:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn deserialize_braces_in_strings() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "user": {"username": "{owo}"}}, {"id": 2, "comment": "}}{"}, {"title": "\"{\"", "id": 3}, {"id": 4, "path": "C:\\"}, {"id": 5, "s": "}"}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let mut iter = scores.iter();
+
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"id": 1, "user": {"username": "{owo}"}}"#.as_slice(), 1)
+        );
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"id": 2, "comment": "}}{"}"#.as_slice(), 2)
+        );
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"title": "\"{\"", "id": 3}"#.as_slice(), 3)
+        );
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"id": 4, "path": "C:\\"}"#.as_slice(), 4)
+        );
+        assert_eq!(
+            iter.next().unwrap(),
+            (br#"{"id": 5, "s": "}"}"#.as_slice(), 5)
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn deserialize_id_in_strings() {
+        const SCORES: &[u8] = br#"{"scores": [{"title": "\"id\": 1", "id": 2}, {"user": {"bio": "\"id\":3"}, "id": 4}, {"msg": "{\"id\": 5}", "id": 6, "x": "\"id\": 7"}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores.iter().map(Score::id).collect();
+        assert_eq!(ids, [2, 4, 6]);
+    }
+
+    #[test]
+    fn deserialize_mode() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "ruleset_id": 3}, {"beatmap": {"ruleset_id": 1}, "id": 2}, {"ruleset_id": 0, "id": 3, "mode": "\"ruleset_id\":2"}, {"id": 4, "ruleset_id": null}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let modes: Vec<_> = scores.iter().map(Score::mode).collect();
+        assert_eq!(modes, [Some(3), None, Some(0), None]);
+    }
+
+    #[test]
+    fn deserialize_user_id() {
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let user_ids: Vec<_> = scores.iter().map(Score::user_id).collect();
+        assert_eq!(user_ids, [None, Some(2), Some(2)]);
+    }
+
+    #[test]
+    fn deserialize_user_id_nested() {
+        const SCORES: &[u8] = br#"{"scores": [{"user": {"username": "\"id\": 1", "id": 7}, "id": 3}, {"id": 4, "user": null}, {"user_id": 8, "id": 5}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let user_ids: Vec<_> = scores.iter().map(Score::user_id).collect();
+        assert_eq!(user_ids, [Some(7), None, None]);
+    }
+
+    #[test]
+    fn peek_u64() {
+        assert_eq!(Deserializer::peek_u64(b"0").unwrap(), 0);
+        assert_eq!(Deserializer::peek_u64(b" 123").unwrap(), 123);
+        assert_eq!(
+            Deserializer::peek_u64(b"18446744073709551615").unwrap(),
+            u64::MAX
+        );
+        assert!(Deserializer::peek_u64(b"18446744073709551616").is_err());
+        assert!(Deserializer::peek_u64(b"-1").is_err());
+
+        // Leading zeros don't overflow but still exceed the digit limit
+        assert!(Deserializer::peek_u64(b"000000000000000000001").is_err());
+    }
+
+    #[test]
+    fn peek_u64_overflow() {
+        let err = Deserializer::peek_u64(b"123456789012345678901234567890").unwrap_err();
+        assert_eq!(err.to_string(), "Number exceeds u64");
+
+        let json = br#"{"scores": [{"id": 123456789012345678901234567890}]}"#;
+        let mut scores = Scores::new();
+
+        assert!(Deserializer::new(json[..].into())
+            .deserialize(&mut scores)
+            .is_err());
+        assert!(scores.is_empty());
+    }
+
+    #[test]
+    fn peek_i64() {
+        assert_eq!(Deserializer::peek_i64(b"0").unwrap(), 0);
+        assert_eq!(Deserializer::peek_i64(b"-0").unwrap(), 0);
+        assert_eq!(Deserializer::peek_i64(b"42").unwrap(), 42);
+        assert_eq!(Deserializer::peek_i64(b" -42").unwrap(), -42);
+        assert_eq!(
+            Deserializer::peek_i64(b"9223372036854775807").unwrap(),
+            i64::MAX
+        );
+        assert_eq!(
+            Deserializer::peek_i64(b"-9223372036854775808").unwrap(),
+            i64::MIN
+        );
+        assert!(Deserializer::peek_i64(b"9223372036854775808").is_err());
+        assert!(Deserializer::peek_i64(b"-9223372036854775809").is_err());
+        assert!(Deserializer::peek_i64(b"-99999999999999999999").is_err());
+        assert!(Deserializer::peek_i64(b"-").is_err());
+        assert!(Deserializer::peek_i64(b"--1").is_err());
+        assert!(Deserializer::peek_i64(b"null").is_err());
+
+        // Negative modes are out of range rather than malformed
+        let err = Deserializer::peek_mode(b"-1").unwrap_err();
+        assert_eq!(err.to_string(), "Expected value between 0 and 3, got -1");
+    }
+
+    #[test]
+    fn deserialize_pp() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "pp": 500}, {"id": 2, "pp": 727.27}, {"id": 3, "pp": null}, {"id": 4, "pp": 1.5e2}, {"id": 5, "user": {"pp": 1}}, {"id": 6, "pp": -0.5E-1}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let pps: Vec<_> = scores.iter().map(Score::pp).collect();
+        assert_eq!(
+            pps,
+            [
+                Some(500.0),
+                Some(727.27),
+                None,
+                Some(150.0),
+                None,
+                Some(-0.05)
+            ]
+        );
+
+        for invalid in [
+            br#"{"scores": [{"id": 1, "pp": "NaN"}]}"#.as_slice(),
+            br#"{"scores": [{"id": 1, "pp": 7.2.7}]}"#,
+            br#"{"scores": [{"id": 1, "pp": 727.}]}"#,
+            br#"{"scores": [{"id": 1, "pp": 0727}]}"#,
+            br#"{"scores": [{"id": 1, "pp": 1e}]}"#,
+        ] {
+            assert!(Deserializer::new(invalid.into())
+                .deserialize(&mut Scores::new())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn deserialize_beatmap_id() {
+        const SCORES: &[u8] = br#"{"scores": [{"beatmap_id": 10, "id": 1, "user": {"id": 2}}, {"beatmap": {"id": 20, "title": "\"beatmap_id\": 0"}, "id": 2, "user_id": 3}, {"id": 3, "beatmap": {"id": 20}, "beatmap_id": 30}, {"id": 4, "beatmap": null}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores
+            .iter()
+            .map(|score| (score.id(), score.user_id(), score.beatmap_id()))
+            .collect();
+
+        assert_eq!(
+            ids,
+            [
+                (1, Some(2), Some(10)),
+                (2, None, Some(20)),
+                (3, None, Some(30)),
+                (4, None, None)
+            ]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        #[derive(serde::Serialize, serde::Deserialize)]
+        struct Wrapper {
+            scores: Vec<Score>,
+        }
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let wrapper = Wrapper {
+            scores: scores.iter().cloned().collect(),
+        };
+
+        let json = serde_json::to_string(&wrapper).unwrap();
+        assert_eq!(
+            json,
+            r#"{"scores":[{"id": 123},{"id":456, "user": {"id": 2}},{"user": {"id":2}, "id": 789}]}"#
+        );
+
+        let Wrapper { scores: roundtrip } = serde_json::from_str(&json).unwrap();
+
+        for (a, b) in scores.iter().zip(&roundtrip) {
+            assert_eq!(a, (b.bytes.as_ref(), b.id));
+            assert_eq!(a.user_id, b.user_id);
+            assert_eq!(a.beatmap_id, b.beatmap_id);
+            assert_eq!(a.pp, b.pp);
+        }
+
+        assert_eq!(roundtrip.len(), 3);
+        assert!(serde_json::from_str::<Score>(r#"{"user": {"id": 2}}"#).is_err());
+    }
+
+    #[test]
+    fn batch() {
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let mut batch = encode_batch(&scores);
+        let mut decoded = Vec::new();
+
+        while !batch.is_empty() {
+            let len = u32::from_le_bytes(batch[..4].try_into().unwrap()) as usize;
+            decoded.push(batch.slice(4..4 + len));
+            batch = batch.slice(4 + len..);
+        }
+
+        let expected: Vec<_> = scores.iter().map(|score| score.bytes.clone()).collect();
+        assert_eq!(decoded, expected);
+        assert!(encode_batch(Scores::new()).is_empty());
+    }
+
+    #[test]
+    fn deserialize_lenient() {
+        const SCORES: &[u8] =
+            br#"{"scores": [{"id": 1}, {"user": {"id": 2}}, {"id": 3}, {"id": "4"}, {"id": 5}]}"#;
+
+        assert!(Deserializer::new(SCORES.into())
+            .deserialize(&mut Scores::new())
+            .is_err());
+
+        let mut scores = Scores::new();
+
+        let skipped = Deserializer::new(SCORES.into())
+            .deserialize_lenient(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores.iter().map(Score::id).collect();
+        assert_eq!(ids, [1, 3, 5]);
+        assert_eq!(skipped, 2);
+    }
+
+    #[test]
+    fn deserialize_lenient_resync() {
+        for json in [
+            &br#"{"scores": [{"id": 1}x{"id": 2}, {"id": 3}]}"#[..],
+            br#"{"scores": [{"id": 1} ; {"id": 2}, {"id": 3}]}"#,
+            br#"{"scores": [{"id": 1} "{\"id\": 4}]" [{"id": 5}], {"id": 2}, {"id": 3}]}"#,
+            br#"{"scores": [{"id": 1}, {"id": 2} {"id": 3}]}"#,
+        ] {
+            #[cfg(feature = "server")]
+            let before = METRICS.missing_separators.load(Relaxed);
+
+            assert!(matches!(
+                Deserializer::new(json.into()).deserialize_scores(|_| {}, false),
+                Err(ParseError::ExpectedCommaOrBracket { .. })
+            ));
+
+            let mut scores = Scores::new();
+
+            let skipped = Deserializer::new(json.into())
+                .deserialize_lenient(&mut scores)
+                .unwrap();
+
+            let ids: Vec<_> = scores.iter().map(Score::id).collect();
+            assert_eq!(ids, [1, 2, 3], "{}", String::from_utf8_lossy(json));
+            assert_eq!(skipped, 0);
+
+            // Other tests deserialize through the same global metrics
+            #[cfg(feature = "server")]
+            assert!(METRICS.missing_separators.load(Relaxed) >= before + 2);
+        }
+
+        // Stray bytes at the end of the array
+        let mut scores = Scores::new();
+
+        Deserializer::new(br#"{"scores": [{"id": 1} x]}"#[..].into())
+            .deserialize_lenient(&mut scores)
+            .unwrap();
+
+        assert_eq!(scores.len(), 1);
+
+        assert!(matches!(
+            Deserializer::new(br#"{"scores": [{"id": 1} x"#[..].into())
+                .deserialize_scores(|_| {}, true),
+            Err(ParseError::UnexpectedEnd { .. })
+        ));
+    }
+
+    #[test]
+    fn raw_shares_buffer() {
+        let mut scores = Scores::new();
+        Deserializer::new(br#"{"scores": [{"id": 1}]}"#[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let score = scores.first().unwrap();
+        assert_eq!(score.raw(), br#"{"id": 1}"#.as_slice());
+
+        #[cfg(feature = "server")]
+        let Message::Binary(msg) = score.as_message() else {
+            panic!("expected binary message");
+        };
+
+        #[cfg(feature = "server")]
+        assert_eq!(msg.as_ptr(), score.raw().as_ptr());
+    }
+
+    #[test]
+    fn clone_hash() {
+        use std::hash::BuildHasher;
+
+        let mut scores = Scores::new();
+        Deserializer::new(br#"{"scores": [{"id": 1, "pp": 727}]}"#[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let score = scores.first().unwrap();
+        let cloned = score.clone();
+        assert!(score == &cloned);
+
+        let state = std::collections::hash_map::RandomState::new();
+        assert_eq!(state.hash_one(score), state.hash_one(&cloned));
+        assert_eq!(state.hash_one(score), state.hash_one(Score::only_id(1)));
+    }
+
+    #[test]
+    fn project() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "user": {"id": 2, "pp": 3}, "pp": 727.27, "user_id": 2, "title": "\"pp\":0"}, {"beatmap": {"id": 3}, "id": 2}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let fields = ["user_id", "pp", "id"].map(Box::from).into();
+        let projected: Vec<_> = scores.iter().map(|score| score.project(&fields)).collect();
+
+        assert_eq!(projected[0], br#"{"id":1,"pp":727.27,"user_id":2}"#[..]);
+        assert_eq!(projected[1], br#"{"id":2}"#[..]);
+
+        for bytes in projected {
+            let value: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_slice(&bytes).unwrap();
+
+            assert!(value.keys().all(|key| fields.contains(key.as_str())));
+        }
+
+        assert_eq!(scores.first().unwrap().project(&BTreeSet::new()), b"{}"[..]);
+    }
+
+    #[test]
+    fn deserialize_pretty() {
+        const SCORES: &[u8] = b"{\n  \"scores\": [\n    {\n      \"id\": 1,\n      \"mods\": [{\"acronym\": \"}]\"}, []],\n      \"user\": {\"id\": 2, \"username\": \"\\\\\"\n    }\n    },\n\t{ \"id\" : 3 }\n  ]\n}";
+
+        let mut scores = Scores::new();
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores
+            .iter()
+            .map(|score| (score.id(), score.user_id()))
+            .collect();
+        assert_eq!(ids, [(1, Some(2)), (3, None)]);
+        assert_eq!(scores.last().unwrap().raw(), br#"{ "id" : 3 }"#.as_slice());
+    }
+
+    #[test]
+    fn deserialize_whitespace() {
+        const SCORES: &[u8] = b"\xEF\xBB\xBF{\r\n\t\"cursor_string\": \"scores\",\r\n\t\"scores\"\t:\r\n\t[\r\n\t\t{\r\n\t\t\t\"id\":\t1,\r\n\t\t\t\"ruleset_id\" :\n3\r\n\t\t}\r\n\t\t,\n\t\t{\"id\":\r\n2}\r\n\t]\r\n}\r\n";
+
+        let mut scores = Scores::new();
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores
+            .iter()
+            .map(|score| (score.id(), score.mode()))
+            .collect();
+        assert_eq!(ids, [(1, Some(3)), (2, None)]);
+
+        let cursor_string = Deserializer::new(SCORES.into()).cursor_string().unwrap();
+        assert_eq!(cursor_string.as_deref(), Some("scores"));
+
+        let mut scores = Scores::new();
+        Deserializer::new(b"{\n\t\"scores\":\r\n\t[\r\n\t]\n}"[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+        assert!(scores.is_empty());
+
+        assert!(Deserializer::new(b"{\"scores\":\x0C[]}"[..].into())
+            .deserialize(&mut Scores::new())
+            .is_err());
+    }
+
+    #[test]
+    fn deserialize_nested_arrays() {
+        let json = br#"{"scores": [{"mods": [{"acronym": "DA", "settings": {"id": 1, "ranges": [[{"id": 2}], []]}}, {"acronym": "DT"}], "maximum_statistics": {"great": [{"id": 3}]}, "id": 42, "user": {"id": 5, "groups": [{"id": 6, "playmodes": ["osu"]}]}}, {"id": 43, "mods": [[{"id": 7}]]}]}"#;
+        let mut scores = Scores::new();
+
+        Deserializer::new(json[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores.iter().map(Score::id).collect();
+        assert_eq!(ids, [42, 43]);
+        assert_eq!(scores.first().unwrap().user_id(), Some(5));
+
+        let mismatched = br#"{"scores": [{"mods": [{"acronym": "DT"]}, "id": 1}]}"#;
+
+        assert!(Deserializer::new(mismatched[..].into())
+            .deserialize(&mut Scores::new())
+            .is_err());
+    }
+
+    #[test]
+    fn deserialize_no_scores() {
+        for json in [
+            &br#"{"scores": null, "cursor": null, "cursor_string": null}"#[..],
+            br#"{"scores":null}"#,
+            b"{\"scores\": null\n}",
+            br#"{"scores": [], "cursor": {"id": 1}, "cursor_string": "abc"}"#,
+            br#"{"scores" : [ ] }"#,
+        ] {
+            let mut scores = Scores::new();
+
+            let skipped = Deserializer::new(json.into())
+                .deserialize_lenient(&mut scores)
+                .unwrap();
+
+            assert_eq!(skipped, 0);
+            assert!(scores.is_empty());
+        }
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        assert_eq!(scores.len(), 3);
+    }
+
+    #[test]
+    fn parse_errors() {
+        fn error(bytes: &'static [u8], max_scores: usize) -> ParseError {
+            Deserializer::new(bytes.into())
+                .max_scores(max_scores)
+                .deserialize_scores(|_| {}, false)
+                .unwrap_err()
+        }
+
+        assert!(matches!(
+            error(br#"{"cursor": null}"#, usize::MAX),
+            ParseError::MissingScores
+        ));
+        assert!(matches!(
+            error(br#"{"scores": nullx}"#, usize::MAX),
+            ParseError::ExpectedArray { offset: 11 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": nullable, "x": []}"#, usize::MAX),
+            ParseError::ExpectedArray { offset: 11 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": null"#, usize::MAX),
+            ParseError::UnexpectedEnd { offset: 15 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": 5}"#, usize::MAX),
+            ParseError::ExpectedArray { offset: 11 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": [{"id": 1}, 2]}"#, usize::MAX),
+            ParseError::ExpectedObject { offset: 23 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": [{"id": 1} {"id": 2}]}"#, usize::MAX),
+            ParseError::ExpectedCommaOrBracket { offset: 22 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": [{"id": 1}, {"id": 2}]}"#, 1),
+            ParseError::TooManyScores { offset: 23, max: 1 }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": [{"id": 1}, {"id" 2}]}"#, usize::MAX),
+            ParseError::InvalidJson { offset: 23, .. }
+        ));
+        assert!(matches!(
+            error(br#"{"scores": [{"id": 1}, {"user_id": 2}]}"#, usize::MAX),
+            ParseError::InvalidScore { offset: 23, .. }
+        ));
+    }
+
+    #[test]
+    fn max_depth() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "mods": [{"settings": {"a": 1}}]}, {"id": 2, "user": {"a": [[[[1]]]]}}]}"#;
+
+        let deserialize = |max_depth| {
+            Deserializer::new(SCORES.into())
+                .max_depth(max_depth)
+                .deserialize_lenient(&mut Scores::new())
+        };
+
+        assert!(deserialize(5).is_ok());
+
+        // Not even lenient deserialization skips the score
+        let err = deserialize(4).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::NestedTooDeeply { offset: 84, max: 4 })
+        ));
+        assert_eq!(SCORES[84], b'[');
+
+        let err = deserialize(2).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ParseError>(),
+            Some(ParseError::NestedTooDeeply { offset: 44, max: 2 })
+        ));
+    }
+
+    #[test]
+    fn truncated() {
+        const SCORES: &str = r#"{"scores": [{"id": 1, "user": {"id": 2, "username": "a\"b"}}, {"id": 3, "pp": 727.27}]}"#;
+
+        let after_key = SCORES.find(':').unwrap() + 1;
+        let end = SCORES.rfind(']').unwrap();
+
+        // Cut right after `"scores":`, inside the array, and inside objects
+        for len in after_key..end {
+            let err = Deserializer::new(SCORES.as_bytes()[..len].into())
+                .deserialize(&mut Scores::new())
+                .unwrap_err();
+
+            assert!(
+                matches!(
+                    err.downcast_ref::<ParseError>(),
+                    Some(&ParseError::UnexpectedEnd { offset }) if offset == len
+                ),
+                "{len}: {err:#}"
+            );
+        }
+
+        assert!(Deserializer::new(SCORES[..=end].into())
+            .deserialize(&mut Scores::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_error_excerpt() {
+        let padding = "x".repeat(1000);
+        let bytes = format!(r#"{{"padding": "{padding}", "scores": [{{"id": 1}} 2]}}"#);
+
+        let err = Deserializer::new(bytes.into())
+            .deserialize(&mut Scores::new())
+            .unwrap_err();
+
+        let offset = err.downcast_ref::<ParseError>().unwrap().offset().unwrap();
+        assert_eq!(offset, 1037);
+
+        let msg = format!("{err:#}");
+        assert!(msg.len() < 200, "{msg}");
+        assert!(msg.contains(r#"[{\"id\": 1} 2]}"#), "{msg}");
+    }
+
+    #[test]
+    fn deserialize_ended_at() {
+        const SCORES: &[u8] = br#"{"scores": [{"beatmap": {"last_updated": "2019-01-01T00:00:00Z"}, "ended_at": "2025-01-02T12:34:56Z", "id": 1, "started_at": "2025-01-02T12:31:02Z", "user": {"id": 2}}, {"ended_at": null, "id": 2}, {"id": 3, "title": "\"ended_at\":\"2000\""}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ended_at: Vec<_> = scores.iter().map(Score::ended_at).collect();
+        assert_eq!(ended_at, [Some("2025-01-02T12:34:56Z"), None, None]);
+
+        assert!(
+            Deserializer::new(br#"{"scores": [{"id": 1, "ended_at": 123}]}"#[..].into())
+                .deserialize(&mut Scores::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn deserialize_passed() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "passed": true}, {"passed": false, "id": 2, "user": {"passed": true}}, {"id": 3, "passed": null}, {"id": 4, "title": "\"passed\":true"}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let passed: Vec<_> = scores.iter().map(Score::passed).collect();
+        assert_eq!(passed, [Some(true), Some(false), None, None]);
+    }
+
+    #[test]
+    fn deserialize_rank_global() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "rank_global": 1}, {"rank_global": 50, "id": 2, "user": {"rank_global": 3}}, {"id": 3, "rank_global": null}, {"id": 4, "title": "\"rank_global\":2"}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ranks: Vec<_> = scores.iter().map(Score::rank_global).collect();
+        assert_eq!(ranks, [Some(1), Some(50), None, None]);
+
+        let invalid = br#"{"scores": [{"id": 1, "rank_global": -1}]}"#;
+
+        assert!(Deserializer::new(invalid[..].into())
+            .deserialize(&mut Scores::new())
+            .is_err());
+    }
+
+    #[test]
+    fn deserialize_legacy_score_id() {
+        const SCORES: &[u8] = br#"{"scores": [{"id": 1, "legacy_score_id": 4000000000}, {"legacy_score_id": null, "id": 2}, {"id": 3, "user": {"legacy_score_id": 5}}]}"#;
+
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        let ids: Vec<_> = scores
+            .iter()
+            .map(|score| (score.id(), score.legacy_score_id()))
+            .collect();
+        assert_eq!(ids, [(1, Some(4_000_000_000)), (2, None), (3, None)]);
+    }
+
+    #[tokio::test]
+    async fn deserialize_stream() {
+        let bodies = [
+            &br#"{"scores": [{"id": 2}, {"id": 1}]}"#[..],
+            br#"{"scores": [{"id": 3}, {"pp": 1}]}"#,
+            br#"{"scores": []}"#,
+            br#"{"scores": [{"id": 4}], "cursor_string": null}"#,
+        ];
+
+        let results: Vec<_> =
+            super::deserialize_stream(stream::iter(bodies).map(Bytes::from_static))
+                .collect()
+                .await;
+
+        let ids: Vec<_> = results
+            .iter()
+            .map(|res| res.as_ref().ok().map(Score::id))
+            .collect();
+
+        // The invalid response yields an error instead of its scores
+        assert_eq!(ids, [Some(1), Some(2), None, Some(4)]);
+    }
+
+    #[test]
+    fn oldest_and_latest_id() {
+        let mut scores = Scores::new();
+        assert_eq!(scores.oldest_id(), None);
+        assert_eq!(scores.latest_id(), None);
+
+        scores.insert(Score::only_id(5));
+        assert_eq!(scores.oldest_id(), Some(5));
+        assert_eq!(scores.latest_id(), Some(5));
+
+        Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .unwrap();
+        assert_eq!(scores.oldest_id(), Some(5));
+        assert_eq!(scores.latest_id(), Some(789));
+    }
+
+    #[test]
+    fn drain_since() {
+        let all = || {
+            let mut scores = Scores::new();
+
+            Deserializer::new(br#"{"scores": [{"id": 3}, {"id": 1}, {"id": 5}]}"#[..].into())
+                .deserialize(&mut scores)
+                .unwrap();
+
+            scores
+        };
+
+        let ids = |scores: &Scores| scores.iter().map(Score::id).collect::<Vec<_>>();
+
+        for (id, kept, drained) in [
+            (3, &[1, 3][..], &[5][..]),
+            (4, &[1, 3], &[5]),
+            (0, &[], &[1, 3, 5]),
+            (5, &[1, 3, 5], &[]),
+            (u64::MAX, &[1, 3, 5], &[]),
+        ] {
+            let mut scores = all();
+            let newer = super::drain_since(&mut scores, id);
+
+            assert_eq!(ids(&scores), kept, "since {id}");
+            assert_eq!(ids(&newer), drained, "since {id}");
+        }
+    }
+
+    #[test]
+    fn deserialize_max_scores() {
+        let mut scores = Scores::new();
+
+        Deserializer::new(SCORES.into())
+            .max_scores(3)
+            .deserialize(&mut scores)
+            .unwrap();
+
+        assert_eq!(scores.len(), 3);
+
+        let mut scores = Scores::new();
+
+        assert!(Deserializer::new(SCORES.into())
+            .max_scores(2)
+            .deserialize_lenient(&mut scores)
+            .is_err());
+
+        assert!(scores.is_empty());
+    }
+
+    #[test]
+    fn deserialize_missing_top_level_id() {
+        const SCORES: &[u8] = br#"{"scores": [{"user": {"id": 2}, "title": "\"id\": 3"}]}"#;
+
+        let mut scores = Scores::new();
+
+        assert!(Deserializer::new(SCORES.into())
+            .deserialize(&mut scores)
+            .is_err());
+    }
 }
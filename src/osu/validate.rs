@@ -0,0 +1,45 @@
+use std::io::Write;
+
+use bytes::Bytes;
+use eyre::{Context as _, Result};
+
+use super::{Scores, ScoresDeserializer};
+
+/// Deserializes a captured osu!api response and writes the ids of its scores
+/// to `out`, one per line, followed by their amount.
+///
+/// Fails if the response can't be deserialized.
+pub fn validate(response: Bytes, out: &mut impl Write) -> Result<()> {
+    let mut scores = Scores::new();
+    ScoresDeserializer::new(response).deserialize(&mut scores)?;
+
+    for score in &scores {
+        writeln!(out, "{}", score.id()).context("Failed to write score id")?;
+    }
+
+    writeln!(out, "Deserialized {} scores", scores.len()).context("Failed to write summary")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osu::scores::tests::SCORES;
+
+    #[test]
+    fn ids() {
+        let mut out = Vec::new();
+        validate(SCORES.into(), &mut out).unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "123\n456\n789\nDeserialized 3 scores\n"
+        );
+
+        let truncated = &SCORES[..SCORES.len() / 2];
+        let err = validate(truncated.into(), &mut Vec::new()).unwrap_err();
+        assert!(
+            err.to_string().starts_with("Failed to deserialize scores"),
+            "{err:#}"
+        );
+    }
+}
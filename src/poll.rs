@@ -0,0 +1,273 @@
+//! Polling scores without necessarily serving them over the network.
+
+use std::{
+    fs,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use eyre::{Context as _, Result};
+use tokio::{sync::broadcast, task::JoinSet};
+
+use crate::{
+    config::{OsuConfig, Setup},
+    context::{Context, Poller},
+    cursor::CursorFile,
+    osu::{Osu, Score, Scores, ScoresDeserializer},
+    relay,
+};
+
+/// Spawns a polling task for each of the configured rulesets, broadcasting
+/// their scores through `ctx`.
+///
+/// If a `recording` is specified, a single task replays it instead of
+/// polling the osu!api. Similarly, if an `upstream` is specified, a single task
+/// relays its scores.
+///
+/// The tasks finish once [`Context::shutdown`] is called.
+pub fn spawn(ctx: &Arc<Context>, setup: &Setup, osu: OsuConfig) -> Result<JoinSet<()>> {
+    let mut pollers = JoinSet::new();
+
+    if let Some(ref path) = setup.recording {
+        let responses = load_recording(path)
+            .with_context(|| format!("Failed to load recording {}", path.display()))?;
+
+        pollers.spawn(replay(Arc::clone(ctx), responses, setup.interval));
+
+        return Ok(pollers);
+    }
+
+    if let Some(ref url) = setup.upstream {
+        pollers.spawn(relay::relay(Arc::clone(ctx), url.clone()));
+
+        return Ok(pollers);
+    }
+
+    let rulesets = osu.rulesets();
+    let osu = Arc::new(Osu::new(osu).context("Failed to create osu! client")?);
+    let multiple_rulesets = rulesets.len() > 1;
+
+    for ruleset in rulesets {
+        let poller = poller(setup, ruleset, multiple_rulesets);
+
+        // Registered upfront so that clients are only notified once all
+        // pollers finished backfilling
+        if poller.backfills() {
+            ctx.expect_backfill();
+        }
+
+        pollers.spawn(Context::fetch_scores(
+            Arc::clone(ctx),
+            Arc::clone(&osu),
+            poller,
+        ));
+    }
+
+    Ok(pollers)
+}
+
+/// Polls scores in the background and returns a receiver for them, without
+/// serving any websockets.
+///
+/// Polling continues until [`Context::shutdown`] is called on the returned
+/// context. See [`Context::score_stream`] for how lagging receivers behave.
+pub fn stream(setup: &Setup, osu: OsuConfig) -> Result<(Arc<Context>, broadcast::Receiver<Score>)> {
+    let ctx = Arc::new(Context::new(setup));
+
+    // Subscribe before polling so that no score is missed
+    let rx = ctx.score_stream();
+    spawn(&ctx, setup, osu)?.detach_all();
+
+    Ok((ctx, rx))
+}
+
+/// Reads the responses of a recording, either one per file of a directory in
+/// order of their name or one per line of a file.
+fn load_recording(path: &Path) -> Result<Vec<Bytes>> {
+    let responses: Vec<_> = if path.is_dir() {
+        let mut paths = fs::read_dir(path)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+
+        paths.retain(|path| path.is_file());
+        paths.sort_unstable();
+
+        paths
+            .iter()
+            .map(|path| fs::read(path).map(Bytes::from))
+            .collect::<Result<_, _>>()?
+    } else {
+        let content = Bytes::from(fs::read(path)?);
+
+        content
+            .split(|&byte| byte == b'\n')
+            .filter(|line| !line.trim_ascii().is_empty())
+            .map(|line| content.slice_ref(line))
+            .collect()
+    };
+
+    eyre::ensure!(!responses.is_empty(), "Recording contains no responses");
+
+    Ok(responses)
+}
+
+/// Broadcasts the scores of one recorded response every `interval` seconds,
+/// starting over after the last one.
+///
+/// Responses are deserialized the same way as those of the osu!api.
+async fn replay(ctx: Arc<Context>, responses: Vec<Bytes>, interval: u64) {
+    info!(
+        "Replaying {} recorded responses every {interval} seconds...",
+        responses.len()
+    );
+
+    let mut interval = tokio::time::interval(Duration::from_secs(interval.max(1)));
+    let mut scores = Scores::new();
+
+    for (i, response) in responses.iter().enumerate().cycle() {
+        tokio::select! {
+            _ = interval.tick() => {}
+            () = ctx.shutting_down() => break,
+        }
+
+        if let Err(err) = ScoresDeserializer::new(response.clone()).deserialize(&mut scores) {
+            warn!(?err, "Failed to deserialize recorded response #{i}");
+
+            continue;
+        }
+
+        ctx.record_poll(Instant::now());
+        let batch = ctx.broadcast(&mut scores, None);
+        debug!(scores = batch.len(), "Replayed recorded response #{i}");
+    }
+
+    info!("Stopped replaying recording");
+}
+
+/// Creates the poller of `ruleset`, resuming from its persisted cursor if any.
+fn poller(setup: &Setup, ruleset: Option<Box<str>>, multiple_rulesets: bool) -> Poller {
+    // Each ruleset has its own cursor and thus its own file
+    let cursor_file = setup.cursor_file.as_ref().map(|path| match ruleset {
+        Some(ref ruleset) if multiple_rulesets => {
+            let mut path = path.clone().into_os_string();
+            path.push(".");
+            path.push(ruleset.as_ref());
+
+            CursorFile::new(path)
+        }
+        _ => CursorFile::new(path),
+    });
+
+    let cursor_id = match (setup.resume_score_id, &cursor_file) {
+        (Some(score_id), _) => Some(score_id),
+        (None, Some(file)) => match file.load() {
+            Ok(cursor_id) => cursor_id,
+            Err(err) => {
+                warn!(?err, path = ?file.path(), "Failed to load cursor");
+
+                None
+            }
+        },
+        (None, None) => None,
+    };
+
+    Poller {
+        ruleset,
+        interval: setup.interval,
+        min_interval: setup.min_interval,
+        max_interval: setup.max_interval,
+        interval_factor: setup.interval_factor,
+        cursor_id,
+        cursor_file,
+        cursor_source: setup.cursor_source,
+        gap_threshold: setup.gap_threshold,
+        skip_repeated_batches: setup.skip_repeated_batches,
+        backfill: setup.backfill,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use super::*;
+    use crate::osu::mock;
+
+    #[tokio::test]
+    async fn receive_scores() {
+        let api = mock::serve(|_| r#"{"scores": [{"id": 2}, {"id": 1}]}"#.to_owned()).await;
+        let (ctx, mut rx) = stream(&Setup::default(), mock::config(api)).unwrap();
+
+        let mut ids = Vec::new();
+
+        for _ in 0..2 {
+            let score = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+
+            ids.push(score.id());
+        }
+
+        assert_eq!(ids, [1, 2]);
+
+        ctx.shutdown();
+        drop(ctx);
+
+        // The pollers drop their context once they stopped
+        let res = tokio::time::timeout(Duration::from_secs(1), rx.recv()).await;
+        assert!(matches!(res, Ok(Err(broadcast::error::RecvError::Closed))));
+    }
+
+    #[tokio::test]
+    async fn resume_score_id() {
+        static QUERIES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let api = mock::serve(|query| {
+            QUERIES.lock().unwrap().push(query.to_owned());
+
+            r#"{"scores": [{"id": 12346}]}"#.to_owned()
+        })
+        .await;
+
+        let setup = Setup {
+            resume_score_id: Some(12345),
+            // The explicit score id takes precedence over the backfill
+            backfill: Some(100),
+            ..Setup::default()
+        };
+
+        let (ctx, mut rx) = stream(&setup, mock::config(api)).unwrap();
+
+        let score = tokio::time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(score.id(), 12346);
+        assert_eq!(QUERIES.lock().unwrap()[0], "cursor[id]=12345");
+
+        ctx.shutdown();
+    }
+
+    #[test]
+    fn recording_lines() {
+        let path =
+            std::env::temp_dir().join(format!("scores-ws-recording-{}.ndjson", std::process::id()));
+        std::fs::write(&path, "{\"scores\": [{\"id\": 1}]}\n\n{\"scores\": []}\r\n").unwrap();
+
+        let responses = load_recording(&path).unwrap();
+        assert_eq!(
+            responses,
+            ["{\"scores\": [{\"id\": 1}]}", "{\"scores\": []}\r"]
+        );
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(load_recording(&path).is_err());
+        std::fs::remove_file(path).unwrap();
+
+        assert!(load_recording(Path::new("missing-recording")).is_err());
+    }
+}
@@ -0,0 +1,252 @@
+//! Relaying scores of an upstream `scores-ws` instead of polling the osu!api.
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use eyre::{Context as _, Result};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    http::{header::SEC_WEBSOCKET_PROTOCOL, HeaderValue},
+    Message,
+};
+
+use crate::{
+    context::Context,
+    options::BATCH_SUBPROTOCOL,
+    osu::{Backoff, Scores, ScoresDeserializer, ScoresExt},
+};
+
+/// Connects to the websocket of the upstream instance at `url` and broadcasts
+/// its scores through `ctx`.
+///
+/// Whenever the connection is lost, it reconnects with an exponential
+/// backoff and resumes from the newest received score.
+pub(crate) async fn relay(ctx: Arc<Context>, url: Box<str>) {
+    info!(%url, "Relaying scores of upstream...");
+
+    let mut latest_id = None;
+    let mut backoff = reconnect_backoff();
+
+    loop {
+        let res = tokio::select! {
+            res = receive(&ctx, &url, &mut latest_id, &mut backoff) => res,
+            () = ctx.shutting_down() => break,
+        };
+
+        match res {
+            Ok(()) => warn!("Upstream closed the connection"),
+            Err(err) => warn!(?err, "Lost connection to upstream"),
+        }
+
+        // There is no maximum amount of retries
+        let delay = backoff.next_delay().unwrap_or_default();
+        info!("Reconnecting to upstream in {delay:.2?}...");
+
+        tokio::select! {
+            () = tokio::time::sleep(delay) => {}
+            () = ctx.shutting_down() => break,
+        }
+    }
+
+    info!("Stopped relaying");
+}
+
+fn reconnect_backoff() -> Backoff {
+    Backoff::new(Duration::from_secs(1), Duration::from_mins(1), None)
+}
+
+/// Broadcasts scores of a single connection to upstream until it closes.
+///
+/// `latest_id` is the id of the newest received score and `backoff` is reset
+/// once connected.
+async fn receive(
+    ctx: &Context,
+    url: &str,
+    latest_id: &mut Option<u64>,
+    backoff: &mut Backoff,
+) -> Result<()> {
+    let mut req = url.into_client_request().context("Invalid upstream URL")?;
+
+    // Receiving all scores of a poll at once allows broadcasting them as one
+    req.headers_mut().insert(
+        SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(BATCH_SUBPROTOCOL),
+    );
+
+    let (mut stream, _) = tokio_tungstenite::connect_async(req)
+        .await
+        .context("Failed to connect")?;
+
+    let initial = match *latest_id {
+        Some(score_id) => format!(r#"{{"resume_from":{score_id}}}"#),
+        None => "connect".to_owned(),
+    };
+
+    stream
+        .send(Message::Text(initial.into()))
+        .await
+        .context("Failed to send initial message")?;
+
+    info!(?latest_id, "Connected to upstream");
+    *backoff = reconnect_backoff();
+
+    let mut scores = Scores::new();
+
+    while let Some(msg) = stream.next().await {
+        let bytes = match msg.context("Failed to receive message")? {
+            Message::Binary(bytes) => bytes,
+            Message::Text(text) if text.as_str() == r#"{"error":"resume_too_old"}"# => {
+                warn!("Upstream history is too short to resume, some scores may be missing");
+
+                continue;
+            }
+            // Heartbeats and the like are of no interest
+            _ => continue,
+        };
+
+        let response = match into_response(&bytes) {
+            Ok(response) => response,
+            Err(err) => {
+                warn!(?err, "Invalid message of upstream");
+
+                continue;
+            }
+        };
+
+        if let Err(err) = ScoresDeserializer::new(response).deserialize(&mut scores) {
+            warn!(?err, "Failed to deserialize scores of upstream");
+
+            continue;
+        }
+
+        if let Some(score_id) = scores.latest_id() {
+            *latest_id = (*latest_id).max(Some(score_id));
+        }
+
+        ctx.record_poll(Instant::now());
+        let batch = ctx.broadcast(&mut scores, None);
+        debug!(scores = batch.len(), "Relayed scores of upstream");
+    }
+
+    Ok(())
+}
+
+/// Wraps the scores of a batch into the form of an osu!api response.
+///
+/// A batch contains the length of each score as little-endian `u32`,
+/// followed by the score's JSON bytes.
+fn into_response(batch: &[u8]) -> Result<Bytes> {
+    let mut response = BytesMut::with_capacity(batch.len() + 16);
+    response.put_slice(br#"{"scores":["#);
+
+    let mut rest = batch;
+
+    while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+        let len = u32::from_le_bytes(*len) as usize;
+        eyre::ensure!(tail.len() >= len, "Truncated batch");
+
+        if rest.len() < batch.len() {
+            response.put_u8(b',');
+        }
+
+        response.put_slice(&tail[..len]);
+        rest = &tail[len..];
+    }
+
+    eyre::ensure!(rest.is_empty(), "Truncated batch");
+    response.put_slice(b"]}");
+
+    Ok(response.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    use super::*;
+    use crate::{config::Setup, osu::encode_batch};
+
+    #[test]
+    fn batch_response() {
+        let batch = encode_batch([r#"{"id":1}"#, r#"{"id":2}"#]);
+
+        assert_eq!(
+            into_response(&batch).unwrap(),
+            r#"{"scores":[{"id":1},{"id":2}]}"#
+        );
+        assert_eq!(into_response(&[]).unwrap(), r#"{"scores":[]}"#);
+        assert!(into_response(&batch[..batch.len() - 1]).is_err());
+        assert!(into_response(&batch[..2]).is_err());
+    }
+
+    #[tokio::test]
+    async fn resume_after_reconnect() {
+        static INITIAL_MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Sends a batch upon each connection and then closes it
+        tokio::spawn(async move {
+            let batches = [vec![r#"{"id":1}"#, r#"{"id":2}"#], vec![r#"{"id":3}"#]];
+
+            for batch in batches {
+                let (stream, _) = listener.accept().await.unwrap();
+
+                // The error type is dictated by tungstenite
+                #[allow(clippy::result_large_err)]
+                let callback = |req: &Request, mut res: Response| {
+                    assert_eq!(req.headers()[SEC_WEBSOCKET_PROTOCOL], BATCH_SUBPROTOCOL);
+                    let protocol = HeaderValue::from_static(BATCH_SUBPROTOCOL);
+                    res.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, protocol);
+
+                    Ok(res)
+                };
+
+                let mut ws = tokio_tungstenite::accept_hdr_async(stream, callback)
+                    .await
+                    .unwrap();
+
+                let initial = ws.next().await.unwrap().unwrap();
+                INITIAL_MESSAGES
+                    .lock()
+                    .unwrap()
+                    .push(initial.into_text().unwrap().to_string());
+
+                let msg = Message::Binary(encode_batch(batch));
+                ws.send(msg).await.unwrap();
+                ws.close(None).await.unwrap();
+            }
+        });
+
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let mut rx = ctx.score_stream();
+        tokio::spawn(relay(Arc::clone(&ctx), format!("ws://{addr}").into()));
+
+        let mut ids = Vec::new();
+
+        while ids.len() < 3 {
+            let score = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+
+            ids.push(score.id());
+        }
+
+        ctx.shutdown();
+
+        assert_eq!(ids, [1, 2, 3]);
+        assert_eq!(
+            *INITIAL_MESSAGES.lock().unwrap(),
+            ["connect", r#"{"resume_from":2}"#]
+        );
+    }
+}
@@ -0,0 +1,513 @@
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{atomic::Ordering::Relaxed, Arc},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, StreamBody};
+use hyper::{
+    body::Frame,
+    header::{ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, VARY},
+    server::conn::http1,
+    service::service_fn,
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use tokio::{net::TcpListener, time::Interval};
+
+use crate::{
+    compress::{Compressor, Encoding},
+    context::{Batch, Context, Replay},
+    metrics::METRICS,
+    observer::Peer,
+};
+
+/// Comments are sent in this interval so that proxies don't close idle
+/// connections.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+const LAST_EVENT_ID: &str = "last-event-id";
+
+type Body = BoxBody<Bytes, Infallible>;
+
+/// Serves scores as Server-Sent Events on `GET /sse` and as newline-delimited
+/// JSON on `GET /stream.ndjson`.
+pub async fn serve(listener: TcpListener, ctx: Arc<Context>) {
+    while let Ok((stream, addr)) = listener.accept().await {
+        if !ctx.is_allowed(addr) {
+            info!(%addr, "Rejecting SSE connection from disallowed address");
+
+            continue;
+        }
+
+        let ctx = Arc::clone(&ctx);
+
+        let service = service_fn(move |req: Request<_>| {
+            let res = handle_request(&req, &ctx, addr);
+
+            async move { Ok::<_, Infallible>(res) }
+        });
+
+        tokio::spawn(async move {
+            let conn = http1::Builder::new().serve_connection(TokioIo::new(stream), service);
+
+            if let Err(err) = conn.await {
+                debug!(?err, "Failed to serve SSE connection");
+            }
+        });
+    }
+}
+
+fn handle_request<B>(req: &Request<B>, ctx: &Arc<Context>, addr: SocketAddr) -> Response<Body> {
+    match (req.method(), req.uri().path()) {
+        (&Method::GET, "/sse" | "/stream.ndjson") if !ctx.is_authorized(req) => {
+            info!(%addr, "Rejecting unauthorized SSE connection");
+
+            empty_response(StatusCode::UNAUTHORIZED)
+        }
+        (&Method::GET, "/sse") => handle_sse(req, ctx, addr),
+        (&Method::GET, "/stream.ndjson") => handle_ndjson(req, ctx, addr),
+        _ => empty_response(StatusCode::NOT_FOUND),
+    }
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Empty::new().boxed());
+    *res.status_mut() = status;
+
+    res
+}
+
+fn handle_sse<B>(req: &Request<B>, ctx: &Arc<Context>, addr: SocketAddr) -> Response<Body> {
+    let last_event_id = req
+        .headers()
+        .get(LAST_EVENT_ID)
+        .and_then(|value| value.to_str().ok()?.trim().parse().ok());
+
+    let replay = if let Some(score_id) = last_event_id {
+        info!(score_id, %addr, "SSE resume from");
+
+        Replay::ResumeFrom(score_id)
+    } else {
+        info!(%addr, "SSE connect");
+
+        Replay::All
+    };
+
+    let peer = Peer {
+        id: ctx.new_connection_id(),
+        addr,
+    };
+    let (rx, too_old) = ctx.subscribe(replay, peer);
+
+    let client = Client {
+        ctx: Arc::clone(ctx),
+        peer,
+        rx,
+        keep_alive: tokio::time::interval_at(tokio::time::Instant::now() + KEEP_ALIVE, KEEP_ALIVE),
+    };
+
+    let initial = too_old.then(|| {
+        Ok(Frame::data(Bytes::from_static(
+            b"event: error\ndata: {\"error\":\"resume_too_old\"}\n\n",
+        )))
+    });
+
+    let events = stream::iter(initial).chain(events(client, encode_batch, KEEP_ALIVE_COMMENT));
+
+    stream_response(req, events, "text/event-stream")
+}
+
+fn handle_ndjson<B>(req: &Request<B>, ctx: &Arc<Context>, addr: SocketAddr) -> Response<Body> {
+    let replay = req.uri().query().map_or(Replay::All, replay_from_query);
+    info!(%addr, "NDJSON connect");
+
+    let peer = Peer {
+        id: ctx.new_connection_id(),
+        addr,
+    };
+    let (rx, too_old) = ctx.subscribe(replay, peer);
+
+    let client = Client {
+        ctx: Arc::clone(ctx),
+        peer,
+        rx,
+        keep_alive: tokio::time::interval_at(tokio::time::Instant::now() + KEEP_ALIVE, KEEP_ALIVE),
+    };
+
+    let initial = too_old.then(|| {
+        Ok(Frame::data(Bytes::from_static(
+            b"{\"error\":\"resume_too_old\"}\n",
+        )))
+    });
+
+    // Empty lines are skipped by NDJSON parsers
+    let lines = stream::iter(initial).chain(events(client, encode_lines, b"\n"));
+
+    stream_response(req, lines, "application/x-ndjson")
+}
+
+/// Responds with `frames`, compressed through the encoding that the request
+/// prefers if it accepts gzip or zstd.
+fn stream_response<B>(
+    req: &Request<B>,
+    frames: impl Stream<Item = Result<Frame<Bytes>, Infallible>> + Send + Sync + 'static,
+    content_type: &'static str,
+) -> Response<Body> {
+    let encoding = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| Encoding::negotiate(value.to_str().ok()?));
+
+    let compressor = encoding.and_then(|encoding| match Compressor::new(encoding) {
+        Ok(compressor) => Some(compressor),
+        Err(err) => {
+            warn!(
+                ?err,
+                ?encoding,
+                "Failed to create compressor, responding uncompressed"
+            );
+
+            None
+        }
+    });
+
+    let (body, encoding) = match compressor {
+        Some(compressor) => (
+            BodyExt::boxed(StreamBody::new(compress(frames, compressor))),
+            encoding,
+        ),
+        None => (BodyExt::boxed(StreamBody::new(frames)), None),
+    };
+
+    let mut res = Response::new(body);
+    let headers = res.headers_mut();
+    headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+    headers.insert(CACHE_CONTROL, "no-cache".parse().unwrap());
+    headers.insert(VARY, "accept-encoding".parse().unwrap());
+
+    if let Some(encoding) = encoding {
+        headers.insert(CONTENT_ENCODING, encoding.as_str().parse().unwrap());
+    }
+
+    res
+}
+
+/// Compresses the data of each frame and finishes the compressed stream once
+/// `frames` ends.
+fn compress(
+    frames: impl Stream<Item = Result<Frame<Bytes>, Infallible>> + Send + Sync + 'static,
+    compressor: Compressor,
+) -> impl Stream<Item = Result<Frame<Bytes>, Infallible>> {
+    let state = (Box::pin(frames), Some(compressor));
+
+    stream::unfold(state, |(mut frames, mut compressor)| async move {
+        // The compressor is taken once `frames` ended which must not be
+        // polled anymore
+        compressor.as_ref()?;
+
+        let res = match frames.next().await {
+            Some(Ok(frame)) => compressor
+                .as_mut()?
+                .compress(&frame.into_data().unwrap_or_default()),
+            None => compressor.take()?.finish(),
+        };
+
+        match res {
+            Ok(bytes) => Some((Ok(Frame::data(bytes)), (frames, compressor))),
+            Err(err) => {
+                warn!(?err, "Failed to compress stream");
+
+                None
+            }
+        }
+    })
+}
+
+/// Parses the replay from a query such as `replay=100` or `resume_from=123`,
+/// equivalent to the initial message of a websocket. Without any of them, the
+/// whole history is replayed.
+fn replay_from_query(query: &str) -> Replay {
+    let mut replay = Replay::All;
+
+    for pair in query.split('&') {
+        let Some((key, value)) = pair.split_once('=') else {
+            continue;
+        };
+
+        replay = match key {
+            "after" => value.parse().map_or(replay, Replay::After),
+            "replay" => value.parse().map_or(replay, Replay::Last),
+            "resume_from" => value.parse().map_or(replay, Replay::ResumeFrom),
+            _ => replay,
+        };
+    }
+
+    replay
+}
+
+struct Client {
+    ctx: Arc<Context>,
+    peer: Peer,
+    rx: tokio::sync::mpsc::Receiver<Batch>,
+    keep_alive: Interval,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        info!("{} disconnected from HTTP stream", self.peer.addr);
+        self.ctx.unsubscribe(self.peer.id);
+    }
+}
+
+const KEEP_ALIVE_COMMENT: &[u8] = b": keep-alive\n\n";
+
+/// Encodes each received batch through `encode`, interspersed with
+/// `keep_alive` whenever the client has been idle for a while.
+///
+/// Once the client disconnects, the stream is dropped and thereby unsubscribed.
+fn events(
+    client: Client,
+    encode: fn(&Batch) -> Bytes,
+    keep_alive: &'static [u8],
+) -> impl Stream<Item = Result<Frame<Bytes>, Infallible>> {
+    stream::unfold(client, move |mut client| async move {
+        let bytes = tokio::select! {
+            // The stream ends if the client lagged behind
+            batch = client.rx.recv() => encode(&batch?),
+            _ = client.keep_alive.tick() => Bytes::from_static(keep_alive),
+            () = client.ctx.shutting_down() => return None,
+        };
+
+        Some((Ok(Frame::data(bytes)), client))
+    })
+}
+
+/// Encodes each score as an event with the score id as event id.
+fn encode_batch(batch: &Batch) -> Bytes {
+    let mut buf = String::new();
+
+    for score in batch.iter() {
+        let Ok(json) = std::str::from_utf8(score.raw()) else {
+            continue;
+        };
+
+        let _ = writeln!(buf, "id: {}", score.id());
+
+        // Multi-line values must be split into multiple data fields
+        for line in json.lines() {
+            let _ = writeln!(buf, "data: {line}");
+        }
+
+        buf.push('\n');
+    }
+
+    METRICS
+        .scores_forwarded
+        .fetch_add(batch.len() as u64, Relaxed);
+
+    Bytes::from(buf)
+}
+
+/// Encodes each score's JSON on its own line.
+fn encode_lines(batch: &Batch) -> Bytes {
+    let mut buf = Vec::new();
+
+    for score in batch.iter() {
+        let json = score.raw();
+
+        // Pretty-printed JSON would span multiple lines
+        if json.contains(&b'\n') {
+            buf.extend(json.iter().filter(|&&byte| byte != b'\n' && byte != b'\r'));
+        } else {
+            buf.extend_from_slice(json);
+        }
+
+        buf.push(b'\n');
+    }
+
+    METRICS
+        .scores_forwarded
+        .fetch_add(batch.len() as u64, Relaxed);
+
+    Bytes::from(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::client::conn::http1 as client_http1;
+
+    use super::*;
+    use crate::{
+        config::Setup,
+        osu::{Scores, ScoresDeserializer},
+    };
+
+    fn scores(json: &'static str) -> Scores {
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(json.into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        scores
+    }
+
+    #[tokio::test]
+    async fn receive_events() {
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        let mut history = scores(r#"{"scores": [{"id": 1}, {"id": 2}]}"#);
+        ctx.broadcast(&mut history, None);
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = client_http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+
+        let req = Request::get("/sse")
+            .header(LAST_EVENT_ID, "1")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let mut body = sender.send_request(req).await.unwrap().into_body();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut live = scores(r#"{"scores": [{"id": 3}, {"id": 4}]}"#);
+        ctx.broadcast(&mut live, Some(2));
+
+        let mut received = String::new();
+
+        while received.matches("\n\n").count() < 3 {
+            let frame = body.frame().await.unwrap().unwrap();
+            received.push_str(std::str::from_utf8(frame.data_ref().unwrap()).unwrap());
+        }
+
+        assert_eq!(
+            received,
+            "id: 2\ndata: {\"id\": 2}\n\nid: 3\ndata: {\"id\": 3}\n\nid: 4\ndata: {\"id\": 4}\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn receive_lines() {
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        let mut history = scores(r#"{"scores": [{"id": 1}, {"id": 2}, {"id": 3}]}"#);
+        ctx.broadcast(&mut history, None);
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = client_http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+
+        let req = Request::get("/stream.ndjson?replay=2")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let res = sender.send_request(req).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/x-ndjson");
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        let mut body = res.into_body();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut live = scores(
+            r#"{"scores": [{"id": 4, "user": {"id": 2}}, {
+                "id": 5,
+                "pp": null
+            }]}"#,
+        );
+        ctx.broadcast(&mut live, Some(3));
+
+        let mut received = String::new();
+
+        while received.matches('\n').count() < 4 {
+            let frame = body.frame().await.unwrap().unwrap();
+            received.push_str(std::str::from_utf8(frame.data_ref().unwrap()).unwrap());
+        }
+
+        let ids: Vec<_> = received
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].as_u64())
+            .collect();
+
+        assert_eq!(ids, [Some(2), Some(3), Some(4), Some(5)]);
+    }
+
+    #[tokio::test]
+    async fn gzip_lines() {
+        use std::io::Write;
+
+        use flate2::write::GzDecoder;
+
+        let ctx = Arc::new(Context::new(&Setup::default()));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, Arc::clone(&ctx)));
+
+        let mut history = scores(r#"{"scores": [{"id": 1}, {"id": 2}]}"#);
+        ctx.broadcast(&mut history, None);
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut sender, conn) = client_http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+
+        let req = Request::get("/stream.ndjson")
+            .header(ACCEPT_ENCODING, "br;q=1.0, gzip;q=0.8")
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+
+        let res = sender.send_request(req).await.unwrap();
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/x-ndjson");
+        let mut body = res.into_body();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let mut live = scores(r#"{"scores": [{"id": 3}]}"#);
+        ctx.broadcast(&mut live, Some(2));
+
+        let mut decoder = GzDecoder::new(Vec::new());
+
+        while memchr::memchr_iter(b'\n', decoder.get_ref()).count() < 3 {
+            let frame = body.frame().await.unwrap().unwrap();
+            decoder.write_all(frame.data_ref().unwrap()).unwrap();
+            decoder.flush().unwrap();
+        }
+
+        assert_eq!(
+            std::str::from_utf8(decoder.get_ref()).unwrap(),
+            "{\"id\": 1}\n{\"id\": 2}\n{\"id\": 3}\n"
+        );
+
+        // The compressed stream is finished once the server shuts down
+        ctx.shutdown();
+
+        while let Some(frame) = body.frame().await {
+            decoder
+                .write_all(frame.unwrap().data_ref().unwrap())
+                .unwrap();
+        }
+
+        assert_eq!(decoder.finish().unwrap().len(), 30);
+    }
+
+    #[test]
+    fn query_replay() {
+        assert!(matches!(replay_from_query(""), Replay::All));
+        assert!(matches!(replay_from_query("after=5"), Replay::After(5)));
+        assert!(matches!(replay_from_query("replay=10"), Replay::Last(10)));
+        assert!(matches!(
+            replay_from_query("foo&resume_from=7"),
+            Replay::ResumeFrom(7)
+        ));
+        assert!(matches!(replay_from_query("replay=abc"), Replay::All));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use eyre::{Context as _, Result};
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+/// The crypto provider as selected through crate features.
+pub(crate) fn crypto_provider() -> CryptoProvider {
+    #[cfg(feature = "ring")]
+    let crypto_provider = rustls::crypto::ring::default_provider();
+    #[cfg(all(feature = "aws", not(feature = "ring")))]
+    let crypto_provider = rustls::crypto::aws_lc_rs::default_provider();
+    #[cfg(not(any(feature = "ring", feature = "aws")))]
+    let crypto_provider = CryptoProvider::get_default()
+        .expect("No default crypto provider installed or configured via crate features")
+        .as_ref()
+        .clone();
+
+    crypto_provider
+}
+
+/// Creates an acceptor for `wss://` connections.
+///
+/// `cert` must be a PEM file containing the certificate chain, starting with
+/// the server's certificate. `key` must be a PEM file containing the private
+/// key in either PKCS#1, PKCS#8, or SEC1 format.
+pub fn acceptor(cert: &Path, key: &Path) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .context("Failed to read certificate file")?
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse certificates")?;
+
+    let key = PrivateKeyDer::from_pem_file(key).context("Failed to read private key file")?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(crypto_provider()))
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure protocol versions")?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid certificate or private key")?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// An accepted connection that may be encrypted.
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use futures_util::{SinkExt, StreamExt};
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{tungstenite::Message, Connector};
+
+    use super::*;
+    use crate::{
+        config::Setup,
+        context::Context,
+        osu::{Scores, ScoresDeserializer},
+    };
+
+    #[tokio::test]
+    async fn receive_score() {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+
+        let dir = std::env::temp_dir();
+        let cert_path = dir.join(format!("scores-ws-cert-{}.pem", std::process::id()));
+        let key_path = dir.join(format!("scores-ws-key-{}.pem", std::process::id()));
+        fs::write(&cert_path, cert.pem()).unwrap();
+        fs::write(&key_path, key_pair.serialize_pem()).unwrap();
+
+        let acceptor = acceptor(&cert_path, &key_path).unwrap();
+        fs::remove_file(cert_path).unwrap();
+        fs::remove_file(key_path).unwrap();
+
+        let ctx = Arc::new(Context::new(&Setup::default()).with_tls(acceptor));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn({
+            let ctx = Arc::clone(&ctx);
+
+            async move {
+                while let Ok(conn) = listener.accept().await {
+                    tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+                }
+            }
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.der().clone()).unwrap();
+
+        let config = ClientConfig::builder_with_provider(Arc::new(crypto_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let connector = Connector::Rustls(Arc::new(config));
+
+        let (mut client, _) = tokio_tungstenite::connect_async_tls_with_config(
+            format!("wss://localhost:{port}"),
+            None,
+            false,
+            Some(connector),
+        )
+        .await
+        .unwrap();
+
+        client.send(Message::from("connect")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut scores = Scores::new();
+
+        ScoresDeserializer::new(br#"{"scores": [{"id": 1}]}"#[..].into())
+            .deserialize(&mut scores)
+            .unwrap();
+
+        ctx.broadcast(&mut scores, None);
+
+        let msg = client.next().await.unwrap().unwrap();
+        assert_eq!(msg, Message::Binary(br#"{"id": 1}"#[..].into()));
+    }
+}
@@ -0,0 +1,359 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::StatusCode;
+use scores_ws::{
+    config::Setup,
+    context::Context,
+    listen,
+    osu::mock::{MockApi, Reply},
+    poll,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+
+#[tokio::test]
+async fn end_to_end() {
+    let api = MockApi::serve([
+        Reply::Status(StatusCode::INTERNAL_SERVER_ERROR),
+        Reply::Scores(vec![1, 3, 2]),
+        Reply::Scores(vec![4, 5]),
+        Reply::Scores(Vec::new()),
+    ])
+    .await;
+
+    let setup = Setup {
+        interval: 1,
+        ..Setup::default()
+    };
+
+    let ctx = Arc::new(Context::new(&setup));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn({
+        let ctx = Arc::clone(&ctx);
+
+        async move {
+            while let Ok(conn) = listener.accept().await {
+                tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+            }
+        }
+    });
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    client.send(Message::from("connect")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let pollers = poll::spawn(&ctx, &setup, api.config()).unwrap();
+
+    let mut ids = Vec::new();
+
+    while ids.len() < 5 {
+        let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let Message::Binary(bytes) = msg else {
+            panic!("expected binary message, got {msg:?}");
+        };
+
+        let score: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        ids.push(score["id"].as_u64().unwrap());
+    }
+
+    assert_eq!(ids, [1, 2, 3, 4, 5]);
+
+    // The failed request is retried on the next poll and the cursor advances
+    // to the newest score of each response
+    let queries = api.queries();
+    assert_eq!(queries[..2], ["", ""]);
+    assert_eq!(queries[2], "cursor[id]=3");
+
+    ctx.shutdown();
+    pollers.join_all().await;
+}
+
+#[tokio::test]
+async fn recording() {
+    let dir = std::env::temp_dir().join(format!("scores-ws-recording-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("2.json"), r#"{"scores": [{"id": 3}]}"#).unwrap();
+    std::fs::write(dir.join("1.json"), r#"{"scores": [{"id": 2}, {"id": 1}]}"#).unwrap();
+
+    let setup = Setup {
+        interval: 1,
+        recording: Some(dir.clone()),
+        ..Setup::default()
+    };
+
+    let ctx = Arc::new(Context::new(&setup));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn({
+        let ctx = Arc::clone(&ctx);
+
+        async move {
+            while let Ok(conn) = listener.accept().await {
+                tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+            }
+        }
+    });
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    client.send(Message::from("connect")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // No credentials are required
+    let osu = toml::from_str("").unwrap();
+    let pollers = poll::spawn(&ctx, &setup, osu).unwrap();
+    std::fs::remove_dir_all(dir).unwrap();
+
+    let mut received = Vec::new();
+
+    while received.len() < 3 {
+        let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        received.push(msg);
+    }
+
+    assert_eq!(
+        received,
+        [
+            Message::Binary(r#"{"id": 1}"#.into()),
+            Message::Binary(r#"{"id": 2}"#.into()),
+            Message::Binary(r#"{"id": 3}"#.into()),
+        ]
+    );
+
+    ctx.shutdown();
+    pollers.join_all().await;
+}
+
+#[tokio::test]
+async fn relay() {
+    let api = MockApi::serve([Reply::Scores(vec![1, 2]), Reply::Scores(vec![3])]).await;
+
+    let primary_setup = Setup {
+        interval: 1,
+        ..Setup::default()
+    };
+
+    let primary = Arc::new(Context::new(&primary_setup));
+    let primary_addr = serve(&primary).await;
+
+    let relay_setup = Setup {
+        upstream: Some(format!("ws://{primary_addr}").into()),
+        ..Setup::default()
+    };
+
+    let relay = Arc::new(Context::new(&relay_setup));
+    let relay_addr = serve(&relay).await;
+
+    // The relay requires no credentials either
+    let relay_pollers = poll::spawn(&relay, &relay_setup, toml::from_str("").unwrap()).unwrap();
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{relay_addr}"))
+        .await
+        .unwrap();
+
+    client.send(Message::from("connect")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let primary_pollers = poll::spawn(&primary, &primary_setup, api.config()).unwrap();
+
+    let mut ids = Vec::new();
+
+    while ids.len() < 3 {
+        let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let Message::Binary(bytes) = msg else {
+            panic!("expected binary message, got {msg:?}");
+        };
+
+        let score: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        ids.push(score["id"].as_u64().unwrap());
+    }
+
+    assert_eq!(ids, [1, 2, 3]);
+
+    primary.shutdown();
+    relay.shutdown();
+    primary_pollers.join_all().await;
+    relay_pollers.join_all().await;
+}
+
+/// The canonical regression test of the whole pipeline from polling the
+/// osu!api over deserializing and deduplicating to sending scores to a client.
+#[tokio::test]
+async fn pipeline() {
+    let api = MockApi::serve([
+        Reply::Scores(vec![3, 1, 2]),
+        // Overlapping with the previous response and out of order
+        Reply::Scores(vec![2, 3, 5, 4]),
+        Reply::Status(StatusCode::INTERNAL_SERVER_ERROR),
+        Reply::Scores(Vec::new()),
+        // Repeated for all following polls
+        Reply::Scores(vec![5, 6]),
+    ])
+    .await;
+
+    let setup = Setup {
+        interval: 1,
+        ..Setup::default()
+    };
+
+    let ctx = Arc::new(Context::new(&setup));
+    let addr = serve(&ctx).await;
+
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+        .await
+        .unwrap();
+
+    client.send(Message::from("connect")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let pollers = poll::spawn(&ctx, &setup, api.config()).unwrap();
+
+    let mut ids = Vec::new();
+
+    while ids.len() < 6 {
+        let msg = tokio::time::timeout(Duration::from_secs(5), client.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let Message::Binary(bytes) = msg else {
+            panic!("expected binary message, got {msg:?}");
+        };
+
+        let score: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        ids.push(score["id"].as_u64().unwrap());
+    }
+
+    // Another poll of the repeated response sends nothing
+    let res = tokio::time::timeout(Duration::from_millis(1500), client.next()).await;
+    assert!(res.is_err(), "unexpected message {res:?}");
+
+    assert_eq!(ids, [1, 2, 3, 4, 5, 6]);
+
+    // The cursor advances to the newest score and stays put on failed or
+    // empty responses
+    let queries = api.queries();
+    assert!(queries.len() >= 6, "{queries:?}");
+    assert_eq!(
+        queries[..6],
+        [
+            "",
+            "cursor[id]=3",
+            "cursor[id]=5",
+            "cursor[id]=5",
+            "cursor[id]=5",
+            "cursor[id]=6",
+        ]
+    );
+
+    ctx.shutdown();
+    pollers.join_all().await;
+}
+
+#[tokio::test]
+async fn dry_run() {
+    let api = MockApi::serve([Reply::Scores(vec![1, 2]), Reply::Scores(vec![3])]).await;
+
+    let setup = Setup {
+        interval: 1,
+        port: unused_port().await,
+        metrics_port: Some(unused_port().await),
+        sse_port: Some(unused_port().await),
+        dry_run: true,
+        ..Setup::default()
+    };
+
+    let listeners = listen::bind_listeners(&setup).await.unwrap();
+    assert!(listeners.websocket.is_none());
+    assert!(listeners.metrics.is_none());
+    assert!(listeners.sse.is_none());
+
+    // Nothing listens on the configured ports
+    for port in [
+        setup.port,
+        setup.metrics_port.unwrap(),
+        setup.sse_port.unwrap(),
+    ] {
+        assert!(TcpStream::connect(("127.0.0.1", port)).await.is_err());
+    }
+
+    let ctx = Arc::new(Context::new(&setup));
+    let mut rx = ctx.score_stream();
+
+    // Even a client that connected anyway isn't sent any scores
+    let addr = serve(&ctx).await;
+    let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+        .await
+        .unwrap();
+    client.send(Message::from("connect")).await.unwrap();
+
+    let pollers = poll::spawn(&ctx, &setup, api.config()).unwrap();
+
+    // Scores are still parsed and the cursor advances
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while ctx.latest_id() != Some(3) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(api.queries()[..2], ["", "cursor[id]=2"]);
+    assert!(rx.try_recv().is_err());
+
+    let received = tokio::time::timeout(Duration::from_millis(500), client.next()).await;
+    assert!(received.is_err(), "unexpected message {received:?}");
+
+    ctx.shutdown();
+    pollers.join_all().await;
+}
+
+/// A port that nothing listens on.
+async fn unused_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    listener.local_addr().unwrap().port()
+}
+
+/// Accepts websocket connections to `ctx` on a random port.
+async fn serve(ctx: &Arc<Context>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn({
+        let ctx = Arc::clone(ctx);
+
+        async move {
+            while let Ok(conn) = listener.accept().await {
+                tokio::spawn(Context::handle_connection(Arc::clone(&ctx), conn));
+            }
+        }
+    });
+
+    addr
+}